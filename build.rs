@@ -1,3 +1,5 @@
 fn main() {
+    // the host build (`--features simulator`) does not link ESP-IDF
+    #[cfg(feature = "hal")]
     embuild::espidf::sysenv::output();
 }
@@ -0,0 +1,55 @@
+// Replays scenario files against the detection loop on the Linux host and
+// checks their expectations.
+//
+// usage: cargo +stable scenario scenarios/*.json
+use motion_detector::scenario::{runner::run_scenario, timeline::Scenario};
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let mut failed = 0;
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    for path in paths.iter() {
+        let scenario = match std::fs::read_to_string(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Scenario::from_json(&json))
+        {
+            Ok(scenario) => scenario,
+            Err(e) => {
+                println!("[ERROR] {}: {}", path, e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        let report = match run_scenario(&scenario) {
+            Ok(report) => report,
+            Err(e) => {
+                println!("[ERROR] {}: {}", scenario.name, e);
+                failed += 1;
+                continue;
+            }
+        };
+        for entry in report.trace.iter() {
+            println!("  {:>6}s {:?} {}", entry.at, entry.kind, entry.detail);
+        }
+        let failures = report.failures(&scenario.expect);
+        if failures.is_empty() {
+            println!("[PASS] {}", report.name);
+        } else {
+            println!("[FAIL] {}", report.name);
+            for failure in failures.iter() {
+                println!("  {}", failure);
+            }
+            failed += 1;
+        }
+    }
+
+    println!("{} scenario(s), {} failed", paths.len(), failed);
+    if failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,244 @@
+// Linux host simulator: runs the same flow as the firmware (register, fetch
+// configuration, heartbeat, cron-gated detection, alert) with the motion
+// sensor driven from stdin or from a timer.
+//
+// usage: cargo +stable simulator [--timer <seconds>] [--mac <mac address>]
+//                                [--storage <directory>] [--mock-server]
+//                                [--mqtt <broker url>] [--status-port <port>]
+//                                [--set <setting>=<value>]... [--provision]
+//
+// the storage directory (default: motion-detector-simulator in the temporary
+// directory) keeps the offline alert queue across runs, and the settings in
+// its `settings` subdirectory: --set writes one of them (wifi_ssid,
+// wifi_pass, server_url, device_name, device_secret, mqtt_broker,
+// config_required) before the start, an empty value restores the one of
+// config.rs. With --provision the settings are first set from the form of
+// the provisioning access point, served on http://localhost:8083/ until it
+// is submitted. With --mock-server the
+// requests are answered in-process, with the configuration of config.rs,
+// instead of being sent to the server. With --mqtt (or the mqtt_broker
+// setting) they are published to the broker, e.g. mqtt://localhost:1883.
+// Over HTTP the server is looked up with mDNS (SERVER_MDNS_SERVICE in
+// config.rs) before falling back to the urls of config.rs. The simulator
+// advertises itself with mDNS (DEVICE_MDNS_SERVICE in config.rs) and serves
+// its status on http://localhost:8081/status unless --status-port is given.
+//
+// stdin commands: `1` motion, `0` no motion, empty line toggles the sensor,
+// `wifi off` / `wifi on` drops and restores the network link.
+use log::{info, warn};
+use motion_detector::{
+    config::config::{
+        CONFIGURATION_URL, DEFAULT_ALERT_URL, DEFAULT_CRONTAB, DEFAULT_I_AM_ALIVE_INTERVAL_SECONDS,
+        DEFAULT_I_AM_ALIVE_URL, DEVICE_MDNS_SERVICE, HOME_ASSISTANT_DISCOVERY_PREFIX,
+        MQTT_TOPIC_PREFIX, SERVER_MDNS_SERVICE,
+    },
+    discovery::{host_discovery::HostMdnsDiscovery, traits::DeviceAdvertiser},
+    dto::device_status::DeviceStatus,
+    peripheral::{
+        host_peripheral::{HostFileStorage, HostMotionSensor, HostNetworkLink, HostOutput},
+        traits::NetworkLink,
+    },
+    provisioning::{
+        host_provisioning_portal::HostProvisioningPortal, provisioning_portal::ProvisioningForm,
+    },
+    service::{
+        discovery_service::{device_advertisement, DiscoveryService},
+        orchestrator_service::orchestrate,
+        peripheral_service::PeripheralService,
+        settings_service::{SettingsService, DEVICE_NAME, MQTT_BROKER_URL},
+    },
+    status::host_status_server::HostStatusServer,
+    transport::{
+        host_transport::{mqtt_transport, HostHttpTransport, MockTransport},
+        mqtt_transport::MqttTopics,
+        traits::Transport,
+    },
+    util::{
+        clock_util::{Clock, SystemClock},
+        thread_util,
+        url_util::origin,
+    },
+};
+use std::{
+    io::BufRead,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+const DEFAULT_MAC_ADDRESS: &str = "02:00:00:00:00:01";
+const DEFAULT_STORAGE_DIRECTORY: &str = "motion-detector-simulator";
+const SETTINGS_DIRECTORY: &str = "settings";
+const PROVISIONING_PORT: u16 = 8083;
+// the firmware serves the status on STATUS_PORT (80), not available to users
+const DEFAULT_STATUS_PORT: u16 = 8081;
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let args: Vec<String> = std::env::args().collect();
+    let timer_seconds = get_argument(&args, "--timer").map(|value| value.parse::<u64>().unwrap());
+    let mac_address = get_argument(&args, "--mac").unwrap_or(DEFAULT_MAC_ADDRESS.to_owned());
+    let is_server_mocked = args.iter().any(|arg| arg == "--mock-server");
+    let storage_directory = get_argument(&args, "--storage")
+        .map(PathBuf::from)
+        .unwrap_or(std::env::temp_dir().join(DEFAULT_STORAGE_DIRECTORY));
+    let status_port = get_argument(&args, "--status-port")
+        .map(|value| value.parse::<u16>().unwrap())
+        .unwrap_or(DEFAULT_STATUS_PORT);
+
+    let mut settings = SettingsService::new(Box::new(
+        HostFileStorage::new(storage_directory.join(SETTINGS_DIRECTORY)).unwrap(),
+    ));
+    for (index, _) in args.iter().enumerate().filter(|(_, arg)| *arg == "--set") {
+        let setting = args.get(index + 1).expect("--set <setting>=<value>");
+        let (key, value) = setting.split_once('=').expect("--set <setting>=<value>");
+        match settings.set_from_str(key, value) {
+            Ok(()) => info!("[settings] {} set", key),
+            Err(e) => panic!("invalid setting {}: {}", key, e),
+        }
+    }
+    if args.iter().any(|arg| arg == "--provision") {
+        let portal = HostProvisioningPortal::start(
+            PROVISIONING_PORT,
+            ProvisioningForm::from_settings(&settings),
+        )
+        .unwrap();
+        if let Some(form) = portal.wait(None) {
+            form.save(&mut settings).unwrap();
+            info!("[provisioning] settings saved");
+        }
+    }
+    let broker_url = get_argument(&args, "--mqtt").or(settings.get(&MQTT_BROKER_URL));
+    let device_name = settings.get(&DEVICE_NAME);
+
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock::new());
+    let motion = Arc::new(AtomicBool::new(false));
+    let reachable = Arc::new(AtomicBool::new(true));
+
+    let mut network = HostNetworkLink::new(&mac_address, reachable.clone());
+    network.connect().unwrap();
+    let peripheral_service = PeripheralService::from_parts(
+        Box::new(HostMotionSensor::new(motion.clone())),
+        Box::new(HostOutput::new("led", Arc::new(Mutex::new(Vec::new())))),
+        Box::new(HostOutput::new("buzzer", Arc::new(Mutex::new(Vec::new())))),
+        Box::new(network),
+        Box::new(HostFileStorage::new(storage_directory).unwrap()),
+        settings,
+        clock.clone(),
+    );
+
+    match timer_seconds {
+        Some(seconds) => {
+            let timer_clock = clock.clone();
+            thread::spawn(move || drive_sensor_from_timer(timer_clock, motion, seconds));
+        }
+        None => {
+            thread::spawn(move || drive_sensor_from_stdin(motion, reachable));
+        }
+    }
+
+    let is_http = !is_server_mocked && broker_url.is_none();
+    let transport: Box<dyn Transport> = match (is_server_mocked, broker_url) {
+        (true, _) => Box::new(MockTransport::new(mock_server_response)),
+        (false, Some(broker_url)) => {
+            let topics = MqttTopics::new(
+                MQTT_TOPIC_PREFIX,
+                HOME_ASSISTANT_DISCOVERY_PREFIX,
+                &device_name,
+                &mac_address,
+            );
+            Box::new(mqtt_transport(&broker_url, topics).unwrap())
+        }
+        (false, None) => Box::new(HostHttpTransport::new()),
+    };
+    let discovery_service = SERVER_MDNS_SERVICE.filter(|_| is_http).map(|service_type| {
+        DiscoveryService::new(Box::new(HostMdnsDiscovery::new().unwrap()), service_type)
+    });
+
+    let status_board = Arc::new(Mutex::new(DeviceStatus::new(&device_name, &mac_address)));
+    // kept until the end of main, which stops the advertisement
+    let _advertiser = DEVICE_MDNS_SERVICE.map(|service_type| {
+        let status_server = HostStatusServer::start(status_port, status_board.clone()).unwrap();
+        let advertisement = device_advertisement(
+            service_type,
+            &device_name,
+            &mac_address,
+            status_server.port(),
+        );
+        let mut advertiser = HostMdnsDiscovery::new().unwrap();
+        match advertiser.advertise(&advertisement) {
+            Ok(()) => info!(
+                "[discovery] advertised as {}.local ({})",
+                advertisement.hostname, service_type
+            ),
+            Err(e) => warn!("[discovery] could not advertise the device: {}", e),
+        }
+        advertiser
+    });
+    orchestrate(
+        peripheral_service,
+        transport,
+        clock,
+        discovery_service,
+        status_board,
+    );
+}
+
+// every request succeeds, the configuration is the one of config.rs, at the
+// path of CONFIGURATION_URL on any server
+fn mock_server_response(url: &str, payload: &[u8]) -> anyhow::Result<(u16, String)> {
+    info!("[mock server] {} {}", url, String::from_utf8_lossy(payload));
+    if url.ends_with(&CONFIGURATION_URL[origin(CONFIGURATION_URL).len()..]) {
+        let configuration = serde_json::json!({
+            "alertEndpoint": DEFAULT_ALERT_URL,
+            "iAmAliveEndpoint": DEFAULT_I_AM_ALIVE_URL,
+            "iAmAliveIntervalSeconds": DEFAULT_I_AM_ALIVE_INTERVAL_SECONDS,
+            "crontab": DEFAULT_CRONTAB,
+            "timezoneOffsetSec": 0,
+        });
+        return Ok((200, configuration.to_string()));
+    }
+    Ok((200, "{\"success\":true}".to_owned()))
+}
+
+fn get_argument(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|index| args.get(index + 1))
+        .cloned()
+}
+
+fn drive_sensor_from_timer(clock: Arc<dyn Clock>, motion: Arc<AtomicBool>, seconds: u64) {
+    loop {
+        thread_util::sleep_time(clock.as_ref(), seconds * 1000);
+        let value = !motion.load(Ordering::SeqCst);
+        info!("[sensor] {}", value);
+        motion.store(value, Ordering::SeqCst);
+    }
+}
+
+fn drive_sensor_from_stdin(motion: Arc<AtomicBool>, reachable: Arc<AtomicBool>) {
+    for line in std::io::stdin().lock().lines() {
+        let line = line.unwrap();
+        match line.trim() {
+            "1" => motion.store(true, Ordering::SeqCst),
+            "0" => motion.store(false, Ordering::SeqCst),
+            "" => motion.store(!motion.load(Ordering::SeqCst), Ordering::SeqCst),
+            "wifi off" => reachable.store(false, Ordering::SeqCst),
+            "wifi on" => reachable.store(true, Ordering::SeqCst),
+            command => {
+                warn!("unknown command: {}", command);
+                continue;
+            }
+        }
+        info!(
+            "[sensor] {} [wifi] {}",
+            motion.load(Ordering::SeqCst),
+            reachable.load(Ordering::SeqCst)
+        );
+    }
+}
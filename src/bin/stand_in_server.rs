@@ -0,0 +1,32 @@
+// Stand-in for the Elisys server, to run the simulator or the firmware
+// against a laptop instead of the Java server.
+//
+// usage: cargo +stable stand-in [--address <ip:port>]
+//
+// control endpoints:
+//   POST /__control/behaviour     {"endpoint": "alert", "behaviour": {"type": "status", "code": 500}}
+//   POST /__control/configuration <Configuration JSON returned to the device>
+//   GET  /__control/requests      every request received so far
+//   POST /__control/tokens        {"required": true}, revokes the issued token
+//   POST /__control/signatures    {"secret": "..."}, alerts and IAmAlive must be signed
+//   POST /__control/reset
+use motion_detector::stand_in::server::StandInServer;
+
+const DEFAULT_ADDRESS: &str = "0.0.0.0:8080";
+
+fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let args: Vec<String> = std::env::args().collect();
+    let address = args
+        .iter()
+        .position(|arg| arg == "--address")
+        .and_then(|index| args.get(index + 1))
+        .cloned()
+        .unwrap_or(DEFAULT_ADDRESS.to_owned());
+
+    let _server = StandInServer::start(&address)?;
+    loop {
+        std::thread::park();
+    }
+}
@@ -1,10 +1,18 @@
 // rename the file in config.rs
 // customize your settings by editing this variables
 // ------------------------------------------------------------------
+// WIFI_SSID, WIFI_PASS, DEVICE_NAME, DEVICE_SECRET, MQTT_BROKER_URL and
+// IS_REMOTE_CONFIGURATION_MANDATORY are only the defaults of the settings
+// stored on the device (src/service/settings_service.rs), as is the origin
+// of the server urls
 // wifi name
 pub const WIFI_SSID: &str = "wifi name";
 // wifi password
 pub const WIFI_PASS: &str = "wifi password";
+// password of the access point opened to set the WiFi network when the
+// device has no credentials (WIFI_SSID empty and none stored) or cannot
+// connect with them; at least 8 characters, None for an open access point
+pub const PROVISIONING_AP_PASSWORD: Option<&str> = None;
 // endpoint that is used to send an alert after a movement detection
 pub const DEFAULT_ALERT_URL: &str = "http://server_url:8080/alert";
 // endpoint on which the server is informed that the device is alive
@@ -26,3 +34,30 @@ pub const DEVICE_NAME: &str = "Motion Detector";
 pub const DEVICE_DESCRIPTION: &str = "Motion Detector Device";
 // Device type
 pub const DEVICE_TYPE: &str = "MotionDetector";
+// DNS-SD service type of the Elisys server, looked up with mDNS at boot and
+// after repeated connection failures; the urls above are the fallback (and
+// give the paths of the endpoints). None disables the lookup
+pub const SERVER_MDNS_SERVICE: Option<&str> = Some("_elisys._tcp");
+// DNS-SD service type under which the device advertises itself, with a
+// hostname made of DEVICE_NAME and the end of the MAC address; its TXT record
+// gives the path of the status endpoint served on STATUS_PORT. None disables
+// the advertisement and the endpoint
+pub const DEVICE_MDNS_SERVICE: Option<&str> = Some("_elisys-detector._tcp");
+pub const STATUS_PORT: u16 = 80;
+// secret shared with the server, used to sign the alerts and the heartbeats
+// with HMAC-SHA256; when None the requests are not signed
+pub const DEVICE_SECRET: Option<&str> = None;
+// PEM of the CA that signed the server certificate, for `https://` endpoints;
+// when None the ESP-IDF certificate bundle is used
+pub const SERVER_CA_PEM: Option<&str> = None;
+// base64 SHA-256 hashes of the accepted server public keys (SubjectPublicKeyInfo);
+// when empty the public key is not pinned
+pub const SERVER_SPKI_SHA256: &[&str] = &[];
+// MQTT broker (e.g. "mqtt://192.168.1.102:1883"); when set, registration,
+// configuration, alerts and heartbeats go through MQTT instead of HTTP
+pub const MQTT_BROKER_URL: Option<&str> = None;
+// topics are <prefix>/<mac address>/<register|alert|heartbeat|status|configuration>
+pub const MQTT_TOPIC_PREFIX: &str = "elisys/motion-detector";
+// Home Assistant discovery prefix; when set, the device announces its motion,
+// connectivity, RSSI and uptime entities to Home Assistant in MQTT mode
+pub const HOME_ASSISTANT_DISCOVERY_PREFIX: Option<&str> = Some("homeassistant");
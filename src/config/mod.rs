@@ -1 +1,3 @@
+// config.rs is the local copy of config.sample.rs
+#[allow(clippy::module_inception)]
 pub mod config;
@@ -0,0 +1,90 @@
+use super::traits::{
+    split_service_type, DeviceAdvertisement, DeviceAdvertiser, DiscoveredServer, ServiceDiscovery,
+    SCHEME_TXT_KEY,
+};
+use esp_idf_svc::mdns::{EspMdns, Interface, Protocol, QueryResult};
+use std::time::Duration;
+
+// instances read from one query
+const MAX_RESULTS: usize = 4;
+
+// ESP-IDF mdns component (espressif/mdns, see Cargo.toml)
+pub struct EspMdnsDiscovery {
+    mdns: EspMdns,
+}
+
+impl EspMdnsDiscovery {
+    pub fn new() -> anyhow::Result<EspMdnsDiscovery> {
+        Ok(EspMdnsDiscovery {
+            mdns: EspMdns::take()?,
+        })
+    }
+}
+
+impl ServiceDiscovery for EspMdnsDiscovery {
+    fn discover(
+        &mut self,
+        service_type: &str,
+        timeout: Duration,
+    ) -> anyhow::Result<Option<DiscoveredServer>> {
+        let (service, protocol) = split_service_type(service_type)?;
+        let mut results: Vec<QueryResult> = (0..MAX_RESULTS).map(|_| empty_result()).collect();
+        let count = self
+            .mdns
+            .query_ptr(service, protocol, timeout, MAX_RESULTS, &mut results)?;
+        for result in results.iter().take(count) {
+            // IPv6 addresses are skipped
+            let host = result
+                .addr
+                .iter()
+                .map(|address| address.to_string())
+                .find(|address| !address.contains(':'));
+            if let Some(host) = host {
+                let is_https = result
+                    .txt
+                    .iter()
+                    .any(|(key, value)| key == SCHEME_TXT_KEY && value == "https");
+                return Ok(Some(DiscoveredServer {
+                    host,
+                    port: result.port,
+                    is_https,
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+// buffer filled by `query_ptr`, which overwrites every field
+fn empty_result() -> QueryResult {
+    QueryResult {
+        instance_name: None,
+        hostname: None,
+        port: 0,
+        txt: Vec::new(),
+        addr: Vec::new(),
+        interface: Interface::STA,
+        ip_protocol: Protocol::V4,
+    }
+}
+
+impl DeviceAdvertiser for EspMdnsDiscovery {
+    fn advertise(&mut self, advertisement: &DeviceAdvertisement) -> anyhow::Result<()> {
+        let (service, protocol) = split_service_type(&advertisement.service_type)?;
+        let txt: Vec<(&str, &str)> = advertisement
+            .txt
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        self.mdns.set_hostname(&advertisement.hostname)?;
+        self.mdns.set_instance_name(&advertisement.instance_name)?;
+        self.mdns.add_service(
+            Some(&advertisement.instance_name),
+            service,
+            protocol,
+            advertisement.port,
+            &txt,
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use super::traits::{
+    split_service_type, DeviceAdvertisement, DeviceAdvertiser, DiscoveredServer, ServiceDiscovery,
+    SCHEME_TXT_KEY,
+};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// mDNS querier of the mdns-sd crate, running on its own thread
+pub struct HostMdnsDiscovery {
+    daemon: ServiceDaemon,
+}
+
+impl HostMdnsDiscovery {
+    pub fn new() -> anyhow::Result<HostMdnsDiscovery> {
+        Ok(HostMdnsDiscovery {
+            daemon: ServiceDaemon::new()?,
+        })
+    }
+}
+
+impl ServiceDiscovery for HostMdnsDiscovery {
+    fn discover(
+        &mut self,
+        service_type: &str,
+        timeout: Duration,
+    ) -> anyhow::Result<Option<DiscoveredServer>> {
+        let (service, protocol) = split_service_type(service_type)?;
+        let service_type = format!("{}.{}.local.", service, protocol);
+        let receiver = self.daemon.browse(&service_type)?;
+        let deadline = Instant::now() + timeout;
+        let mut server = None;
+        while let Ok(event) = receiver.recv_deadline(deadline) {
+            if let ServiceEvent::ServiceResolved(info) = event {
+                if let Some(address) = info.get_addresses_v4().into_iter().next() {
+                    server = Some(DiscoveredServer {
+                        host: address.to_string(),
+                        port: info.get_port(),
+                        is_https: info.get_property_val_str(SCHEME_TXT_KEY) == Some("https"),
+                    });
+                    break;
+                }
+            }
+        }
+        self.daemon.stop_browse(&service_type)?;
+        Ok(server)
+    }
+}
+
+impl DeviceAdvertiser for HostMdnsDiscovery {
+    fn advertise(&mut self, advertisement: &DeviceAdvertisement) -> anyhow::Result<()> {
+        let (service, protocol) = split_service_type(&advertisement.service_type)?;
+        let properties: HashMap<String, String> = advertisement.txt.iter().cloned().collect();
+        // the addresses of the interfaces are filled in by the daemon
+        let info = ServiceInfo::new(
+            &format!("{}.{}.local.", service, protocol),
+            &advertisement.instance_name,
+            &format!("{}.local.", advertisement.hostname),
+            "",
+            advertisement.port,
+            properties,
+        )?
+        .enable_addr_auto();
+        self.daemon.register(info)?;
+        Ok(())
+    }
+}
+
+// answers with the server set from outside, for the scenario runner
+pub struct StaticDiscovery {
+    server: Arc<Mutex<Option<DiscoveredServer>>>,
+}
+
+impl StaticDiscovery {
+    pub fn new(server: Arc<Mutex<Option<DiscoveredServer>>>) -> StaticDiscovery {
+        StaticDiscovery { server }
+    }
+}
+
+impl ServiceDiscovery for StaticDiscovery {
+    fn discover(
+        &mut self,
+        _service_type: &str,
+        _timeout: Duration,
+    ) -> anyhow::Result<Option<DiscoveredServer>> {
+        Ok(self.server.lock().unwrap().clone())
+    }
+}
@@ -0,0 +1,5 @@
+#[cfg(feature = "hal")]
+pub mod esp_discovery;
+#[cfg(feature = "simulator")]
+pub mod host_discovery;
+pub mod traits;
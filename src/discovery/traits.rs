@@ -0,0 +1,115 @@
+// Lookup of the Elisys server on the local network with mDNS/DNS-SD, and
+// advertisement of the device itself: the ESP-IDF mdns component implements
+// them on the chip, the mdns-sd crate on Linux.
+use std::time::Duration;
+
+// TXT key of the scheme, `https` when the server only accepts TLS
+pub const SCHEME_TXT_KEY: &str = "scheme";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredServer {
+    // IPv4 address of the server
+    pub host: String,
+    pub port: u16,
+    pub is_https: bool,
+}
+
+impl DiscoveredServer {
+    pub fn origin(&self) -> String {
+        let scheme = if self.is_https { "https" } else { "http" };
+        format!("{}://{}:{}", scheme, self.host, self.port)
+    }
+}
+
+pub trait ServiceDiscovery {
+    // first instance of `service_type` (e.g. `_elisys._tcp`) answering
+    // within `timeout`, if any
+    fn discover(
+        &mut self,
+        service_type: &str,
+        timeout: Duration,
+    ) -> anyhow::Result<Option<DiscoveredServer>>;
+}
+
+// service record of the device, e.g. `motion-detector-a1b2c3.local` offering
+// `_elisys-detector._tcp` on port 80
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceAdvertisement {
+    // without the `.local` suffix
+    pub hostname: String,
+    pub instance_name: String,
+    pub service_type: String,
+    pub port: u16,
+    pub txt: Vec<(String, String)>,
+}
+
+pub trait DeviceAdvertiser {
+    // answers the queries for the hostname and the service until dropped
+    fn advertise(&mut self, advertisement: &DeviceAdvertisement) -> anyhow::Result<()>;
+}
+
+// `_elisys._tcp` as (`_elisys`, `_tcp`)
+pub fn split_service_type(service_type: &str) -> anyhow::Result<(&str, &str)> {
+    let service_type = service_type
+        .trim_end_matches('.')
+        .trim_end_matches(".local");
+    match service_type.rsplit_once('.') {
+        Some((service, protocol)) if protocol == "_tcp" || protocol == "_udp" => {
+            Ok((service, protocol))
+        }
+        _ => Err(anyhow::Error::msg(format!(
+            "invalid service type: {}",
+            service_type
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_type_is_split_at_the_protocol() {
+        assert_eq!(
+            split_service_type("_elisys._tcp").unwrap(),
+            ("_elisys", "_tcp")
+        );
+        assert_eq!(
+            split_service_type("_elisys-detector._udp").unwrap(),
+            ("_elisys-detector", "_udp")
+        );
+    }
+
+    #[test]
+    fn local_domain_is_ignored() {
+        assert_eq!(
+            split_service_type("_elisys._tcp.local.").unwrap(),
+            ("_elisys", "_tcp")
+        );
+        assert_eq!(
+            split_service_type("_elisys._tcp.local").unwrap(),
+            ("_elisys", "_tcp")
+        );
+        assert_eq!(
+            split_service_type("_elisys._tcp.").unwrap(),
+            ("_elisys", "_tcp")
+        );
+    }
+
+    #[test]
+    fn service_type_without_protocol_is_refused() {
+        assert!(split_service_type("_elisys").is_err());
+        assert!(split_service_type("_elisys._sctp").is_err());
+        assert!(split_service_type("_elisys.local.").is_err());
+    }
+
+    #[test]
+    fn origin_of_the_discovered_server() {
+        let server = DiscoveredServer {
+            host: "192.168.1.40".to_owned(),
+            port: 8443,
+            is_https: true,
+        };
+        assert_eq!(server.origin(), "https://192.168.1.40:8443");
+    }
+}
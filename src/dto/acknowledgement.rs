@@ -0,0 +1,7 @@
+// Common part of the server responses: `{"success": true}`, or
+// `{"success": false, "message": "..."}` when the server could not handle
+// the request.
+pub trait Acknowledgement {
+    fn is_success(&self) -> bool;
+    fn message(&self) -> Option<&str>;
+}
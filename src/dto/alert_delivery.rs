@@ -0,0 +1,27 @@
+use serde::Serialize;
+
+// what became of an alert on each of its targets, reported in the next
+// heartbeat
+#[derive(Serialize, Debug, Clone)]
+pub struct AlertDelivery {
+    #[serde(rename = "detectedAt")]
+    pub detected_at: String,
+    pub targets: Vec<TargetDelivery>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TargetDelivery {
+    pub name: String,
+    pub result: DeliveryResult,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum DeliveryResult {
+    Delivered,
+    Failed,
+    // a failover target not needed, the alert was delivered before it
+    Skipped,
+}
@@ -1,9 +1,12 @@
+use crate::util::retry_util::{RetryPolicy, ALERT_RETRY_POLICY, I_AM_ALIVE_RETRY_POLICY};
 use serde::Deserialize;
+use std::fmt::{self, Debug, Formatter};
 
 #[derive(Deserialize, Debug)]
 pub struct Configuration {
-    #[serde(rename = "alertEndpoint")]
-    pub alert_endpoint: String,
+    // alerts are sent to the server only when present
+    #[serde(rename = "alertEndpoint", default)]
+    pub alert_endpoint: Option<String>,
     #[serde(rename = "iAmAliveEndpoint")]
     pub i_am_alive_endpoint: String,
     #[serde(rename = "iAmAliveIntervalSeconds")]
@@ -11,4 +14,253 @@ pub struct Configuration {
     pub crontab: String,
     #[serde(rename = "timezoneOffsetSec")]
     pub timezone_offset: i32,
+    // IANA name (e.g. Europe/Rome), preferred over the offset when known
+    #[serde(default)]
+    pub timezone: Option<String>,
+    // when present, replaces the crontab as the arming schedule
+    #[serde(rename = "armingWindow", default)]
+    pub arming_window: Option<ArmingWindow>,
+    // when not empty, replaces both the crontab and the arming window
+    #[serde(default)]
+    pub schedules: Vec<NamedSchedule>,
+    #[serde(rename = "retryPolicies", default)]
+    pub retry_policies: RetryPolicies,
+    #[serde(rename = "alertQueue", default)]
+    pub alert_queue: AlertQueueConfiguration,
+    // TLS settings of the `https://` endpoints, by default those of the
+    // server in config.rs
+    #[serde(rename = "alertTls", default)]
+    pub alert_tls: Option<TlsConfiguration>,
+    #[serde(rename = "iAmAliveTls", default)]
+    pub i_am_alive_tls: Option<TlsConfiguration>,
+    // notification services alerted directly, alongside the server
+    #[serde(default)]
+    pub notifiers: Vec<NotifierConfiguration>,
+    // when not empty, replaces `alertEndpoint` and `notifiers`
+    #[serde(rename = "alertTargets", default)]
+    pub alert_targets: Vec<AlertTargetConfiguration>,
+    // text of the notifications, with the {deviceName}, {macAddress} and
+    // {detectedAt} placeholders
+    #[serde(rename = "messageTemplate", default = "default_message_template")]
+    pub message_template: String,
+}
+
+pub fn default_message_template() -> String {
+    "Motion detected by {deviceName} ({macAddress}) at {detectedAt}".to_owned()
+}
+
+// server or notification service receiving the alerts; the targets are
+// used from the lowest `priority` to the highest
+#[derive(Deserialize, Debug, Clone)]
+pub struct AlertTargetConfiguration {
+    // shown in the logs and the heartbeat, by default the type of the target
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub priority: u32,
+    #[serde(default)]
+    pub mode: DeliveryMode,
+    #[serde(flatten)]
+    pub service: AlertTargetService,
+    // by default the settings of the server for a server target, the
+    // certificate bundle for a notification service
+    #[serde(default)]
+    pub tls: Option<TlsConfiguration>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum AlertTargetService {
+    // alert endpoint of an Elisys server
+    Server {
+        url: String,
+    },
+    #[serde(untagged)]
+    Notifier(NotifierService),
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum DeliveryMode {
+    // the target receives every alert
+    #[default]
+    FanOut,
+    // the target receives the alert only when the failover targets before
+    // it failed to deliver it
+    Failover,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NotifierConfiguration {
+    #[serde(flatten)]
+    pub service: NotifierService,
+    // by default the certificate bundle, without pinning
+    #[serde(default)]
+    pub tls: Option<TlsConfiguration>,
+}
+
+// Debug shows the service only: the urls and the tokens are secrets
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum NotifierService {
+    // https://discord.com/api/webhooks/<id>/<token>
+    Discord {
+        #[serde(rename = "webhookUrl")]
+        webhook_url: String,
+        #[serde(default)]
+        username: Option<String>,
+    },
+    // the server (https://ntfy.sh by default) and the topic
+    Ntfy {
+        #[serde(default = "default_ntfy_url")]
+        url: String,
+        topic: String,
+        #[serde(default)]
+        token: Option<String>,
+        #[serde(default)]
+        priority: Option<u8>,
+    },
+    // the server and the token of an application
+    Gotify {
+        url: String,
+        token: String,
+        #[serde(default)]
+        priority: Option<u8>,
+    },
+    Telegram {
+        #[serde(rename = "botToken")]
+        bot_token: String,
+        #[serde(rename = "chatId")]
+        chat_id: String,
+        #[serde(rename = "apiUrl", default = "default_telegram_api_url")]
+        api_url: String,
+    },
+}
+
+impl NotifierService {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NotifierService::Discord { .. } => "discord",
+            NotifierService::Ntfy { .. } => "ntfy",
+            NotifierService::Gotify { .. } => "gotify",
+            NotifierService::Telegram { .. } => "telegram",
+        }
+    }
+}
+
+impl Debug for NotifierService {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+fn default_ntfy_url() -> String {
+    "https://ntfy.sh".to_owned()
+}
+
+fn default_telegram_api_url() -> String {
+    "https://api.telegram.org".to_owned()
+}
+
+// CA trusted in place of the certificate bundle and base64 SHA-256 hashes of
+// the accepted server public keys
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct TlsConfiguration {
+    #[serde(rename = "caPem", default)]
+    pub ca_pem: Option<String>,
+    #[serde(rename = "spkiSha256", default)]
+    pub spki_sha256: Vec<String>,
+}
+
+// alerts that could not be delivered are kept, up to `capacity`, and
+// replayed in order once the server is reachable again
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct AlertQueueConfiguration {
+    #[serde(default = "default_alert_queue_capacity")]
+    pub capacity: usize,
+    #[serde(default)]
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for AlertQueueConfiguration {
+    fn default() -> Self {
+        AlertQueueConfiguration {
+            capacity: default_alert_queue_capacity(),
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+fn default_alert_queue_capacity() -> usize {
+    32
+}
+
+// which alert is dropped when the queue is full
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum OverflowPolicy {
+    #[default]
+    DropOldest,
+    DropNewest,
+}
+
+// retries of the requests sent from the detection loop; registration and
+// configuration download use compile-time policies
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct RetryPolicies {
+    #[serde(default = "default_alert_retry_policy")]
+    pub alert: RetryPolicy,
+    #[serde(rename = "iAmAlive", default = "default_i_am_alive_retry_policy")]
+    pub i_am_alive: RetryPolicy,
+}
+
+impl Default for RetryPolicies {
+    fn default() -> Self {
+        RetryPolicies {
+            alert: ALERT_RETRY_POLICY,
+            i_am_alive: I_AM_ALIVE_RETRY_POLICY,
+        }
+    }
+}
+
+fn default_alert_retry_policy() -> RetryPolicy {
+    ALERT_RETRY_POLICY
+}
+
+fn default_i_am_alive_retry_policy() -> RetryPolicy {
+    I_AM_ALIVE_RETRY_POLICY
+}
+
+// a crontab or an arming window, applying its action while active
+#[derive(Deserialize, Debug, Clone)]
+pub struct NamedSchedule {
+    pub name: String,
+    pub action: ScheduleAction,
+    pub crontab: Option<String>,
+    pub window: Option<ArmingWindow>,
+}
+
+// declared from the lowest to the highest precedence: when several schedules
+// are active, the greatest action wins
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum ScheduleAction {
+    // motion is alerted, with the buzzer
+    Arm,
+    // motion is alerted, without the buzzer
+    SilentArm,
+    // motion is ignored, heartbeats are still sent
+    HeartbeatOnly,
+    // motion is ignored and no heartbeat is sent
+    Disarm,
+}
+
+// armed from each `start` tick until the next `end` tick, or for
+// `durationSeconds` after each `start` tick
+#[derive(Deserialize, Debug, Clone)]
+pub struct ArmingWindow {
+    pub start: String,
+    pub end: Option<String>,
+    #[serde(rename = "durationSeconds")]
+    pub duration_seconds: Option<u64>,
 }
@@ -0,0 +1,44 @@
+use crate::config::config::DEVICE_TYPE;
+use serde::Serialize;
+
+// answer of the status endpoint of the device, updated by the detection loop
+#[derive(Serialize, Debug, Clone)]
+pub struct DeviceStatus {
+    #[serde(rename = "deviceName")]
+    pub device_name: String,
+    #[serde(rename = "deviceType")]
+    pub device_type: String,
+    #[serde(rename = "firmwareVersion")]
+    pub firmware_version: String,
+    #[serde(rename = "macAddress")]
+    pub mac_address: String,
+    // state of the detector, e.g. ArmedIdle
+    pub state: String,
+    #[serde(rename = "activeSchedule")]
+    pub active_schedule: Option<String>,
+    #[serde(rename = "queuedAlerts")]
+    pub queued_alerts: usize,
+    #[serde(rename = "droppedAlerts")]
+    pub dropped_alerts: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rssi: Option<i8>,
+    #[serde(rename = "uptimeSeconds")]
+    pub uptime_seconds: u64,
+}
+
+impl DeviceStatus {
+    pub fn new(device_name: &str, mac_address: &str) -> DeviceStatus {
+        DeviceStatus {
+            device_name: device_name.to_owned(),
+            device_type: DEVICE_TYPE.to_owned(),
+            firmware_version: env!("CARGO_PKG_VERSION").to_owned(),
+            mac_address: mac_address.to_owned(),
+            state: "Booting".to_owned(),
+            active_schedule: None,
+            queued_alerts: 0,
+            dropped_alerts: 0,
+            rssi: None,
+            uptime_seconds: 0,
+        }
+    }
+}
@@ -1,5 +1,10 @@
+pub mod acknowledgement;
+pub mod alert_delivery;
 pub mod config_request;
 pub mod config_response;
+pub mod device_status;
 pub mod register_device;
 pub mod request_alert;
 pub mod request_i_am_alive;
+pub mod response_acknowledgement;
+pub mod response_register_device;
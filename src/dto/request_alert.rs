@@ -5,10 +5,17 @@ use serde::Serialize;
 pub struct RequestAlert {
     #[serde(rename = "macAddress")]
     mac_address: String,
+    // RFC 3339 instant of the detection, earlier than the request when the
+    // alert was queued while offline
+    #[serde(rename = "detectedAt")]
+    detected_at: String,
 }
 
 impl RequestAlert {
-    pub fn new(mac_address: String) -> RequestAlert {
-        RequestAlert { mac_address }
+    pub fn new(mac_address: String, detected_at: String) -> RequestAlert {
+        RequestAlert {
+            mac_address,
+            detected_at,
+        }
     }
 }
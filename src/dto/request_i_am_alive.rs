@@ -1,3 +1,4 @@
+use super::alert_delivery::AlertDelivery;
 use serde::Serialize;
 
 #[derive(Serialize)]
@@ -5,10 +6,46 @@ use serde::Serialize;
 pub struct RequestIAmAlive {
     #[serde(rename = "macAddress")]
     mac_address: String,
+    // alerts waiting in the offline queue
+    #[serde(rename = "queuedAlerts")]
+    queued_alerts: usize,
+    // alerts lost to a full offline queue since the queue was created
+    #[serde(rename = "droppedAlerts")]
+    dropped_alerts: u64,
+    // signal strength of the access point in dBm, when known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rssi: Option<i8>,
+    // seconds since the boot
+    #[serde(rename = "uptimeSeconds")]
+    uptime_seconds: u64,
+    // alerts sent since the previous heartbeat, with their result per target
+    #[serde(rename = "alertDeliveries", skip_serializing_if = "Vec::is_empty")]
+    alert_deliveries: Vec<AlertDelivery>,
 }
 
 impl RequestIAmAlive {
-    pub fn new(mac_address: String) -> RequestIAmAlive {
-        RequestIAmAlive { mac_address }
+    pub fn new(
+        mac_address: String,
+        queued_alerts: usize,
+        dropped_alerts: u64,
+        rssi: Option<i8>,
+        uptime_seconds: u64,
+    ) -> RequestIAmAlive {
+        RequestIAmAlive {
+            mac_address,
+            queued_alerts,
+            dropped_alerts,
+            rssi,
+            uptime_seconds,
+            alert_deliveries: Vec::new(),
+        }
+    }
+
+    pub fn with_alert_deliveries(
+        mut self,
+        alert_deliveries: Vec<AlertDelivery>,
+    ) -> RequestIAmAlive {
+        self.alert_deliveries = alert_deliveries;
+        self
     }
 }
@@ -0,0 +1,20 @@
+use super::acknowledgement::Acknowledgement;
+use serde::Deserialize;
+
+// response of the requests that carry no other data (alert, IAmAlive)
+#[derive(Deserialize, Debug)]
+pub struct ResponseAcknowledgement {
+    pub success: bool,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+impl Acknowledgement for ResponseAcknowledgement {
+    fn is_success(&self) -> bool {
+        self.success
+    }
+
+    fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+}
@@ -0,0 +1,22 @@
+use super::acknowledgement::Acknowledgement;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+pub struct ResponseRegisterDevice {
+    pub success: bool,
+    #[serde(default)]
+    pub message: Option<String>,
+    // sent as a bearer token with the other requests to the server
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+impl Acknowledgement for ResponseRegisterDevice {
+    fn is_success(&self) -> bool {
+        self.success
+    }
+
+    fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+}
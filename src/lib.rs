@@ -0,0 +1,14 @@
+pub mod config;
+pub mod discovery;
+pub mod dto;
+pub mod notifier;
+pub mod peripheral;
+pub mod provisioning;
+#[cfg(feature = "simulator")]
+pub mod scenario;
+pub mod service;
+#[cfg(feature = "simulator")]
+pub mod stand_in;
+pub mod status;
+pub mod transport;
+pub mod util;
@@ -1,15 +1,87 @@
-use crate::service::orchestrator_service::orchestrate;
 use anyhow::Ok;
-mod config;
 use esp_idf_sys::{self as _};
-mod dto;
-mod service;
-mod util;
+use log::warn;
+use motion_detector::{
+    config::config,
+    discovery::{esp_discovery::EspMdnsDiscovery, traits::DeviceAdvertiser},
+    dto::device_status::DeviceStatus,
+    service::{
+        discovery_service::{device_advertisement, DiscoveryService},
+        orchestrator_service::orchestrate,
+        peripheral_service::PeripheralService,
+        settings_service::{DEVICE_NAME, MQTT_BROKER_URL},
+    },
+    status::esp_status_server::EspStatusServer,
+    transport::{
+        esp_transport::{mqtt_transport, EspHttpTransport},
+        mqtt_transport::MqttTopics,
+        traits::Transport,
+    },
+    util::clock_util::{Clock, SystemClock},
+};
+use std::sync::{Arc, Mutex};
 fn main() -> anyhow::Result<()> {
     esp_idf_svc::sys::link_patches();
     esp_idf_svc::log::EspLogger::initialize_default();
 
-    orchestrate();
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock::new());
+    let mut peripheral_service = PeripheralService::new(clock.clone());
+    let mac_address = peripheral_service.get_mac_address();
+    let device_name = peripheral_service.settings().get(&DEVICE_NAME);
+    let broker_url = peripheral_service.settings().get(&MQTT_BROKER_URL);
+    let transport: Box<dyn Transport> = match broker_url.as_deref() {
+        Some(broker_url) => Box::new(mqtt_transport(
+            broker_url,
+            MqttTopics::new(
+                config::MQTT_TOPIC_PREFIX,
+                config::HOME_ASSISTANT_DISCOVERY_PREFIX,
+                &device_name,
+                &mac_address,
+            ),
+        )?),
+        None => Box::new(EspHttpTransport::new()),
+    };
+    let status_board = Arc::new(Mutex::new(DeviceStatus::new(&device_name, &mac_address)));
+    // the mdns component is shared by the advertisement and the lookup, and
+    // kept until the end of main when the lookup does not take it
+    let mut mdns = match (config::DEVICE_MDNS_SERVICE, config::SERVER_MDNS_SERVICE) {
+        (None, None) => None,
+        _ => Some(EspMdnsDiscovery::new()?),
+    };
+    // kept until the end of main, which stops the server
+    let _status_server = match (config::DEVICE_MDNS_SERVICE, mdns.as_mut()) {
+        (Some(service_type), Some(mdns)) => {
+            let advertisement = device_advertisement(
+                service_type,
+                &device_name,
+                &mac_address,
+                config::STATUS_PORT,
+            );
+            if let Err(e) = mdns.advertise(&advertisement) {
+                warn!("could not advertise the device: {}", e);
+            }
+            Some(EspStatusServer::start(
+                config::STATUS_PORT,
+                status_board.clone(),
+            )?)
+        }
+        _ => None,
+    };
+    // the MQTT transport only needs the broker; otherwise the mdns component
+    // moves to the lookup and keeps answering for the device from there
+    let discovery_service = match (config::SERVER_MDNS_SERVICE, broker_url) {
+        (Some(service_type), None) => mdns
+            .take()
+            .map(|mdns| DiscoveryService::new(Box::new(mdns), service_type)),
+        _ => None,
+    };
+    orchestrate(
+        peripheral_service,
+        transport,
+        clock,
+        discovery_service,
+        status_board,
+    );
 
     return Ok(());
 }
@@ -1,4 +0,0 @@
-pub mod config;
-pub mod dto;
-pub mod service;
-pub mod util;
@@ -0,0 +1,31 @@
+use super::traits::{Notifier, NotifierRequest};
+use serde_json::json;
+
+// message posted to a channel through a webhook
+pub struct DiscordNotifier {
+    webhook_url: String,
+    username: Option<String>,
+}
+
+impl DiscordNotifier {
+    pub fn new(webhook_url: String, username: Option<String>) -> DiscordNotifier {
+        DiscordNotifier {
+            webhook_url,
+            username,
+        }
+    }
+}
+
+impl Notifier for DiscordNotifier {
+    fn request(&self, _title: &str, message: &str) -> NotifierRequest {
+        let mut payload = json!({ "content": message });
+        if let Some(username) = &self.username {
+            payload["username"] = json!(username);
+        }
+        NotifierRequest {
+            url: self.webhook_url.clone(),
+            authorization: None,
+            payload: payload.to_string(),
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use super::traits::{Notifier, NotifierRequest};
+use serde_json::json;
+
+// message created with the token of a Gotify application
+pub struct GotifyNotifier {
+    url: String,
+    token: String,
+    priority: Option<u8>,
+}
+
+impl GotifyNotifier {
+    pub fn new(url: String, token: String, priority: Option<u8>) -> GotifyNotifier {
+        GotifyNotifier {
+            url,
+            token,
+            priority,
+        }
+    }
+}
+
+impl Notifier for GotifyNotifier {
+    fn request(&self, title: &str, message: &str) -> NotifierRequest {
+        let mut payload = json!({
+            "title": title,
+            "message": message,
+        });
+        if let Some(priority) = self.priority {
+            payload["priority"] = json!(priority);
+        }
+        NotifierRequest {
+            url: format!("{}/message", self.url.trim_end_matches('/')),
+            authorization: Some(format!("Bearer {}", self.token)),
+            payload: payload.to_string(),
+        }
+    }
+}
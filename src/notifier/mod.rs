@@ -0,0 +1,5 @@
+pub mod discord_notifier;
+pub mod gotify_notifier;
+pub mod ntfy_notifier;
+pub mod telegram_notifier;
+pub mod traits;
@@ -0,0 +1,46 @@
+use super::traits::{Notifier, NotifierRequest};
+use serde_json::json;
+
+// message published to a topic, as JSON on the root of the server; `token`
+// is an access token of a protected topic
+pub struct NtfyNotifier {
+    url: String,
+    topic: String,
+    token: Option<String>,
+    priority: Option<u8>,
+}
+
+impl NtfyNotifier {
+    pub fn new(
+        url: String,
+        topic: String,
+        token: Option<String>,
+        priority: Option<u8>,
+    ) -> NtfyNotifier {
+        NtfyNotifier {
+            url,
+            topic,
+            token,
+            priority,
+        }
+    }
+}
+
+impl Notifier for NtfyNotifier {
+    fn request(&self, title: &str, message: &str) -> NotifierRequest {
+        let mut payload = json!({
+            "topic": self.topic,
+            "title": title,
+            "message": message,
+            "tags": ["rotating_light"],
+        });
+        if let Some(priority) = self.priority {
+            payload["priority"] = json!(priority);
+        }
+        NotifierRequest {
+            url: format!("{}/", self.url.trim_end_matches('/')),
+            authorization: self.token.as_ref().map(|token| format!("Bearer {}", token)),
+            payload: payload.to_string(),
+        }
+    }
+}
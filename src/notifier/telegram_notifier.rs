@@ -0,0 +1,33 @@
+use super::traits::{Notifier, NotifierRequest};
+use serde_json::json;
+
+// message sent by a bot to a chat with the Bot API `sendMessage` method
+pub struct TelegramNotifier {
+    api_url: String,
+    bot_token: String,
+    chat_id: String,
+}
+
+impl TelegramNotifier {
+    pub fn new(api_url: String, bot_token: String, chat_id: String) -> TelegramNotifier {
+        TelegramNotifier {
+            api_url,
+            bot_token,
+            chat_id,
+        }
+    }
+}
+
+impl Notifier for TelegramNotifier {
+    fn request(&self, _title: &str, message: &str) -> NotifierRequest {
+        NotifierRequest {
+            url: format!(
+                "{}/bot{}/sendMessage",
+                self.api_url.trim_end_matches('/'),
+                self.bot_token
+            ),
+            authorization: None,
+            payload: json!({ "chat_id": self.chat_id, "text": message }).to_string(),
+        }
+    }
+}
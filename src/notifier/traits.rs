@@ -0,0 +1,171 @@
+// Notification services alerted directly by the device, without the Elisys
+// server: each implementation turns the text of the alert into the JSON
+// request expected by its service, sent by `ClientService` through the
+// transport.
+use super::{
+    discord_notifier::DiscordNotifier, gotify_notifier::GotifyNotifier,
+    ntfy_notifier::NtfyNotifier, telegram_notifier::TelegramNotifier,
+};
+use crate::dto::config_response::NotifierService;
+
+pub struct NotifierRequest {
+    pub url: String,
+    // value of the `authorization` header
+    pub authorization: Option<String>,
+    pub payload: String,
+}
+
+pub trait Notifier {
+    // `title` is the name of the device, for the services showing one
+    fn request(&self, title: &str, message: &str) -> NotifierRequest;
+}
+
+pub fn from_configuration(service: &NotifierService) -> Box<dyn Notifier> {
+    match service.clone() {
+        NotifierService::Discord {
+            webhook_url,
+            username,
+        } => Box::new(DiscordNotifier::new(webhook_url, username)),
+        NotifierService::Ntfy {
+            url,
+            topic,
+            token,
+            priority,
+        } => Box::new(NtfyNotifier::new(url, topic, token, priority)),
+        NotifierService::Gotify {
+            url,
+            token,
+            priority,
+        } => Box::new(GotifyNotifier::new(url, token, priority)),
+        NotifierService::Telegram {
+            bot_token,
+            chat_id,
+            api_url,
+        } => Box::new(TelegramNotifier::new(api_url, bot_token, chat_id)),
+    }
+}
+
+// `template` with its {deviceName}, {macAddress} and {detectedAt} placeholders
+// replaced
+pub fn render_message(
+    template: &str,
+    device_name: &str,
+    mac_address: &str,
+    detected_at: &str,
+) -> String {
+    template
+        .replace("{deviceName}", device_name)
+        .replace("{macAddress}", mac_address)
+        .replace("{detectedAt}", detected_at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn request(service: Value) -> (NotifierRequest, Value) {
+        let service: NotifierService = serde_json::from_value(service).unwrap();
+        let request = from_configuration(&service).request("hall", "Motion detected");
+        let payload = serde_json::from_str(&request.payload).unwrap();
+        (request, payload)
+    }
+
+    #[test]
+    fn message_replaces_the_placeholders() {
+        assert_eq!(
+            render_message(
+                "{deviceName} ({macAddress}) at {detectedAt}, {deviceName} again, {unknown}",
+                "hall",
+                "02:00:00:00:00:01",
+                "2023-11-20T10:00:00Z"
+            ),
+            "hall (02:00:00:00:00:01) at 2023-11-20T10:00:00Z, hall again, {unknown}"
+        );
+    }
+
+    #[test]
+    fn discord_posts_the_message_to_the_webhook() {
+        let (request, payload) = request(json!({
+            "type": "discord",
+            "webhookUrl": "https://discord.com/api/webhooks/1/abc",
+            "username": "sensor",
+        }));
+
+        assert_eq!(request.url, "https://discord.com/api/webhooks/1/abc");
+        assert_eq!(request.authorization, None);
+        assert_eq!(
+            payload,
+            json!({"content": "Motion detected", "username": "sensor"})
+        );
+    }
+
+    #[test]
+    fn ntfy_publishes_on_the_root_of_the_server() {
+        let (request, payload) = request(json!({
+            "type": "ntfy",
+            "url": "https://ntfy.example.org/",
+            "topic": "alerts",
+            "token": "tk_1",
+            "priority": 5,
+        }));
+
+        assert_eq!(request.url, "https://ntfy.example.org/");
+        assert_eq!(request.authorization.as_deref(), Some("Bearer tk_1"));
+        assert_eq!(
+            payload,
+            json!({
+                "topic": "alerts",
+                "title": "hall",
+                "message": "Motion detected",
+                "tags": ["rotating_light"],
+                "priority": 5,
+            })
+        );
+    }
+
+    #[test]
+    fn ntfy_defaults_to_ntfy_sh_without_token() {
+        let (request, payload) = request(json!({"type": "ntfy", "topic": "alerts"}));
+
+        assert_eq!(request.url, "https://ntfy.sh/");
+        assert_eq!(request.authorization, None);
+        assert_eq!(payload.get("priority"), None);
+    }
+
+    #[test]
+    fn gotify_creates_a_message_with_the_application_token() {
+        let (request, payload) = request(json!({
+            "type": "gotify",
+            "url": "https://gotify.example.org",
+            "token": "app-token",
+            "priority": 8,
+        }));
+
+        assert_eq!(request.url, "https://gotify.example.org/message");
+        assert_eq!(request.authorization.as_deref(), Some("Bearer app-token"));
+        assert_eq!(
+            payload,
+            json!({"title": "hall", "message": "Motion detected", "priority": 8})
+        );
+    }
+
+    #[test]
+    fn telegram_sends_the_message_to_the_chat() {
+        let (request, payload) = request(json!({
+            "type": "telegram",
+            "botToken": "123:abc",
+            "chatId": "-42",
+        }));
+
+        assert_eq!(
+            request.url,
+            "https://api.telegram.org/bot123:abc/sendMessage"
+        );
+        assert_eq!(request.authorization, None);
+        assert_eq!(
+            payload,
+            json!({"chat_id": "-42", "text": "Motion detected"})
+        );
+    }
+}
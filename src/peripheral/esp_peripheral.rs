@@ -0,0 +1,159 @@
+use super::traits::{
+    Buzzer, MotionSensor, NetworkLink, StatusLed, Storage, MAX_STORAGE_VALUE_LENGTH,
+};
+use embedded_svc::wifi::{AuthMethod, ClientConfiguration, Configuration};
+use esp_idf_hal::gpio::{Input, InputPin, Output, OutputPin, PinDriver};
+use esp_idf_svc::{
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+    wifi::{BlockingWifi, EspWifi, WifiDeviceId},
+};
+use log::info;
+
+impl<P: InputPin> MotionSensor for PinDriver<'static, P, Input> {
+    fn is_motion_detected(&self) -> bool {
+        self.is_high()
+    }
+}
+
+impl<P: OutputPin> StatusLed for PinDriver<'static, P, Output> {
+    fn turn_on(&mut self) {
+        self.set_high().unwrap();
+    }
+
+    fn turn_off(&mut self) {
+        self.set_low().unwrap();
+    }
+}
+
+impl<P: OutputPin> Buzzer for PinDriver<'static, P, Output> {
+    fn turn_on(&mut self) {
+        self.set_high().unwrap();
+    }
+
+    fn turn_off(&mut self) {
+        self.set_low().unwrap();
+    }
+}
+
+pub struct EspNetworkLink {
+    wifi: BlockingWifi<EspWifi<'static>>,
+    wifi_ssid: String,
+    wifi_password: String,
+}
+
+impl EspNetworkLink {
+    pub fn new(
+        wifi: BlockingWifi<EspWifi<'static>>,
+        wifi_ssid: &str,
+        wifi_password: &str,
+    ) -> EspNetworkLink {
+        EspNetworkLink {
+            wifi,
+            wifi_ssid: wifi_ssid.to_owned(),
+            wifi_password: wifi_password.to_owned(),
+        }
+    }
+
+    // for the provisioning access point
+    pub fn wifi(&mut self) -> &mut BlockingWifi<EspWifi<'static>> {
+        &mut self.wifi
+    }
+}
+
+impl NetworkLink for EspNetworkLink {
+    fn is_connected(&self) -> bool {
+        self.wifi.is_connected().unwrap()
+    }
+
+    fn connect(&mut self) -> anyhow::Result<()> {
+        connect_wifi(&mut self.wifi, &self.wifi_ssid, &self.wifi_password)
+    }
+
+    fn get_mac_address(&self) -> String {
+        let mav = &self
+            .wifi
+            .wifi()
+            .driver()
+            .get_mac(WifiDeviceId::Sta)
+            .unwrap();
+        let mac_address_obj =
+            macaddr::MacAddr6::new(mav[0], mav[1], mav[2], mav[3], mav[4], mav[5]);
+        let mac_address_value = mac_address_obj.to_string();
+        info!("MAC_ADDRESS: {:?}", mac_address_value);
+        mac_address_value
+    }
+
+    fn get_rssi(&self) -> Option<i8> {
+        let mut access_point = esp_idf_sys::wifi_ap_record_t::default();
+        match unsafe { esp_idf_sys::esp_wifi_sta_get_ap_info(&mut access_point) } {
+            esp_idf_sys::ESP_OK => Some(access_point.rssi),
+            _ => None,
+        }
+    }
+}
+
+pub struct EspStorage {
+    nvs: EspNvs<NvsDefault>,
+}
+
+impl EspStorage {
+    pub fn new(partition: EspDefaultNvsPartition, namespace: &str) -> anyhow::Result<EspStorage> {
+        Ok(EspStorage {
+            nvs: EspNvs::new(partition, namespace, true)?,
+        })
+    }
+}
+
+impl Storage for EspStorage {
+    fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        let mut buffer = vec![0u8; MAX_STORAGE_VALUE_LENGTH + 1];
+        Ok(self
+            .nvs
+            .get_str(key, &mut buffer)?
+            .map(|value| value.to_owned()))
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        if value.len() > MAX_STORAGE_VALUE_LENGTH {
+            return Err(anyhow::Error::msg(format!(
+                "value of {} too long for NVS: {} bytes",
+                key,
+                value.len()
+            )));
+        }
+        self.nvs.set_str(key, value)?;
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> anyhow::Result<()> {
+        self.nvs.remove(key)?;
+        Ok(())
+    }
+}
+
+fn connect_wifi(
+    wifi: &mut BlockingWifi<EspWifi<'static>>,
+    ssid: &str,
+    password: &str,
+) -> anyhow::Result<()> {
+    let wifi_configuration: Configuration = Configuration::Client(ClientConfiguration {
+        ssid: ssid.into(),
+        bssid: None,
+        auth_method: AuthMethod::WPA2Personal,
+        password: password.into(),
+        channel: None,
+    });
+    info!("Connecting to SSID: {}", ssid);
+    wifi.set_configuration(&wifi_configuration)?;
+
+    wifi.start()?;
+    info!("Wifi started");
+
+    wifi.connect()?;
+    info!("Wifi connected: {}", ssid);
+
+    wifi.wait_netif_up()?;
+    info!("Wifi netif up");
+
+    Ok(())
+}
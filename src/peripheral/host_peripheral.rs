@@ -0,0 +1,186 @@
+use super::traits::{Buzzer, MotionSensor, NetworkLink, StatusLed, Storage};
+use anyhow::Error;
+use log::info;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+pub struct HostMotionSensor {
+    motion: Arc<AtomicBool>,
+}
+
+impl HostMotionSensor {
+    pub fn new(motion: Arc<AtomicBool>) -> HostMotionSensor {
+        HostMotionSensor { motion }
+    }
+}
+
+impl MotionSensor for HostMotionSensor {
+    fn is_motion_detected(&self) -> bool {
+        self.motion.load(Ordering::SeqCst)
+    }
+}
+
+// in-memory output pin: keeps the current level and every level change,
+// so that LED/buzzer patterns can be inspected after the fact
+pub struct HostOutput {
+    name: String,
+    is_on: bool,
+    changes: Arc<Mutex<Vec<bool>>>,
+}
+
+impl HostOutput {
+    pub fn new(name: &str, changes: Arc<Mutex<Vec<bool>>>) -> HostOutput {
+        HostOutput {
+            name: name.to_owned(),
+            is_on: false,
+            changes,
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.is_on
+    }
+
+    fn set(&mut self, is_on: bool) {
+        if self.is_on != is_on {
+            info!("[{}] {}", self.name, if is_on { "ON" } else { "OFF" });
+        }
+        self.is_on = is_on;
+        self.changes.lock().unwrap().push(is_on);
+    }
+}
+
+impl StatusLed for HostOutput {
+    fn turn_on(&mut self) {
+        self.set(true);
+    }
+
+    fn turn_off(&mut self) {
+        self.set(false);
+    }
+}
+
+impl Buzzer for HostOutput {
+    fn turn_on(&mut self) {
+        self.set(true);
+    }
+
+    fn turn_off(&mut self) {
+        self.set(false);
+    }
+}
+
+pub struct HostNetworkLink {
+    mac_address: String,
+    reachable: Arc<AtomicBool>,
+    connected: bool,
+}
+
+impl HostNetworkLink {
+    pub fn new(mac_address: &str, reachable: Arc<AtomicBool>) -> HostNetworkLink {
+        HostNetworkLink {
+            mac_address: mac_address.to_owned(),
+            reachable,
+            connected: false,
+        }
+    }
+}
+
+impl NetworkLink for HostNetworkLink {
+    fn is_connected(&self) -> bool {
+        self.connected && self.reachable.load(Ordering::SeqCst)
+    }
+
+    fn connect(&mut self) -> anyhow::Result<()> {
+        self.connected = self.reachable.load(Ordering::SeqCst);
+        if !self.connected {
+            return Err(Error::msg("network unreachable"));
+        }
+        info!("Host network connected");
+        Ok(())
+    }
+
+    fn get_mac_address(&self) -> String {
+        self.mac_address.clone()
+    }
+
+    fn get_rssi(&self) -> Option<i8> {
+        None
+    }
+}
+
+// one file per key in the given directory, kept across simulator runs
+pub struct HostFileStorage {
+    directory: PathBuf,
+}
+
+impl HostFileStorage {
+    pub fn new(directory: PathBuf) -> anyhow::Result<HostFileStorage> {
+        fs::create_dir_all(&directory)?;
+        Ok(HostFileStorage { directory })
+    }
+}
+
+impl Storage for HostFileStorage {
+    fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        match fs::read_to_string(self.directory.join(key)) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        // written aside and renamed, so that a crash leaves the old value
+        let path = self.directory.join(key);
+        let temporary_path = self.directory.join(format!("{}.tmp", key));
+        fs::write(&temporary_path, value)?;
+        fs::rename(temporary_path, path)?;
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> anyhow::Result<()> {
+        match fs::remove_file(self.directory.join(key)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+// shared map, so that the content can be inspected or survive a restart of
+// the detector within the same process
+pub struct HostMemoryStorage {
+    values: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl HostMemoryStorage {
+    pub fn new(values: Arc<Mutex<HashMap<String, String>>>) -> HostMemoryStorage {
+        HostMemoryStorage { values }
+    }
+}
+
+impl Storage for HostMemoryStorage {
+    fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.values.lock().unwrap().get(key).cloned())
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        self.values
+            .lock()
+            .unwrap()
+            .insert(key.to_owned(), value.to_owned());
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> anyhow::Result<()> {
+        self.values.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
@@ -0,0 +1,5 @@
+#[cfg(feature = "hal")]
+pub mod esp_peripheral;
+#[cfg(feature = "simulator")]
+pub mod host_peripheral;
+pub mod traits;
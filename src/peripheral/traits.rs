@@ -0,0 +1,36 @@
+// Hardware abstraction used by `PeripheralService` and the orchestrator:
+// the ESP-IDF drivers implement these traits on the chip, the in-memory
+// host types implement them on Linux.
+
+pub trait MotionSensor {
+    fn is_motion_detected(&self) -> bool;
+}
+
+pub trait StatusLed {
+    fn turn_on(&mut self);
+    fn turn_off(&mut self);
+}
+
+pub trait Buzzer {
+    fn turn_on(&mut self);
+    fn turn_off(&mut self);
+}
+
+pub trait NetworkLink {
+    fn is_connected(&self) -> bool;
+    fn connect(&mut self) -> anyhow::Result<()>;
+    fn get_mac_address(&self) -> String;
+    // signal strength of the access point, in dBm, when connected
+    fn get_rssi(&self) -> Option<i8>;
+}
+
+// longest value every storage accepts: NVS strings are limited to 4000
+// bytes, terminator included
+pub const MAX_STORAGE_VALUE_LENGTH: usize = 3999;
+
+// persistent key/value storage: NVS on the chip, files or memory on Linux
+pub trait Storage {
+    fn get(&self, key: &str) -> anyhow::Result<Option<String>>;
+    fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()>;
+    fn remove(&mut self, key: &str) -> anyhow::Result<()>;
+}
@@ -0,0 +1,163 @@
+// DNS server of the provisioning access point: every A query is answered
+// with the address of the device, so that the captive portal checks of the
+// phones reach the form. Other queries get an empty answer.
+use log::{info, warn};
+use std::net::{Ipv4Addr, UdpSocket};
+
+pub const DNS_PORT: u16 = 53;
+const HEADER_LENGTH: usize = 12;
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+const TTL_SECONDS: u32 = 60;
+// one UDP message
+const MAX_MESSAGE_LENGTH: usize = 512;
+
+// answers until the socket fails
+pub fn serve(socket: UdpSocket, address: Ipv4Addr) {
+    info!("[captive dns] answering with {}", address);
+    let mut buffer = [0u8; MAX_MESSAGE_LENGTH];
+    loop {
+        let (length, client) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("[captive dns] {}", e);
+                return;
+            }
+        };
+        if let Some(response) = answer(&buffer[..length], address) {
+            if let Err(e) = socket.send_to(&response, client) {
+                warn!("[captive dns] {}", e);
+            }
+        }
+    }
+}
+
+// response to a standard query with one question, None for anything else
+pub fn answer(query: &[u8], address: Ipv4Addr) -> Option<Vec<u8>> {
+    if query.len() < HEADER_LENGTH {
+        return None;
+    }
+    let flags = u16::from_be_bytes([query[2], query[3]]);
+    let question_count = u16::from_be_bytes([query[4], query[5]]);
+    // responses and opcodes other than QUERY are ignored
+    if flags & 0xf800 != 0 || question_count != 1 {
+        return None;
+    }
+    // the question is a sequence of labels ended by an empty one
+    let mut position = HEADER_LENGTH;
+    loop {
+        let length = *query.get(position)? as usize;
+        if length & 0xc0 != 0 {
+            return None;
+        }
+        position += 1 + length;
+        if length == 0 {
+            break;
+        }
+    }
+    let question_end = position + 4;
+    let question = query.get(HEADER_LENGTH..question_end)?;
+    let query_type = u16::from_be_bytes([query[position], query[position + 1]]);
+    let query_class = u16::from_be_bytes([query[position + 2], query[position + 3]]);
+    let is_answered = query_type == TYPE_A && query_class == CLASS_IN;
+
+    let mut response = Vec::with_capacity(question_end + 16);
+    response.extend_from_slice(&query[0..2]);
+    // response, authoritative, recursion desired copied, recursion available
+    response.extend_from_slice(&(0x8480 | (flags & 0x0100)).to_be_bytes());
+    response.extend_from_slice(&1u16.to_be_bytes());
+    response.extend_from_slice(&(is_answered as u16).to_be_bytes());
+    response.extend_from_slice(&[0, 0, 0, 0]);
+    response.extend_from_slice(question);
+    if is_answered {
+        // pointer to the name of the question
+        response.extend_from_slice(&[0xc0, HEADER_LENGTH as u8]);
+        response.extend_from_slice(&TYPE_A.to_be_bytes());
+        response.extend_from_slice(&CLASS_IN.to_be_bytes());
+        response.extend_from_slice(&TTL_SECONDS.to_be_bytes());
+        response.extend_from_slice(&4u16.to_be_bytes());
+        response.extend_from_slice(&address.octets());
+    }
+    Some(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: Ipv4Addr = Ipv4Addr::new(192, 168, 71, 1);
+
+    // recursive query with one question for `name`
+    fn query(name: &str, query_type: u16) -> Vec<u8> {
+        let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in name.split('.') {
+            query.push(label.len() as u8);
+            query.extend_from_slice(label.as_bytes());
+        }
+        query.push(0);
+        query.extend_from_slice(&query_type.to_be_bytes());
+        query.extend_from_slice(&CLASS_IN.to_be_bytes());
+        query
+    }
+
+    #[test]
+    fn a_query_is_answered_with_the_address() {
+        let query = query("connectivitycheck.gstatic.com", TYPE_A);
+
+        let response = answer(&query, ADDRESS).unwrap();
+
+        // same id, response with recursion desired and available
+        assert_eq!(response[0..4], [0x12, 0x34, 0x85, 0x80]);
+        // one question, one answer
+        assert_eq!(response[4..12], [0, 1, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(response[HEADER_LENGTH..query.len()], query[HEADER_LENGTH..]);
+        let record = &response[query.len()..];
+        assert_eq!(record[0..2], [0xc0, HEADER_LENGTH as u8]);
+        assert_eq!(record[2..6], [0, 1, 0, 1]);
+        assert_eq!(record[10..12], [0, 4]);
+        assert_eq!(record[12..], ADDRESS.octets());
+    }
+
+    #[test]
+    fn other_queries_get_an_empty_answer() {
+        let query = query("captive.apple.com", 28);
+
+        let response = answer(&query, ADDRESS).unwrap();
+
+        assert_eq!(response[6..8], [0, 0]);
+        assert_eq!(response.len(), query.len());
+    }
+
+    #[test]
+    fn malformed_queries_are_ignored() {
+        let query = query("example.com", TYPE_A);
+        // shorter than the header, question cut in the middle of a label or
+        // before its type
+        for length in [0, 5, HEADER_LENGTH, HEADER_LENGTH + 3, query.len() - 1] {
+            assert_eq!(answer(&query[..length], ADDRESS), None, "{}", length);
+        }
+        // label longer than the message
+        let mut long_label = query.clone();
+        long_label[HEADER_LENGTH] = 63;
+        assert_eq!(answer(&long_label, ADDRESS), None);
+        // compression pointer in the question
+        let mut pointer = query.clone();
+        pointer[HEADER_LENGTH] = 0xc0;
+        assert_eq!(answer(&pointer, ADDRESS), None);
+    }
+
+    #[test]
+    fn responses_and_other_opcodes_are_ignored() {
+        let mut response = query("example.com", TYPE_A);
+        response[2] |= 0x80;
+        assert_eq!(answer(&response, ADDRESS), None);
+
+        let mut status = query("example.com", TYPE_A);
+        status[2] |= 2 << 3;
+        assert_eq!(answer(&status, ADDRESS), None);
+
+        let mut two_questions = query("example.com", TYPE_A);
+        two_questions[5] = 2;
+        assert_eq!(answer(&two_questions, ADDRESS), None);
+    }
+}
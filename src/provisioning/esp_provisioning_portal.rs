@@ -0,0 +1,115 @@
+use super::{
+    captive_dns::{self, DNS_PORT},
+    provisioning_portal::{form_page, saved_page, ProvisioningForm, MAX_FORM_LENGTH, PORTAL_PATH},
+};
+use embedded_svc::{
+    http::{Headers, Method},
+    io::{Read, Write},
+    wifi::{AccessPointConfiguration, AuthMethod, Configuration},
+};
+use esp_idf_svc::{
+    http::server::{Configuration as HttpConfiguration, EspHttpServer},
+    wifi::{BlockingWifi, EspWifi},
+};
+use log::info;
+use std::{
+    net::UdpSocket,
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Duration,
+};
+
+// every path not handled by the form
+const CAPTIVE_PATHS: &str = "/*";
+
+// access point, DNS server and form; the HTTP server stops when dropped
+pub struct EspProvisioningPortal {
+    _server: EspHttpServer<'static>,
+    receiver: Receiver<ProvisioningForm>,
+}
+
+impl EspProvisioningPortal {
+    // `password` is None for an open access point
+    pub fn start(
+        wifi: &mut BlockingWifi<EspWifi<'static>>,
+        access_point_name: &str,
+        password: Option<&str>,
+        current: ProvisioningForm,
+    ) -> anyhow::Result<EspProvisioningPortal> {
+        if wifi.is_started()? {
+            wifi.stop()?;
+        }
+        wifi.set_configuration(&Configuration::AccessPoint(AccessPointConfiguration {
+            ssid: access_point_name.into(),
+            auth_method: match password {
+                Some(_) => AuthMethod::WPA2Personal,
+                None => AuthMethod::None,
+            },
+            password: password.unwrap_or_default().into(),
+            ..Default::default()
+        }))?;
+        wifi.start()?;
+        wifi.wait_netif_up()?;
+        let address = wifi.wifi().ap_netif().get_ip_info()?.ip;
+        info!(
+            "[provisioning] access point {} on http://{}{}",
+            access_point_name, address, PORTAL_PATH
+        );
+
+        let socket = UdpSocket::bind(("0.0.0.0", DNS_PORT))?;
+        thread::spawn(move || captive_dns::serve(socket, address));
+
+        let (sender, receiver) = mpsc::channel();
+        let mut server = EspHttpServer::new(&HttpConfiguration {
+            uri_match_wildcard: true,
+            ..Default::default()
+        })?;
+        let form = current.clone();
+        server.fn_handler(PORTAL_PATH, Method::Get, move |request| {
+            request
+                .into_response(200, None, &[("content-type", "text/html; charset=utf-8")])?
+                .write_all(form_page(&form, None).as_bytes())?;
+            Ok(())
+        })?;
+        server.fn_handler(PORTAL_PATH, Method::Post, move |mut request| {
+            let content_length = request.content_len().unwrap_or(0) as usize;
+            if content_length > MAX_FORM_LENGTH {
+                request
+                    .into_response(413, None, &[("content-type", "text/html; charset=utf-8")])?
+                    .write_all(form_page(&current, Some("form too large")).as_bytes())?;
+                return Ok(());
+            }
+            let mut body = vec![0u8; content_length];
+            request.read_exact(&mut body)?;
+            let (status, page) = match ProvisioningForm::parse(&String::from_utf8_lossy(&body)) {
+                Ok(form) => {
+                    let page = saved_page(&form);
+                    sender.send(form)?;
+                    (200, page)
+                }
+                Err(e) => (400, form_page(&current, Some(&e.to_string()))),
+            };
+            request
+                .into_response(
+                    status,
+                    None,
+                    &[("content-type", "text/html; charset=utf-8")],
+                )?
+                .write_all(page.as_bytes())?;
+            Ok(())
+        })?;
+        server.fn_handler(CAPTIVE_PATHS, Method::Get, |request| {
+            request.into_response(302, None, &[("location", PORTAL_PATH)])?;
+            Ok(())
+        })?;
+        Ok(EspProvisioningPortal {
+            _server: server,
+            receiver,
+        })
+    }
+
+    // the first valid form submitted, None after `timeout`
+    pub fn wait(&self, timeout: Duration) -> Option<ProvisioningForm> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+}
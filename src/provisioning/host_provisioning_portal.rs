@@ -0,0 +1,108 @@
+use super::provisioning_portal::{
+    form_page, saved_page, ProvisioningForm, MAX_FORM_LENGTH, PORTAL_PATH,
+};
+use log::{info, warn};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::Duration,
+};
+
+// the form on a local port, for the simulator; no access point nor DNS
+pub struct HostProvisioningPortal {
+    address: SocketAddr,
+    receiver: Receiver<ProvisioningForm>,
+}
+
+impl HostProvisioningPortal {
+    pub fn start(port: u16, current: ProvisioningForm) -> anyhow::Result<HostProvisioningPortal> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        let address = listener.local_addr()?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = answer(stream, &current, &sender) {
+                    warn!("[provisioning] {}", e);
+                }
+            }
+        });
+        info!("[provisioning] form on http://{}{}", address, PORTAL_PATH);
+        Ok(HostProvisioningPortal { address, receiver })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.address.port()
+    }
+
+    // the first valid form submitted, None after `timeout`
+    pub fn wait(&self, timeout: Option<Duration>) -> Option<ProvisioningForm> {
+        match timeout {
+            Some(timeout) => self.receiver.recv_timeout(timeout).ok(),
+            None => self.receiver.recv().ok(),
+        }
+    }
+}
+
+fn answer(
+    stream: TcpStream,
+    current: &ProvisioningForm,
+    sender: &Sender<ProvisioningForm>,
+) -> anyhow::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse::<usize>()?;
+            }
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    let (status, location, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(PORTAL_PATH)) => ("200 OK", None, form_page(current, None)),
+        (Some("POST"), Some(PORTAL_PATH)) if content_length > MAX_FORM_LENGTH => (
+            "413 Payload Too Large",
+            None,
+            form_page(current, Some("form too large")),
+        ),
+        (Some("POST"), Some(PORTAL_PATH)) => {
+            let mut body = vec![0u8; content_length];
+            reader.read_exact(&mut body)?;
+            match ProvisioningForm::parse(&String::from_utf8_lossy(&body)) {
+                Ok(form) => {
+                    let page = saved_page(&form);
+                    sender.send(form)?;
+                    ("200 OK", None, page)
+                }
+                Err(e) => (
+                    "400 Bad Request",
+                    None,
+                    form_page(current, Some(&e.to_string())),
+                ),
+            }
+        }
+        _ => ("302 Found", Some(PORTAL_PATH), String::new()),
+    };
+    let location = location
+        .map(|location| format!("location: {}\r\n", location))
+        .unwrap_or_default();
+    let response = format!(
+        "HTTP/1.1 {}\r\n{}content-type: text/html; charset=utf-8\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        status,
+        location,
+        body.len(),
+        body
+    );
+    let stream = reader.get_mut();
+    stream.write_all(response.as_bytes())?;
+    stream.flush()?;
+    Ok(())
+}
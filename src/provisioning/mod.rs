@@ -0,0 +1,6 @@
+pub mod captive_dns;
+#[cfg(feature = "hal")]
+pub mod esp_provisioning_portal;
+#[cfg(feature = "simulator")]
+pub mod host_provisioning_portal;
+pub mod provisioning_portal;
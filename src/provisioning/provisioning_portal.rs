@@ -0,0 +1,364 @@
+// Web form of the provisioning access point, started when the device has no
+// WiFi credentials or cannot connect with them: it sets the WiFi network,
+// the server and the name of the device, which are saved in the settings
+// before the device restarts in station mode. Every other path redirects to
+// the form, so that the phones opening a captive portal land on it.
+use crate::{
+    service::{
+        discovery_service::device_hostname,
+        settings_service::{SettingsService, DEVICE_NAME, SERVER_URL, WIFI_PASSWORD, WIFI_SSID},
+    },
+    util::url_util::origin,
+};
+use anyhow::Error;
+
+pub const PORTAL_PATH: &str = "/";
+// larger bodies are refused
+pub const MAX_FORM_LENGTH: usize = 1024;
+// limits of the WiFi standard
+const MAX_SSID_LENGTH: usize = 32;
+const MIN_PASSWORD_LENGTH: usize = 8;
+const MAX_PASSWORD_LENGTH: usize = 63;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProvisioningForm {
+    pub wifi_ssid: String,
+    // None keeps the stored password, empty for an open network
+    pub wifi_password: Option<String>,
+    // origin of the server, None for the urls of config.rs
+    pub server_url: Option<String>,
+    pub device_name: String,
+}
+
+impl ProvisioningForm {
+    // current values, shown in the form; the password is never sent back
+    pub fn from_settings(settings: &SettingsService) -> ProvisioningForm {
+        ProvisioningForm {
+            wifi_ssid: settings.get(&WIFI_SSID),
+            wifi_password: None,
+            server_url: settings.get(&SERVER_URL),
+            device_name: settings.get(&DEVICE_NAME),
+        }
+    }
+
+    // `application/x-www-form-urlencoded` body of the form: an empty password
+    // keeps the stored one, unless the network is marked as open
+    pub fn parse(body: &str) -> anyhow::Result<ProvisioningForm> {
+        let mut form = ProvisioningForm {
+            wifi_ssid: String::new(),
+            wifi_password: None,
+            server_url: None,
+            device_name: String::new(),
+        };
+        let mut is_open = false;
+        for pair in body.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = decode_form_value(value)?;
+            match name {
+                "ssid" => form.wifi_ssid = value,
+                "password" => form.wifi_password = Some(value).filter(|value| !value.is_empty()),
+                "open" => is_open = true,
+                "server" => {
+                    let value = value.trim();
+                    form.server_url = Some(origin(value).to_owned()).filter(|url| !url.is_empty())
+                }
+                "name" => form.device_name = value.trim().to_owned(),
+                _ => {}
+            }
+        }
+        if is_open {
+            if form.wifi_password.is_some() {
+                return Err(Error::msg("an open network has no password"));
+            }
+            form.wifi_password = Some(String::new());
+        }
+        form.validate()?;
+        Ok(form)
+    }
+
+    pub fn save(&self, settings: &mut SettingsService) -> anyhow::Result<()> {
+        settings.set(&WIFI_SSID, &self.wifi_ssid)?;
+        if let Some(wifi_password) = &self.wifi_password {
+            settings.set(&WIFI_PASSWORD, wifi_password)?;
+        }
+        match &self.server_url {
+            Some(_) => settings.set(&SERVER_URL, &self.server_url)?,
+            None => settings.reset(&SERVER_URL)?,
+        }
+        settings.set(&DEVICE_NAME, &self.device_name)?;
+        Ok(())
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.wifi_ssid.is_empty() || self.wifi_ssid.len() > MAX_SSID_LENGTH {
+            return Err(Error::msg(format!(
+                "the network name must have 1 to {} characters",
+                MAX_SSID_LENGTH
+            )));
+        }
+        let password_length = self.wifi_password.as_ref().map_or(0, String::len);
+        if password_length != 0
+            && !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&password_length)
+        {
+            return Err(Error::msg(format!(
+                "the password must have {} to {} characters",
+                MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH
+            )));
+        }
+        if let Some(server_url) = &self.server_url {
+            if !server_url.starts_with("http://") && !server_url.starts_with("https://") {
+                return Err(Error::msg("the server must start with http:// or https://"));
+            }
+        }
+        if self.device_name.is_empty() {
+            return Err(Error::msg("the device name is missing"));
+        }
+        Ok(())
+    }
+}
+
+// name of the access point, e.g. `motion-detector-a1b2c3`
+pub fn access_point_name(device_name: &str, mac_address: &str) -> String {
+    let mut name = device_hostname(device_name, mac_address);
+    if name.len() > MAX_SSID_LENGTH {
+        name = name.split_off(name.len() - MAX_SSID_LENGTH);
+    }
+    name
+}
+
+pub fn form_page(form: &ProvisioningForm, error: Option<&str>) -> String {
+    let error = match error {
+        Some(error) => format!("<p class=\"error\">{}</p>", escape_html(error)),
+        None => String::new(),
+    };
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{name}</title>
+<style>body{{font-family:sans-serif;max-width:24em;margin:1em auto;padding:0 1em}}label,input,button{{display:block;width:100%;margin:.3em 0}}input[type=checkbox]{{display:inline;width:auto}}.error{{color:#b00}}</style>
+</head>
+<body>
+<h1>{name}</h1>
+{error}
+<form method="post" action="{path}">
+<label>WiFi network <input name="ssid" value="{ssid}" maxlength="32" required></label>
+<label>WiFi password <input name="password" type="password" maxlength="63" placeholder="empty to keep the current password"></label>
+<label><input name="open" type="checkbox"> Open network, without password</label>
+<label>Server <input name="server" value="{server}" placeholder="http://192.168.1.40:8080"></label>
+<label>Device name <input name="name" value="{name}" required></label>
+<button type="submit">Save and restart</button>
+</form>
+</body>
+</html>
+"#,
+        name = escape_html(&form.device_name),
+        error = error,
+        path = PORTAL_PATH,
+        ssid = escape_html(&form.wifi_ssid),
+        server = escape_html(form.server_url.as_deref().unwrap_or_default()),
+    )
+}
+
+pub fn saved_page(form: &ProvisioningForm) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{name}</title></head>\n<body><p>Saved. {name} restarts and connects to {ssid}.</p></body>\n</html>\n",
+        name = escape_html(&form.device_name),
+        ssid = escape_html(&form.wifi_ssid),
+    )
+}
+
+// `+` and `%XX` of a form value
+fn decode_form_value(value: &str) -> anyhow::Result<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let high = input.next().and_then(|digit| (digit as char).to_digit(16));
+                let low = input.next().and_then(|digit| (digit as char).to_digit(16));
+                match (high, low) {
+                    (Some(high), Some(low)) => bytes.push((high * 16 + low) as u8),
+                    _ => return Err(Error::msg("malformed form value")),
+                }
+            }
+            byte => bytes.push(byte),
+        }
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peripheral::traits::Storage;
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    #[derive(Clone, Default)]
+    struct MemoryStorage(Arc<Mutex<HashMap<String, String>>>);
+
+    impl Storage for MemoryStorage {
+        fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+            Ok(self.0.lock().unwrap().get(key).cloned())
+        }
+
+        fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(key.to_owned(), value.to_owned());
+            Ok(())
+        }
+
+        fn remove(&mut self, key: &str) -> anyhow::Result<()> {
+            self.0.lock().unwrap().remove(key);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn form_is_parsed() {
+        let form = ProvisioningForm::parse(
+            "ssid=Home+WiFi&password=p%40ssw0rd%21&server=http%3A%2F%2F192.168.1.40%3A8080%2Fapi&name=+Hall+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            form,
+            ProvisioningForm {
+                wifi_ssid: "Home WiFi".to_owned(),
+                wifi_password: Some("p@ssw0rd!".to_owned()),
+                server_url: Some("http://192.168.1.40:8080".to_owned()),
+                device_name: "Hall".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn empty_fields_keep_the_password_and_the_urls_of_config() {
+        let form =
+            ProvisioningForm::parse("ssid=Home&password=&server=&name=Hall&other=1").unwrap();
+
+        assert_eq!(form.wifi_password, None);
+        assert_eq!(form.server_url, None);
+    }
+
+    #[test]
+    fn open_network_has_an_empty_password() {
+        let form = ProvisioningForm::parse("ssid=Cafe&password=&open=on&name=Hall").unwrap();
+        assert_eq!(form.wifi_password.as_deref(), Some(""));
+
+        assert!(
+            ProvisioningForm::parse("ssid=Cafe&password=p%40ssw0rd%21&open=on&name=Hall").is_err()
+        );
+    }
+
+    #[test]
+    fn saving_without_password_keeps_the_stored_one() {
+        let storage = MemoryStorage::default();
+        let mut settings = SettingsService::new(Box::new(storage.clone()));
+        ProvisioningForm::parse(
+            "ssid=Home&password=p%40ssw0rd%21&server=http%3A%2F%2Fserver&name=Hall",
+        )
+        .unwrap()
+        .save(&mut settings)
+        .unwrap();
+
+        ProvisioningForm::parse("ssid=Office&name=Hall")
+            .unwrap()
+            .save(&mut settings)
+            .unwrap();
+
+        assert_eq!(settings.get(&WIFI_SSID), "Office");
+        assert_eq!(settings.get(&WIFI_PASSWORD), "p@ssw0rd!");
+        // the urls of config.rs again
+        assert_eq!(settings.get(&SERVER_URL), None);
+
+        ProvisioningForm::parse("ssid=Cafe&open=on&name=Hall")
+            .unwrap()
+            .save(&mut settings)
+            .unwrap();
+        assert_eq!(settings.get(&WIFI_PASSWORD), "");
+    }
+
+    #[test]
+    fn invalid_forms_are_refused() {
+        for body in [
+            // no network
+            "ssid=&name=Hall",
+            // network name longer than 32 bytes
+            "ssid=aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa&name=Hall",
+            // password too short, too long
+            "ssid=Home&password=short&name=Hall",
+            &format!("ssid=Home&password={}&name=Hall", "p".repeat(64)),
+            // server without scheme
+            "ssid=Home&server=192.168.1.40&name=Hall",
+            // no device name
+            "ssid=Home&name=+",
+            // malformed escape
+            "ssid=Home%2&name=Hall",
+        ] {
+            assert!(ProvisioningForm::parse(body).is_err(), "{}", body);
+        }
+    }
+
+    #[test]
+    fn form_values_are_decoded() {
+        assert_eq!(decode_form_value("a+b%20c").unwrap(), "a b c");
+        assert_eq!(decode_form_value("%C3%A9t%C3%A9").unwrap(), "été");
+        assert_eq!(decode_form_value("100%25").unwrap(), "100%");
+        assert_eq!(decode_form_value("").unwrap(), "");
+        // truncated or non hexadecimal escapes
+        assert!(decode_form_value("%4").is_err());
+        assert!(decode_form_value("%").is_err());
+        assert!(decode_form_value("%zz").is_err());
+        // not UTF-8
+        assert!(decode_form_value("%C3%28").is_err());
+    }
+
+    #[test]
+    fn access_point_name_keeps_the_end_of_a_long_name() {
+        assert_eq!(
+            access_point_name("Motion Detector", "02:00:00:a1:b2:c3"),
+            "motion-detector-a1b2c3"
+        );
+        let name = access_point_name(
+            "Motion detector of the first floor corridor",
+            "02:00:00:a1:b2:c3",
+        );
+        assert_eq!(name.len(), MAX_SSID_LENGTH);
+        assert_eq!(name, "-the-first-floor-corridor-a1b2c3");
+    }
+
+    #[test]
+    fn page_escapes_the_values() {
+        let form = ProvisioningForm {
+            wifi_ssid: "\"><script>".to_owned(),
+            wifi_password: None,
+            server_url: None,
+            device_name: "Hall & co".to_owned(),
+        };
+
+        let page = form_page(&form, Some("<b>error</b>"));
+
+        assert!(page.contains("value=\"&quot;&gt;&lt;script&gt;\""));
+        assert!(page.contains("Hall &amp; co"));
+        assert!(page.contains("&lt;b&gt;error&lt;/b&gt;"));
+        assert!(!page.contains("<script>"));
+    }
+}
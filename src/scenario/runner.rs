@@ -0,0 +1,380 @@
+use super::timeline::{Expectations, Scenario, ScenarioEvent, TimelineEntry};
+use crate::{
+    config::config::{DEFAULT_CRONTAB, DEFAULT_I_AM_ALIVE_INTERVAL_SECONDS},
+    discovery::{host_discovery::StaticDiscovery, traits::DiscoveredServer},
+    dto::config_response::Configuration,
+    peripheral::{
+        host_peripheral::{HostMemoryStorage, HostMotionSensor, HostNetworkLink, HostOutput},
+        traits::NetworkLink,
+    },
+    service::{
+        alert_queue_service::AlertQueue,
+        client_service::ClientService,
+        discovery_service::DiscoveryService,
+        orchestrator_service::{Detector, LOOP_SLEEP_TIME},
+        peripheral_service::PeripheralService,
+        settings_service::SettingsService,
+        state_machine::{DetectorEvent, StateMachine},
+    },
+    stand_in::server::{
+        Behaviour, Endpoint, StandInServer, ALERT_PATH, I_AM_ALIVE_PATH, REGISTER_DEVICE_PATH,
+    },
+    transport::host_transport::HostHttpTransport,
+    util::{
+        clock_util::{Clock, VirtualClock},
+        thread_util,
+    },
+};
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+const MAC_ADDRESS: &str = "02:00:00:00:00:01";
+const SERVICE_TYPE: &str = "_elisys._tcp";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceKind {
+    Event,
+    State,
+    Alert,
+    Heartbeat,
+    Led,
+    Buzzer,
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceEntry {
+    pub at: u64,
+    pub kind: TraceKind,
+    pub detail: String,
+}
+
+pub struct ScenarioReport {
+    pub name: String,
+    pub trace: Vec<TraceEntry>,
+    // `detectedAt` of each delivered alert, in seconds from the start
+    pub alerts_detected_at: Vec<i64>,
+    pub connections: usize,
+    pub registrations: usize,
+}
+
+impl ScenarioReport {
+    // delivered alerts only, failed attempts are traced as "failed"
+    pub fn alerts(&self) -> Vec<&TraceEntry> {
+        self.delivered(TraceKind::Alert)
+    }
+
+    pub fn heartbeats(&self) -> Vec<&TraceEntry> {
+        self.delivered(TraceKind::Heartbeat)
+    }
+
+    pub fn buzzes(&self) -> usize {
+        self.trace
+            .iter()
+            .filter(|entry| entry.kind == TraceKind::Buzzer)
+            .map(|entry| entry.detail.matches("on").count())
+            .sum()
+    }
+
+    pub fn failures(&self, expect: &Expectations) -> Vec<String> {
+        let mut failures = Vec::new();
+        if let Some(alerts) = expect.alerts {
+            check_count(&mut failures, "alerts", alerts, self.alerts().len());
+        }
+        if let Some(alerts_at) = &expect.alerts_at {
+            let actual: Vec<u64> = self.alerts().iter().map(|entry| entry.at).collect();
+            if &actual != alerts_at {
+                failures.push(format!(
+                    "alerts at: expected {:?}, got {:?}",
+                    alerts_at, actual
+                ));
+            }
+        }
+        if let Some(alerts_detected_at) = &expect.alerts_detected_at {
+            if &self.alerts_detected_at != alerts_detected_at {
+                failures.push(format!(
+                    "alerts detected at: expected {:?}, got {:?}",
+                    alerts_detected_at, self.alerts_detected_at
+                ));
+            }
+        }
+        if let Some(heartbeats) = expect.heartbeats {
+            check_count(
+                &mut failures,
+                "heartbeats",
+                heartbeats,
+                self.heartbeats().len(),
+            );
+        }
+        if let Some(states) = &expect.states {
+            let actual: Vec<&str> = self
+                .trace
+                .iter()
+                .filter(|entry| entry.kind == TraceKind::State)
+                .map(|entry| entry.detail.as_str())
+                .collect();
+            if &actual != states {
+                failures.push(format!("states: expected {:?}, got {:?}", states, actual));
+            }
+        }
+        if let Some(buzzes) = expect.buzzes {
+            check_count(&mut failures, "buzzes", buzzes, self.buzzes());
+        }
+        if let Some(connections) = expect.connections {
+            check_count(&mut failures, "connections", connections, self.connections);
+        }
+        if let Some(registrations) = expect.registrations {
+            check_count(
+                &mut failures,
+                "registrations",
+                registrations,
+                self.registrations,
+            );
+        }
+        failures
+    }
+
+    fn delivered(&self, kind: TraceKind) -> Vec<&TraceEntry> {
+        self.trace
+            .iter()
+            .filter(|entry| entry.kind == kind && entry.detail == "delivered")
+            .collect()
+    }
+}
+
+fn check_count(failures: &mut Vec<String>, name: &str, expected: usize, actual: usize) {
+    if expected != actual {
+        failures.push(format!("{}: expected {}, got {}", name, expected, actual));
+    }
+}
+
+// replays the scenario timeline against the detection loop on a virtual
+// clock, sleeping between steps as the firmware loop does, and records what
+// the device did; entries are traced at the second in which they happened
+pub fn run_scenario(scenario: &Scenario) -> Result<ScenarioReport, Error> {
+    let server = StandInServer::start("127.0.0.1:0")?;
+    let configuration = build_configuration(scenario, &server)?;
+    // the server found by the discovery, the last one started
+    let discovered_server = Arc::new(Mutex::new(Some(discovered(&server))));
+    let mut servers = vec![server];
+
+    let start = DateTime::parse_from_rfc3339(&scenario.start)?.with_timezone(&Utc);
+    let clock = Arc::new(VirtualClock::new(start));
+    let motion = Arc::new(AtomicBool::new(false));
+    let reachable = Arc::new(AtomicBool::new(true));
+    let led_changes = Arc::new(Mutex::new(Vec::new()));
+    let buzzer_changes = Arc::new(Mutex::new(Vec::new()));
+
+    let mut network = HostNetworkLink::new(MAC_ADDRESS, reachable.clone());
+    network.connect()?;
+    let mut peripheral_service = PeripheralService::from_parts(
+        Box::new(HostMotionSensor::new(motion.clone())),
+        Box::new(HostOutput::new("led", led_changes.clone())),
+        Box::new(HostOutput::new("buzzer", buzzer_changes.clone())),
+        Box::new(network),
+        Box::new(HostMemoryStorage::new(Arc::new(Mutex::new(HashMap::new())))),
+        SettingsService::new(Box::new(HostMemoryStorage::new(Arc::new(Mutex::new(
+            HashMap::new(),
+        ))))),
+        clock.clone(),
+    );
+
+    // the runner starts from the detection loop, the boot sequence is skipped
+    let mut state_machine = StateMachine::new();
+    for event in [
+        DetectorEvent::BootCompleted,
+        DetectorEvent::NetworkConnected,
+        DetectorEvent::RegistrationCompleted,
+        DetectorEvent::ConfigurationLoaded,
+    ] {
+        state_machine.handle(event);
+    }
+    let transitions = Arc::new(Mutex::new(Vec::new()));
+    let listener_transitions = transitions.clone();
+    state_machine.set_listener(Box::new(move |_, _, next_state| {
+        listener_transitions.lock().unwrap().push(next_state)
+    }));
+    let alert_queue = AlertQueue::load(peripheral_service.storage(), &configuration.alert_queue);
+    let mut client_service = ClientService::new(Box::new(HostHttpTransport::new()), clock.clone());
+    client_service.set_device_secret(scenario.device_secret.as_deref());
+    servers[0].set_device_secret(scenario.device_secret.as_deref());
+    // the boot registration gives the device its token
+    client_service.register_device(&servers[0].url(REGISTER_DEVICE_PATH), MAC_ADDRESS)?;
+    servers[0].take_requests();
+    let discovery_service = DiscoveryService::new(
+        Box::new(StaticDiscovery::new(discovered_server.clone())),
+        SERVICE_TYPE,
+    );
+    let mut detector = Detector::new(
+        configuration,
+        client_service,
+        MAC_ADDRESS,
+        clock.clone(),
+        state_machine,
+        alert_queue,
+        Some(discovery_service),
+    );
+    let mut events: Vec<&TimelineEntry> = scenario.events.iter().collect();
+    events.sort_by_key(|entry| entry.at);
+    let mut events = events.into_iter().peekable();
+    let mut trace = Vec::new();
+    let mut alerts_detected_at = Vec::new();
+    let mut registrations = 0;
+
+    while clock.elapsed().as_secs() <= scenario.duration_seconds {
+        let second = clock.elapsed().as_secs();
+        while let Some(entry) = events.next_if(|entry| entry.at <= second) {
+            let server = servers.last().unwrap();
+            match &entry.event {
+                ScenarioEvent::SensorHigh => motion.store(true, Ordering::SeqCst),
+                ScenarioEvent::SensorLow => motion.store(false, Ordering::SeqCst),
+                ScenarioEvent::WifiDown => reachable.store(false, Ordering::SeqCst),
+                ScenarioEvent::WifiUp => reachable.store(true, Ordering::SeqCst),
+                ScenarioEvent::ServerDown => set_server_behaviour(server, Behaviour::Drop),
+                ScenarioEvent::ServerUp => set_server_behaviour(server, Behaviour::Ok),
+                ScenarioEvent::ServerStatus { code } => {
+                    set_server_behaviour(server, Behaviour::Status { code: *code })
+                }
+                ScenarioEvent::ServerRejects => {
+                    set_server_behaviour(server, Behaviour::Rejected { message: None })
+                }
+                ScenarioEvent::ServerChunked => set_server_behaviour(server, Behaviour::Chunked),
+                ScenarioEvent::ServerOversized { bytes } => {
+                    set_server_behaviour(server, Behaviour::Oversized { bytes: *bytes })
+                }
+                ScenarioEvent::ServerRequiresToken => server.require_tokens(),
+                ScenarioEvent::ServerMoves => {
+                    set_server_behaviour(server, Behaviour::Drop);
+                    let moved_server = StandInServer::start("127.0.0.1:0")?;
+                    moved_server.set_device_secret(scenario.device_secret.as_deref());
+                    *discovered_server.lock().unwrap() = Some(discovered(&moved_server));
+                    servers.push(moved_server);
+                }
+                ScenarioEvent::ClockJump { seconds } => clock.jump(*seconds),
+            }
+            trace.push(TraceEntry {
+                at: second,
+                kind: TraceKind::Event,
+                detail: format!("{:?}", entry.event),
+            });
+        }
+
+        detector.step(&mut peripheral_service);
+        for state in std::mem::take(&mut *transitions.lock().unwrap()) {
+            trace.push(TraceEntry {
+                at: second,
+                kind: TraceKind::State,
+                detail: format!("{:?}", state),
+            });
+        }
+
+        let requests = servers.iter().flat_map(|server| server.take_requests());
+        for request in requests.collect::<Vec<_>>() {
+            let kind = match request.endpoint {
+                Endpoint::Alert => TraceKind::Alert,
+                Endpoint::IAmAlive => TraceKind::Heartbeat,
+                Endpoint::RegisterDevice => {
+                    registrations += 1;
+                    continue;
+                }
+                _ => continue,
+            };
+            if kind == TraceKind::Alert && request.delivered {
+                alerts_detected_at.push(detected_at(&request.body, &start)?);
+            }
+            trace.push(TraceEntry {
+                at: second,
+                kind,
+                detail: if request.delivered {
+                    "delivered".to_owned()
+                } else {
+                    "failed".to_owned()
+                },
+            });
+        }
+        record_pattern(&mut trace, second, TraceKind::Led, &led_changes);
+        record_pattern(&mut trace, second, TraceKind::Buzzer, &buzzer_changes);
+
+        thread_util::sleep_time(clock.as_ref(), LOOP_SLEEP_TIME);
+    }
+
+    Ok(ScenarioReport {
+        name: scenario.name.clone(),
+        trace,
+        alerts_detected_at,
+        connections: servers.iter().map(|server| server.connections()).sum(),
+        registrations,
+    })
+}
+
+fn build_configuration(
+    scenario: &Scenario,
+    server: &StandInServer,
+) -> Result<Configuration, Error> {
+    let mut configuration = serde_json::json!({
+        "iAmAliveIntervalSeconds": DEFAULT_I_AM_ALIVE_INTERVAL_SECONDS,
+        "crontab": DEFAULT_CRONTAB,
+        "timezoneOffsetSec": 0,
+    });
+    let fields = configuration.as_object_mut().unwrap();
+    for (key, value) in scenario.configuration.iter() {
+        fields.insert(key.clone(), value.clone());
+    }
+    fields.insert("alertEndpoint".to_owned(), server.url(ALERT_PATH).into());
+    fields.insert(
+        "iAmAliveEndpoint".to_owned(),
+        server.url(I_AM_ALIVE_PATH).into(),
+    );
+    Ok(serde_json::from_value(configuration)?)
+}
+
+fn discovered(server: &StandInServer) -> DiscoveredServer {
+    DiscoveredServer {
+        host: server.address().ip().to_string(),
+        port: server.address().port(),
+        is_https: false,
+    }
+}
+
+fn detected_at(body: &str, start: &DateTime<Utc>) -> Result<i64, Error> {
+    let body: serde_json::Value = serde_json::from_str(body)?;
+    let detected_at = body["detectedAt"]
+        .as_str()
+        .ok_or(Error::msg(format!("alert without detectedAt: {}", body)))?;
+    let detected_at = DateTime::parse_from_rfc3339(detected_at)?.with_timezone(&Utc);
+    Ok((detected_at - *start).num_seconds())
+}
+
+fn set_server_behaviour(server: &StandInServer, behaviour: Behaviour) {
+    server.set_behaviour(Endpoint::Alert, behaviour.clone());
+    server.set_behaviour(Endpoint::IAmAlive, behaviour);
+}
+
+// collapses the level changes of an output during one step into a pattern
+// such as "on,off,on,off"
+fn record_pattern(
+    trace: &mut Vec<TraceEntry>,
+    second: u64,
+    kind: TraceKind,
+    changes: &Mutex<Vec<bool>>,
+) {
+    let changes = std::mem::take(&mut *changes.lock().unwrap());
+    if changes.is_empty() {
+        return;
+    }
+    let pattern: Vec<&str> = changes
+        .iter()
+        .map(|is_on| if *is_on { "on" } else { "off" })
+        .collect();
+    trace.push(TraceEntry {
+        at: second,
+        kind,
+        detail: pattern.join(","),
+    });
+}
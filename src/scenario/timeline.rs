@@ -0,0 +1,92 @@
+use serde::Deserialize;
+
+// A scenario file describes a timeline replayed against the detection loop:
+//
+// {
+//   "name": "motion at 02:00 outside the crontab window sends no alert",
+//   "start": "2023-11-20T02:00:00Z",
+//   "durationSeconds": 60,
+//   "configuration": { "crontab": "0-59 0-59 8-18 * * * *" },
+//   "events": [
+//     { "at": 10, "event": "sensorHigh" },
+//     { "at": 20, "event": "clockJump", "seconds": 3600 }
+//   ],
+//   "expect": { "alerts": 0 }
+// }
+//
+// `at` is the number of seconds since the beginning of the scenario,
+// `configuration` overrides the fields of the default `Configuration`,
+// `deviceSecret` makes the device sign its requests and the server check them.
+#[derive(Deserialize, Debug)]
+pub struct Scenario {
+    pub name: String,
+    pub start: String,
+    #[serde(rename = "durationSeconds")]
+    pub duration_seconds: u64,
+    #[serde(default)]
+    pub configuration: serde_json::Map<String, serde_json::Value>,
+    // secret shared by the device and the server, which then rejects the
+    // alerts and heartbeats that are not signed with it
+    #[serde(rename = "deviceSecret", default)]
+    pub device_secret: Option<String>,
+    #[serde(default)]
+    pub events: Vec<TimelineEntry>,
+    #[serde(default)]
+    pub expect: Expectations,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TimelineEntry {
+    pub at: u64,
+    #[serde(flatten)]
+    pub event: ScenarioEvent,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum ScenarioEvent {
+    SensorHigh,
+    SensorLow,
+    WifiDown,
+    WifiUp,
+    ServerDown,
+    ServerUp,
+    // the alert and heartbeat endpoints answer with the given status
+    ServerStatus { code: u16 },
+    // the alert and heartbeat endpoints answer `"success": false`
+    ServerRejects,
+    // the alert and heartbeat endpoints answer with a chunked body
+    ServerChunked,
+    // the alert and heartbeat endpoints answer with a body of the given size
+    ServerOversized { bytes: usize },
+    // the server revokes the device token and requires a new one
+    ServerRequiresToken,
+    // the server stops answering at its address and is found at a new one
+    // by the discovery
+    ServerMoves,
+    ClockJump { seconds: i64 },
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct Expectations {
+    pub alerts: Option<usize>,
+    #[serde(rename = "alertsAt")]
+    pub alerts_at: Option<Vec<u64>>,
+    // `detectedAt` of the delivered alerts, in seconds from the start
+    #[serde(rename = "alertsDetectedAt")]
+    pub alerts_detected_at: Option<Vec<i64>>,
+    pub heartbeats: Option<usize>,
+    pub buzzes: Option<usize>,
+    // states entered by the detection loop, in order
+    pub states: Option<Vec<String>>,
+    // connections opened by the device to the server
+    pub connections: Option<usize>,
+    // registrations sent by the detection loop
+    pub registrations: Option<usize>,
+}
+
+impl Scenario {
+    pub fn from_json(json: &str) -> anyhow::Result<Scenario> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
@@ -0,0 +1,417 @@
+use crate::{
+    dto::config_response::{AlertQueueConfiguration, OverflowPolicy},
+    peripheral::traits::{Storage, MAX_STORAGE_VALUE_LENGTH},
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+const QUEUE_KEY: &str = "alert_queue";
+// `{"alerts":[],"dropped":<u64>}`
+const MAX_ENVELOPE_LENGTH: usize = 45;
+// `{"detectedAt":"2023-11-20T10:00:00Z","deliveredTo":4294967295}` and its
+// comma
+const MAX_ALERT_LENGTH: usize = 63;
+// targets whose delivery is remembered, one bit of `deliveredTo` each
+pub const MAX_TRACKED_TARGETS: usize = 32;
+// the whole queue is stored in a single value
+pub const MAX_CAPACITY: usize = (MAX_STORAGE_VALUE_LENGTH - MAX_ENVELOPE_LENGTH) / MAX_ALERT_LENGTH;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QueuedAlert {
+    #[serde(rename = "detectedAt")]
+    pub detected_at: String,
+    // alert targets that accepted the alert, by position in the priority
+    // order: an alert kept for a target that failed is not sent again to
+    // the others
+    #[serde(rename = "deliveredTo", default, skip_serializing_if = "is_zero")]
+    pub delivered_to: u32,
+}
+
+impl QueuedAlert {
+    pub fn new(detected_at: String) -> QueuedAlert {
+        QueuedAlert {
+            detected_at,
+            delivered_to: 0,
+        }
+    }
+
+    // accepted by at least one target
+    pub fn is_delivered(&self) -> bool {
+        self.delivered_to != 0
+    }
+
+    pub fn is_delivered_to(&self, target: usize) -> bool {
+        target < MAX_TRACKED_TARGETS && self.delivered_to & (1 << target) != 0
+    }
+
+    // the targets beyond `MAX_TRACKED_TARGETS` are not remembered
+    pub fn set_delivered_to(&mut self, target: usize) {
+        if target < MAX_TRACKED_TARGETS {
+            self.delivered_to |= 1 << target;
+        }
+    }
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct PersistedQueue {
+    alerts: Vec<QueuedAlert>,
+    dropped: u64,
+}
+
+// Alerts waiting to be delivered, oldest first. A queued alert is written
+// to the storage at once, so that the alerts detected while offline survive
+// a reboot; the alerts delivered by a replay are removed with a single write
+// at its end (`flush`), to spare the flash. A reboot in between sends them
+// again.
+pub struct AlertQueue {
+    alerts: VecDeque<QueuedAlert>,
+    dropped: u64,
+    capacity: usize,
+    overflow: OverflowPolicy,
+    // changed since the last write
+    is_dirty: bool,
+}
+
+impl AlertQueue {
+    // the capacity is clamped to what fits in one storage value
+    pub fn new(configuration: &AlertQueueConfiguration) -> AlertQueue {
+        if configuration.capacity > MAX_CAPACITY {
+            warn!(
+                "[alert queue] capacity {} reduced to {}, the storage limit",
+                configuration.capacity, MAX_CAPACITY
+            );
+        }
+        AlertQueue {
+            alerts: VecDeque::new(),
+            dropped: 0,
+            capacity: configuration.capacity.clamp(1, MAX_CAPACITY),
+            overflow: configuration.overflow,
+            is_dirty: false,
+        }
+    }
+
+    // an unreadable queue is logged and replaced by an empty one; the queue
+    // is written back only when it had to be changed
+    pub fn load(storage: &mut dyn Storage, configuration: &AlertQueueConfiguration) -> AlertQueue {
+        let mut queue = AlertQueue::new(configuration);
+        let mut is_unreadable = false;
+        let persisted = match storage.get(QUEUE_KEY) {
+            Ok(Some(value)) => serde_json::from_str::<PersistedQueue>(&value).unwrap_or_else(|e| {
+                warn!("[alert queue] discarding unreadable queue: {}", e);
+                is_unreadable = true;
+                PersistedQueue::default()
+            }),
+            Ok(None) => PersistedQueue::default(),
+            Err(e) => {
+                error!("[alert queue] cannot read the queue: {}", e);
+                PersistedQueue::default()
+            }
+        };
+        queue.dropped = persisted.dropped;
+        for alert in persisted.alerts {
+            queue.insert(alert);
+        }
+        // alerts beyond a reduced capacity were dropped
+        queue.is_dirty = is_unreadable || queue.dropped != persisted.dropped;
+        if !queue.is_empty() {
+            info!(
+                "[alert queue] {} alert(s) to replay, {} dropped",
+                queue.len(),
+                queue.dropped
+            );
+        }
+        queue.flush(storage);
+        queue
+    }
+
+    // returns false when the alert itself was dropped by a full queue
+    pub fn push(&mut self, alert: QueuedAlert, storage: &mut dyn Storage) -> bool {
+        let is_queued = self.insert(alert);
+        self.flush(storage);
+        is_queued
+    }
+
+    pub fn front(&self) -> Option<&QueuedAlert> {
+        self.alerts.front()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&QueuedAlert> {
+        self.alerts.get(index)
+    }
+
+    // the changes below are kept in memory until the next `flush`
+
+    pub fn pop_front(&mut self) -> Option<QueuedAlert> {
+        self.remove(0)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<QueuedAlert> {
+        let alert = self.alerts.remove(index);
+        self.is_dirty |= alert.is_some();
+        alert
+    }
+
+    pub fn replace(&mut self, index: usize, alert: QueuedAlert) {
+        if let Some(queued_alert) = self.alerts.get_mut(index) {
+            if *queued_alert != alert {
+                *queued_alert = alert;
+                self.is_dirty = true;
+            }
+        }
+    }
+
+    // alerts not accepted by any target yet
+    pub fn undelivered(&self) -> usize {
+        self.alerts
+            .iter()
+            .filter(|alert| !alert.is_delivered())
+            .count()
+    }
+
+    // writes the queue if it changed; a failed write keeps it in memory, it
+    // is written again on the next flush
+    pub fn flush(&mut self, storage: &mut dyn Storage) {
+        if !self.is_dirty {
+            return;
+        }
+        let persisted = PersistedQueue {
+            alerts: self.alerts.iter().cloned().collect(),
+            dropped: self.dropped,
+        };
+        let result = serde_json::to_string(&persisted)
+            .map_err(anyhow::Error::from)
+            .and_then(|value| storage.set(QUEUE_KEY, &value));
+        match result {
+            Ok(()) => self.is_dirty = false,
+            Err(e) => error!("[alert queue] cannot write the queue: {}", e),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.alerts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.alerts.is_empty()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn insert(&mut self, alert: QueuedAlert) -> bool {
+        self.is_dirty = true;
+        if self.alerts.len() < self.capacity {
+            self.alerts.push_back(alert);
+            return true;
+        }
+        self.dropped += 1;
+        match self.overflow {
+            OverflowPolicy::DropOldest => {
+                let dropped_alert = self.alerts.pop_front();
+                warn!("[alert queue] full, dropped {:?}", dropped_alert);
+                self.alerts.push_back(alert);
+                true
+            }
+            OverflowPolicy::DropNewest => {
+                warn!("[alert queue] full, dropped {:?}", alert);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct MemoryStorage {
+        values: HashMap<String, String>,
+        writes: usize,
+    }
+
+    impl Storage for MemoryStorage {
+        fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+            Ok(self.values.get(key).cloned())
+        }
+
+        fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+            self.writes += 1;
+            self.values.insert(key.to_owned(), value.to_owned());
+            Ok(())
+        }
+
+        fn remove(&mut self, key: &str) -> anyhow::Result<()> {
+            self.values.remove(key);
+            Ok(())
+        }
+    }
+
+    fn configuration(capacity: usize, overflow: OverflowPolicy) -> AlertQueueConfiguration {
+        AlertQueueConfiguration { capacity, overflow }
+    }
+
+    fn alert(second: u32) -> QueuedAlert {
+        QueuedAlert::new(format!("2023-11-20T10:00:{:02}Z", second))
+    }
+
+    fn detected_at(queue: &AlertQueue) -> Vec<String> {
+        queue
+            .alerts
+            .iter()
+            .map(|alert| alert.detected_at.clone())
+            .collect()
+    }
+
+    #[test]
+    fn replays_in_detection_order() {
+        let mut storage = MemoryStorage::default();
+        let mut queue = AlertQueue::new(&configuration(4, OverflowPolicy::DropOldest));
+        queue.push(alert(1), &mut storage);
+        queue.push(alert(2), &mut storage);
+        assert_eq!(queue.front(), Some(&alert(1)));
+        assert_eq!(queue.pop_front(), Some(alert(1)));
+        assert_eq!(queue.pop_front(), Some(alert(2)));
+        assert_eq!(queue.pop_front(), None);
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_latest_alerts() {
+        let mut storage = MemoryStorage::default();
+        let mut queue = AlertQueue::new(&configuration(2, OverflowPolicy::DropOldest));
+        for second in 1..=3 {
+            assert!(queue.push(alert(second), &mut storage));
+        }
+        assert_eq!(
+            detected_at(&queue),
+            [alert(2).detected_at, alert(3).detected_at]
+        );
+        assert_eq!(queue.dropped(), 1);
+    }
+
+    #[test]
+    fn drop_newest_keeps_the_first_alerts() {
+        let mut storage = MemoryStorage::default();
+        let mut queue = AlertQueue::new(&configuration(2, OverflowPolicy::DropNewest));
+        assert!(queue.push(alert(1), &mut storage));
+        assert!(queue.push(alert(2), &mut storage));
+        assert!(!queue.push(alert(3), &mut storage));
+        assert_eq!(
+            detected_at(&queue),
+            [alert(1).detected_at, alert(2).detected_at]
+        );
+        assert_eq!(queue.dropped(), 1);
+    }
+
+    #[test]
+    fn survives_a_reboot() {
+        let mut storage = MemoryStorage::default();
+        let configuration = configuration(4, OverflowPolicy::DropOldest);
+        let mut queue = AlertQueue::new(&configuration);
+        queue.push(alert(1), &mut storage);
+        queue.push(alert(2), &mut storage);
+
+        let queue = AlertQueue::load(&mut storage, &configuration);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.front(), Some(&alert(1)));
+    }
+
+    #[test]
+    fn reload_applies_a_smaller_capacity() {
+        let mut storage = MemoryStorage::default();
+        let mut queue = AlertQueue::new(&configuration(4, OverflowPolicy::DropOldest));
+        for second in 1..=4 {
+            queue.push(alert(second), &mut storage);
+        }
+
+        let queue = AlertQueue::load(&mut storage, &configuration(2, OverflowPolicy::DropOldest));
+        assert_eq!(
+            detected_at(&queue),
+            [alert(3).detected_at, alert(4).detected_at]
+        );
+        assert_eq!(queue.dropped(), 2);
+    }
+
+    #[test]
+    fn replayed_alerts_are_removed_with_one_write() {
+        let mut storage = MemoryStorage::default();
+        let configuration = configuration(4, OverflowPolicy::DropOldest);
+        let mut queue = AlertQueue::new(&configuration);
+        for second in 1..=3 {
+            queue.push(alert(second), &mut storage);
+        }
+        assert_eq!(storage.writes, 3);
+
+        queue.pop_front();
+        queue.pop_front();
+        assert_eq!(storage.writes, 3);
+        queue.flush(&mut storage);
+        queue.flush(&mut storage);
+        assert_eq!(storage.writes, 4);
+        let queue = AlertQueue::load(&mut storage, &configuration);
+        assert_eq!(detected_at(&queue), [alert(3).detected_at]);
+    }
+
+    #[test]
+    fn load_writes_only_a_changed_queue() {
+        let mut storage = MemoryStorage::default();
+        let mut queue = AlertQueue::new(&configuration(4, OverflowPolicy::DropOldest));
+        queue.push(alert(1), &mut storage);
+        queue.push(alert(2), &mut storage);
+
+        AlertQueue::load(&mut storage, &configuration(4, OverflowPolicy::DropOldest));
+        assert_eq!(storage.writes, 2);
+        AlertQueue::load(&mut storage, &configuration(1, OverflowPolicy::DropOldest));
+        assert_eq!(storage.writes, 3);
+    }
+
+    #[test]
+    fn capacity_is_clamped_to_the_storage_limit() {
+        let mut storage = MemoryStorage::default();
+        let mut queue = AlertQueue::new(&configuration(10000, OverflowPolicy::DropOldest));
+        assert_eq!(queue.capacity, MAX_CAPACITY);
+        let mut delivered_alert = alert(59);
+        delivered_alert.delivered_to = u32::MAX;
+        for _ in 0..MAX_CAPACITY + 1 {
+            queue.push(delivered_alert.clone(), &mut storage);
+        }
+        queue.dropped = u64::MAX - 1;
+        queue.push(delivered_alert, &mut storage);
+        assert_eq!(queue.len(), MAX_CAPACITY);
+        assert!(storage.values[QUEUE_KEY].len() <= MAX_STORAGE_VALUE_LENGTH);
+    }
+
+    #[test]
+    fn unreadable_queue_is_discarded() {
+        let mut storage = MemoryStorage::default();
+        storage.set(QUEUE_KEY, "{\"alerts\":").unwrap();
+        let queue = AlertQueue::load(&mut storage, &configuration(4, OverflowPolicy::DropOldest));
+        assert!(queue.is_empty());
+        assert_eq!(queue.dropped(), 0);
+    }
+
+    #[test]
+    fn delivery_state_survives_a_reboot() {
+        let mut storage = MemoryStorage::default();
+        let configuration = configuration(4, OverflowPolicy::DropOldest);
+        let mut queue = AlertQueue::new(&configuration);
+        queue.push(alert(1), &mut storage);
+        let mut partially_delivered = alert(1);
+        partially_delivered.set_delivered_to(2);
+        queue.replace(0, partially_delivered);
+        queue.flush(&mut storage);
+
+        let queue = AlertQueue::load(&mut storage, &configuration);
+        let queued_alert = queue.front().unwrap();
+        assert!(queued_alert.is_delivered_to(2));
+        assert!(!queued_alert.is_delivered_to(0));
+        assert_eq!(queue.undelivered(), 0);
+    }
+}
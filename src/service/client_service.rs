@@ -1,198 +1,904 @@
 use crate::{
     config::config::{
         DEFAULT_ALERT_URL, DEFAULT_I_AM_ALIVE_INTERVAL_SECONDS, DEFAULT_I_AM_ALIVE_URL,
-        DEVICE_DESCRIPTION, DEVICE_NAME, DEVICE_TYPE, REGISTER_DEVICE_URL,
+        DEVICE_DESCRIPTION, DEVICE_NAME, DEVICE_SECRET, DEVICE_TYPE, REGISTER_DEVICE_URL,
     },
     dto::{
-        config_request::ConfigRequest, config_response::Configuration,
-        register_device::RegisterDeviceDTO, request_alert::RequestAlert,
+        acknowledgement::Acknowledgement,
+        alert_delivery::{AlertDelivery, DeliveryResult, TargetDelivery},
+        config_request::ConfigRequest,
+        config_response::{
+            default_message_template, AlertQueueConfiguration, AlertTargetService, Configuration,
+            DeliveryMode, RetryPolicies, TlsConfiguration,
+        },
+        register_device::RegisterDeviceDTO,
+        request_alert::RequestAlert,
         request_i_am_alive::RequestIAmAlive,
+        response_acknowledgement::ResponseAcknowledgement,
+        response_register_device::ResponseRegisterDevice,
+    },
+    notifier::traits::{self as notifier, Notifier},
+    service::{alert_queue_service::QueuedAlert, discovery_service::ServerEndpoints},
+    transport::traits::{RequestKind, Transport, TransportRequest},
+    util::{
+        clock_util::Clock,
+        response_util::{read_error_body, LimitedReader, MAX_RESPONSE_SIZE},
+        retry_util::{
+            self, RetryPolicy, Retryable, CONFIGURATION_RETRY_POLICY, REGISTRATION_RETRY_POLICY,
+        },
+        signature_util::{RequestSignature, RequestSigner},
+        tls_util::{TlsSettings, TlsVerificationError},
+        url_util::{origin, with_origin},
     },
 };
-use anyhow::{Error, Ok};
-use embedded_svc::{http::client::Client as HttpClient, io::Write, utils::io};
-use esp_idf_svc::http::client::EspHttpConnection;
+use anyhow::Error;
+#[cfg(feature = "hal")]
 use esp_idf_sys as _;
-use log::{error, info};
+use log::{error, info, warn};
+use serde::de::DeserializeOwned;
+use std::fmt::{self, Display, Formatter};
+use std::io::Read;
 use std::result::Result::Ok as StandardOk;
+use std::sync::Arc;
+use std::time::Duration;
+
+// name of the server in the logs and the heartbeat
+const SERVER_TARGET_NAME: &str = "server";
+// alert deliveries kept for the next heartbeat
+const MAX_REPORTED_DELIVERIES: usize = 8;
+
+// Failure of a request to the server. The variants let the caller tell a
+// request that may succeed later from one the server will never accept.
+#[derive(Debug)]
+pub enum ClientError {
+    // no response: connection, write or read failure
+    Transport(Error),
+    // the server certificate or its public key is not trusted
+    Tls(TlsVerificationError),
+    // response with a non-2xx status
+    Status { status: u16, body: String },
+    // 2xx response that is not the expected JSON
+    InvalidResponse { status: u16, error: String },
+    // 2xx response with `"success": false`
+    Rejected { message: Option<String> },
+    // body longer than the accepted size
+    ResponseTooLarge { limit: usize },
+    // the notification service does not accept the credentials of the
+    // target (401 or 403): they are part of its configuration
+    CredentialsRejected { status: u16, body: String },
+    // the alert has nowhere to go
+    NoAlertTarget,
+    // not sent, the blocking budget of the alerts is spent
+    DeadlineExceeded,
+}
+
+impl ClientError {
+    // the server refused the request itself: sending it again is useless
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            ClientError::Status { status, .. } => {
+                (400..500).contains(status) && *status != 401 && *status != 408 && *status != 429
+            }
+            ClientError::Rejected { .. }
+            | ClientError::ResponseTooLarge { .. }
+            | ClientError::CredentialsRejected { .. }
+            | ClientError::NoAlertTarget => true,
+            _ => false,
+        }
+    }
+}
+
+impl ClientError {
+    // the server does not accept the device token: the request can succeed
+    // once the device registered again
+    pub fn is_token_rejected(&self) -> bool {
+        matches!(self, ClientError::Status { status: 401, .. })
+    }
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Transport(e) => write!(f, "connection error: {}", e),
+            ClientError::Tls(e) => write!(f, "{}", e),
+            ClientError::Status { status, body } => {
+                write!(f, "server answered with status {}: {}", status, body)
+            }
+            ClientError::InvalidResponse { status, error } => {
+                write!(f, "invalid response (status {}): {}", status, error)
+            }
+            ClientError::Rejected { message } => write!(
+                f,
+                "request rejected by the server: {}",
+                message.as_deref().unwrap_or("no message")
+            ),
+            ClientError::ResponseTooLarge { limit } => {
+                write!(f, "response body larger than {} bytes", limit)
+            }
+            ClientError::CredentialsRejected { status, body } => write!(
+                f,
+                "notification service refused the credentials (status {}): {}",
+                status, body
+            ),
+            ClientError::NoAlertTarget => write!(f, "no alert target configured"),
+            ClientError::DeadlineExceeded => write!(f, "no time left to send the request"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl Retryable for ClientError {
+    fn is_retryable(&self) -> bool {
+        !self.is_permanent() && !self.is_token_rejected()
+    }
+}
+
 pub struct ClientService {
-    alert_url: String,
+    transport: Box<dyn Transport>,
+    clock: Arc<dyn Clock>,
+    alert_targets: Vec<AlertTarget>,
+    message_template: String,
+    // results of the alerts sent since the last heartbeat
+    alert_deliveries: Vec<AlertDelivery>,
     i_am_alive_url: String,
+    i_am_alive_tls: TlsSettings,
+    retry_policies: RetryPolicies,
+    // received with the registration, sent with every other server request
+    device_token: Option<String>,
+    // set when the server answered 401, until the next registration
+    is_token_rejected: bool,
+    // endpoint of the last registration, used to register again
+    register_device_uri: Option<String>,
+    // signs the alerts and heartbeats sent to the server, when a device
+    // secret is provisioned
+    signer: Option<RequestSigner>,
+    // consecutive requests to the server that got no response
+    connection_failures: u32,
+    device_name: String,
 }
 
 impl ClientService {
-    pub fn new(alert_url: &str, i_am_alive_url: &str) -> ClientService {
+    // the alert and IAmAlive endpoints are those of config.rs until
+    // `configure` is called with the downloaded configuration
+    pub fn new(transport: Box<dyn Transport>, clock: Arc<dyn Clock>) -> ClientService {
         ClientService {
-            alert_url: alert_url.to_owned(),
-            i_am_alive_url: i_am_alive_url.to_owned(),
+            transport,
+            clock,
+            alert_targets: vec![AlertTarget {
+                name: SERVER_TARGET_NAME.to_owned(),
+                priority: 0,
+                mode: DeliveryMode::FanOut,
+                endpoint: AlertEndpoint::Server(DEFAULT_ALERT_URL.to_owned()),
+                tls: TlsSettings::server(),
+            }],
+            message_template: default_message_template(),
+            alert_deliveries: Vec::new(),
+            i_am_alive_url: DEFAULT_I_AM_ALIVE_URL.to_owned(),
+            i_am_alive_tls: TlsSettings::server(),
+            retry_policies: RetryPolicies::default(),
+            device_token: None,
+            is_token_rejected: false,
+            register_device_uri: None,
+            signer: DEVICE_SECRET.map(|secret| RequestSigner::new(secret.as_bytes())),
+            connection_failures: 0,
+            device_name: DEVICE_NAME.to_owned(),
         }
     }
 
-    pub fn send_alert(&self, mac_address: &str) -> anyhow::Result<(), anyhow::Error> {
-        let client = HttpClient::wrap(EspHttpConnection::new(&Default::default())?);
+    // replaces the name of config.rs in the registration and the notifications
+    pub fn set_device_name(&mut self, device_name: &str) {
+        self.device_name = device_name.to_owned();
+    }
 
-        let payload = serde_json::to_string(&RequestAlert::new(mac_address.to_owned())).unwrap();
-        let payload = payload.as_bytes();
+    // replaces the secret of config.rs
+    pub fn set_device_secret(&mut self, device_secret: Option<&str>) {
+        self.signer = device_secret.map(|secret| RequestSigner::new(secret.as_bytes()));
+    }
 
-        info!("trying to send alert notification...");
-        let result = post_request(payload, client, &self.alert_url);
-        info!("notification sent? {}", !result.is_err());
-        return match result {
-            Err(e) => Err(e.into()),
-            StandardOk(_) => Ok(()),
+    pub fn device_token(&self) -> Option<&str> {
+        self.device_token.as_deref()
+    }
+
+    // token stored by a previous registration
+    pub fn set_device_token(&mut self, device_token: Option<String>) {
+        self.device_token = device_token;
+    }
+
+    pub fn is_token_rejected(&self) -> bool {
+        self.is_token_rejected
+    }
+
+    pub fn connection_failures(&self) -> u32 {
+        self.connection_failures
+    }
+
+    // the server moved to `server_origin` (e.g. `http://192.168.1.40:8080`):
+    // the endpoints on its previous origin, the one of the registration, are
+    // moved with it
+    pub fn relocate_server(&mut self, server_origin: &str) {
+        let previous_origin = match &self.register_device_uri {
+            Some(register_device_uri) => origin(register_device_uri).to_owned(),
+            None => return,
+        };
+        if previous_origin == server_origin {
+            return;
+        }
+        info!("server moved from {} to {}", previous_origin, server_origin);
+        let relocate = |url: &mut String| {
+            if origin(url) == previous_origin {
+                *url = with_origin(url, server_origin);
+            }
         };
+        if let Some(register_device_uri) = self.register_device_uri.as_mut() {
+            relocate(register_device_uri);
+        }
+        relocate(&mut self.i_am_alive_url);
+        for target in self.alert_targets.iter_mut() {
+            if let AlertEndpoint::Server(url) = &mut target.endpoint {
+                relocate(url);
+            }
+        }
+        self.connection_failures = 0;
+    }
+
+    pub fn configure(&mut self, configuration: &Configuration) {
+        self.alert_targets = alert_targets(configuration);
+        info!(
+            "alert targets: {:?}",
+            self.alert_targets
+                .iter()
+                .map(|target| (&target.name, target.priority, target.mode))
+                .collect::<Vec<_>>()
+        );
+        self.message_template = configuration.message_template.clone();
+        self.i_am_alive_url = configuration.i_am_alive_endpoint.clone();
+        self.i_am_alive_tls =
+            TlsSettings::from_configuration(configuration.i_am_alive_tls.as_ref());
+        self.retry_policies = configuration.retry_policies;
+    }
+
+    // at boot; the token of the response replaces the current one
+    pub fn register_device(
+        &mut self,
+        register_device_uri: &str,
+        mac_address: &str,
+    ) -> Result<ResponseRegisterDevice, ClientError> {
+        self.register(register_device_uri, mac_address, REGISTRATION_RETRY_POLICY)
+    }
+
+    // from the detection loop, after the token was rejected: retried as an
+    // alert, so that the sensor is not left unsampled for long
+    pub fn register_device_again(
+        &mut self,
+        mac_address: &str,
+    ) -> Result<ResponseRegisterDevice, ClientError> {
+        info!("the device token was rejected, registering again...");
+        let register_device_uri = self
+            .register_device_uri
+            .clone()
+            .unwrap_or(REGISTER_DEVICE_URL.to_owned());
+        let policy = self.retry_policies.alert;
+        self.register(&register_device_uri, mac_address, policy)
+    }
+
+    fn register(
+        &mut self,
+        register_device_uri: &str,
+        mac_address: &str,
+        policy: RetryPolicy,
+    ) -> Result<ResponseRegisterDevice, ClientError> {
+        self.register_device_uri = Some(register_device_uri.to_owned());
+        let payload = serde_json::to_string(&RegisterDeviceDTO::new(
+            mac_address.to_owned(),
+            DEVICE_TYPE.into(),
+            self.device_name.clone(),
+            DEVICE_DESCRIPTION.into(),
+        ))
+        .unwrap();
+        let payload = payload.as_bytes();
+        let tls = TlsSettings::server();
+
+        info!("trying to send data...");
+        let result = retry_util::retry(self.clock.as_ref(), &policy, "registration", || {
+            post_acknowledged::<ResponseRegisterDevice>(
+                self.transport.as_mut(),
+                &json_request(
+                    RequestKind::RegisterDevice,
+                    register_device_uri,
+                    None,
+                    payload,
+                    &tls,
+                ),
+            )
+        });
+        info!("data sent? {}", result.is_ok());
+        count_connection_failures(&mut self.connection_failures, &result);
+        if let StandardOk(response) = &result {
+            if response.token.is_none() {
+                warn!("no device token in the registration response");
+            }
+            self.device_token = response.token.clone();
+            self.is_token_rejected = false;
+        }
+        result
+    }
+
+    pub fn get_configuration(
+        &mut self,
+        configuration_uri: &str,
+        mac_address: &str,
+    ) -> Result<Configuration, ClientError> {
+        let payload = serde_json::to_string(&ConfigRequest::new(mac_address.to_owned())).unwrap();
+        let payload = payload.as_bytes();
+        let tls = TlsSettings::server();
+        let authorization = self.authorization();
+
+        info!("[config downloader]: trying to get remote configuration...");
+        let result = retry_util::retry(
+            self.clock.as_ref(),
+            &CONFIGURATION_RETRY_POLICY,
+            "config downloader",
+            || {
+                post_json::<Configuration>(
+                    self.transport.as_mut(),
+                    &json_request(
+                        RequestKind::Configuration,
+                        configuration_uri,
+                        authorization.as_deref(),
+                        payload,
+                        &tls,
+                    ),
+                )
+            },
+        );
+        info!(
+            "[config downloader]: configuration retrieved with success? {}",
+            result.is_ok()
+        );
+        count_connection_failures(&mut self.connection_failures, &result);
+
+        match result {
+            StandardOk(configuration) => {
+                info!(
+                    "[config downloader]: Remote configuration loaded successfully: {:?}",
+                    configuration
+                );
+                StandardOk(configuration)
+            }
+            Err(e) => {
+                error!("[config downloader]: {}", e);
+                self.is_token_rejected |= e.is_token_rejected();
+                Err(e)
+            }
+        }
+    }
+
+    // end of the blocking budget of the alerts sent from now on: the targets
+    // of an alert and the alerts of a replay share it
+    pub fn alert_deadline(&self) -> Duration {
+        self.clock.elapsed() + Duration::from_millis(self.retry_policies.alert.max_blocking_millis)
+    }
+
+    // The targets are used in priority order: the fan-out targets receive
+    // every alert, a failover target only when the failover targets before it
+    // failed. The targets that accept the alert are recorded in it, so that
+    // an alert sent again only goes to the targets still missing. Returns Ok
+    // once every target that needs the alert got it; otherwise a retryable
+    // error if any, so that it stays queued. No target is tried past
+    // `deadline`.
+    pub fn send_alert(
+        &mut self,
+        mac_address: &str,
+        alert: &mut QueuedAlert,
+        deadline: Duration,
+    ) -> Result<(), ClientError> {
+        if self.alert_targets.is_empty() {
+            return Err(ClientError::NoAlertTarget);
+        }
+        let payload = serde_json::to_string(&RequestAlert::new(
+            mac_address.to_owned(),
+            alert.detected_at.clone(),
+        ))
+        .unwrap();
+        let message = notifier::render_message(
+            &self.message_template,
+            &self.device_name,
+            mac_address,
+            &alert.detected_at,
+        );
+        let authorization = self.authorization();
+
+        let mut targets = Vec::new();
+        let mut errors = Vec::new();
+        let mut failover_errors = Vec::new();
+        let mut is_failover_delivered = false;
+        for (index, target) in self.alert_targets.iter().enumerate() {
+            let is_failover = target.mode == DeliveryMode::Failover;
+            if alert.is_delivered_to(index) {
+                is_failover_delivered |= is_failover;
+                continue;
+            }
+            if is_failover && is_failover_delivered {
+                info!("[alert] {}: skipped", target.name);
+                targets.push(TargetDelivery {
+                    name: target.name.clone(),
+                    result: DeliveryResult::Skipped,
+                    error: None,
+                });
+                continue;
+            }
+            if self.clock.elapsed() >= deadline {
+                warn!("[alert] {}: {}", target.name, ClientError::DeadlineExceeded);
+                errors.push(ClientError::DeadlineExceeded);
+                continue;
+            }
+            info!("[alert] trying to send the alert to {}...", target.name);
+            let result = retry_util::retry_until(
+                self.clock.as_ref(),
+                &self.retry_policies.alert,
+                &target.name,
+                deadline,
+                || {
+                    // every attempt is signed with a new nonce
+                    let signature = match target.endpoint {
+                        AlertEndpoint::Server(_) => {
+                            sign(&mut self.signer, self.clock.as_ref(), payload.as_bytes())
+                        }
+                        AlertEndpoint::Notifier(_) => None,
+                    };
+                    send_to_target(
+                        self.transport.as_mut(),
+                        target,
+                        authorization.as_deref(),
+                        signature.as_ref(),
+                        &payload,
+                        &self.device_name,
+                        &message,
+                    )
+                },
+            );
+            if let AlertEndpoint::Server(_) = target.endpoint {
+                count_connection_failures(&mut self.connection_failures, &result);
+            }
+            match result {
+                StandardOk(()) => {
+                    info!("[alert] {}: delivered", target.name);
+                    is_failover_delivered |= is_failover;
+                    alert.set_delivered_to(index);
+                    targets.push(TargetDelivery {
+                        name: target.name.clone(),
+                        result: DeliveryResult::Delivered,
+                        error: None,
+                    });
+                }
+                Err(e) => {
+                    warn!("[alert] {}: failed: {}", target.name, e);
+                    // the device token is only sent to the server
+                    if let AlertEndpoint::Server(_) = target.endpoint {
+                        self.is_token_rejected |= e.is_token_rejected();
+                    }
+                    targets.push(TargetDelivery {
+                        name: target.name.clone(),
+                        result: DeliveryResult::Failed,
+                        error: Some(e.to_string()),
+                    });
+                    if is_failover {
+                        failover_errors.push(e);
+                    } else {
+                        errors.push(e);
+                    }
+                }
+            }
+        }
+
+        if !targets.is_empty() {
+            if self.alert_deliveries.len() >= MAX_REPORTED_DELIVERIES {
+                self.alert_deliveries.remove(0);
+            }
+            self.alert_deliveries.push(AlertDelivery {
+                detected_at: alert.detected_at.clone(),
+                targets,
+            });
+        }
+        // the failures of the failover targets do not matter once one of
+        // them accepted the alert
+        if !is_failover_delivered {
+            errors.append(&mut failover_errors);
+        }
+        // the targets that refused the alert will not accept it later
+        let index = errors.iter().position(|e| e.is_retryable());
+        info!(
+            "notification sent? {} (to every target? {})",
+            alert.is_delivered(),
+            index.is_none()
+        );
+        match index {
+            Some(index) => Err(errors.swap_remove(index)),
+            None if alert.is_delivered() || errors.is_empty() => StandardOk(()),
+            None => Err(errors.swap_remove(0)),
+        }
     }
 
-    pub fn send_i_am_alive(&self, mac_address: &str) -> anyhow::Result<(), anyhow::Error> {
-        let client = HttpClient::wrap(EspHttpConnection::new(&Default::default())?);
-        let payload = serde_json::to_string(&RequestIAmAlive::new(mac_address.to_owned())).unwrap();
+    // the heartbeat reports the alert deliveries since the previous one
+    pub fn send_i_am_alive(
+        &mut self,
+        request: RequestIAmAlive,
+    ) -> Result<ResponseAcknowledgement, ClientError> {
+        let request = request.with_alert_deliveries(self.alert_deliveries.clone());
+        let payload = serde_json::to_string(&request).unwrap();
         let payload = payload.as_bytes();
+        let authorization = self.authorization();
 
         info!("trying to send is alive ack...");
-        let result = post_request(payload, client, &self.i_am_alive_url);
-        info!("ack sent? {}", !result.is_err());
-        return match result {
-            Err(e) => Err(e.into()),
-            StandardOk(_) => Ok(()),
+        let result = retry_util::retry(
+            self.clock.as_ref(),
+            &self.retry_policies.i_am_alive,
+            "is alive",
+            || {
+                let signature = sign(&mut self.signer, self.clock.as_ref(), payload);
+                post_delivered(
+                    self.transport.as_mut(),
+                    &TransportRequest {
+                        signature: signature.as_ref(),
+                        ..json_request(
+                            RequestKind::IAmAlive,
+                            &self.i_am_alive_url,
+                            authorization.as_deref(),
+                            payload,
+                            &self.i_am_alive_tls,
+                        )
+                    },
+                )
+            },
+        );
+        info!("ack sent? {}", result.is_ok());
+        count_connection_failures(&mut self.connection_failures, &result);
+        match &result {
+            StandardOk(_) => self.alert_deliveries.clear(),
+            Err(e) => self.is_token_rejected |= e.is_token_rejected(),
+        }
+        result
+    }
+
+    fn authorization(&self) -> Option<String> {
+        self.device_token
+            .as_ref()
+            .map(|device_token| format!("Bearer {}", device_token))
+    }
+
+    // sample of the motion sensor, for the transports that publish it
+    pub fn report_motion(&mut self, is_motion_detected: bool) {
+        if let Err(e) = self.transport.report_motion(is_motion_detected) {
+            warn!("failed to report the motion state: {}", e);
+        }
+    }
+}
+
+// alert target resolved from the configuration
+struct AlertTarget {
+    name: String,
+    priority: u32,
+    mode: DeliveryMode,
+    endpoint: AlertEndpoint,
+    tls: TlsSettings,
+}
+
+enum AlertEndpoint {
+    // alert endpoint of the server, acknowledging the `RequestAlert`
+    Server(String),
+    Notifier(Box<dyn Notifier>),
+}
+
+// `alertTargets`, or the `alertEndpoint` and the `notifiers` as fan-out
+// targets when there is none; sorted by priority
+fn alert_targets(configuration: &Configuration) -> Vec<AlertTarget> {
+    let mut targets: Vec<AlertTarget> = Vec::new();
+    if configuration.alert_targets.is_empty() {
+        if let Some(alert_endpoint) = &configuration.alert_endpoint {
+            targets.push(AlertTarget {
+                name: SERVER_TARGET_NAME.to_owned(),
+                priority: 0,
+                mode: DeliveryMode::FanOut,
+                endpoint: AlertEndpoint::Server(alert_endpoint.clone()),
+                tls: TlsSettings::from_configuration(configuration.alert_tls.as_ref()),
+            });
+        }
+        for notifier in configuration.notifiers.iter() {
+            targets.push(AlertTarget {
+                name: notifier.service.name().to_owned(),
+                priority: 0,
+                mode: DeliveryMode::FanOut,
+                endpoint: AlertEndpoint::Notifier(notifier::from_configuration(&notifier.service)),
+                tls: notifier_tls(notifier.tls.as_ref()),
+            });
+        }
+        return targets;
+    }
+
+    for target in configuration.alert_targets.iter() {
+        let (name, endpoint, tls) = match &target.service {
+            AlertTargetService::Server { url } => (
+                SERVER_TARGET_NAME,
+                AlertEndpoint::Server(url.clone()),
+                TlsSettings::from_configuration(target.tls.as_ref()),
+            ),
+            AlertTargetService::Notifier(service) => (
+                service.name(),
+                AlertEndpoint::Notifier(notifier::from_configuration(service)),
+                notifier_tls(target.tls.as_ref()),
+            ),
         };
+        targets.push(AlertTarget {
+            name: target.name.clone().unwrap_or(name.to_owned()),
+            priority: target.priority,
+            mode: target.mode,
+            endpoint,
+            tls,
+        });
     }
+    targets.sort_by_key(|target| target.priority);
+    targets
 }
 
-pub fn get_configuration(
-    configuration_uri: &str,
-    mac_address: &str,
-) -> anyhow::Result<Configuration, anyhow::Error> {
-    let client = HttpClient::wrap(EspHttpConnection::new(&Default::default())?);
-    let payload = serde_json::to_string(&ConfigRequest::new(mac_address.to_owned())).unwrap();
-    let payload = payload.as_bytes();
+// the notification services are reached with the certificate bundle unless
+// configured otherwise
+fn notifier_tls(tls: Option<&TlsConfiguration>) -> TlsSettings {
+    match tls {
+        Some(tls) => TlsSettings::from_configuration(Some(tls)),
+        None => TlsSettings::default(),
+    }
+}
 
-    info!("[config downloader]: trying to get remote configuration...");
-    let result = post_request(payload, client, configuration_uri);
-    info!(
-        "[config downloader]: configuration retrieved with success? {}",
-        !result.is_err()
-    );
+// one attempt to deliver the alert to `target`: `payload` is the
+// `RequestAlert` of a server, `title` and `message` the text of a
+// notification
+fn send_to_target(
+    transport: &mut dyn Transport,
+    target: &AlertTarget,
+    authorization: Option<&str>,
+    signature: Option<&RequestSignature>,
+    payload: &str,
+    title: &str,
+    message: &str,
+) -> Result<(), ClientError> {
+    match &target.endpoint {
+        AlertEndpoint::Server(url) => post_delivered(
+            transport,
+            &TransportRequest {
+                signature,
+                ..json_request(
+                    RequestKind::Alert,
+                    url,
+                    authorization,
+                    payload.as_bytes(),
+                    &target.tls,
+                )
+            },
+        )
+        .map(|_| ()),
+        AlertEndpoint::Notifier(notifier) => {
+            let request = notifier.request(title, message);
+            post_request(
+                transport,
+                &TransportRequest {
+                    kind: RequestKind::Notification,
+                    url: &request.url,
+                    content_type: "application/json",
+                    authorization: request.authorization.as_deref(),
+                    signature: None,
+                    payload: request.payload.as_bytes(),
+                    tls: &target.tls,
+                },
+                read_status,
+            )
+            .map_err(|e| match e {
+                ClientError::Status {
+                    status: status @ (401 | 403),
+                    body,
+                } => ClientError::CredentialsRejected { status, body },
+                e => e,
+            })
+        }
+    }
+}
 
+fn count_connection_failures<T>(failures: &mut u32, result: &Result<T, ClientError>) {
     match result {
-        StandardOk(body_string) => {
-            let configuration: Result<Configuration, serde_json::Error> =
-                serde_json::from_str(&body_string);
-            info!("{:?}", configuration);
-
-            if configuration.is_err() {
-                let err = configuration.err().unwrap();
-                error!(
-            "[config downloader]: error while trying to parse the configuration response: {}",
-            &err
-        );
-                return Err(err.into());
-            }
+        Err(ClientError::Transport(_)) => *failures += 1,
+        _ => *failures = 0,
+    }
+}
+
+fn sign(
+    signer: &mut Option<RequestSigner>,
+    clock: &dyn Clock,
+    payload: &[u8],
+) -> Option<RequestSignature> {
+    signer
+        .as_mut()
+        .map(|signer| signer.sign(clock.now().timestamp_millis(), payload))
+}
+
+fn json_request<'a>(
+    kind: RequestKind,
+    url: &'a str,
+    authorization: Option<&'a str>,
+    payload: &'a [u8],
+    tls: &'a TlsSettings,
+) -> TransportRequest<'a> {
+    TransportRequest {
+        kind,
+        url,
+        content_type: "application/json",
+        authorization,
+        signature: None,
+        payload,
+        tls,
+    }
+}
+
+// the response must have a 2xx status and a body of type T, deserialized
+// while it is read
+fn post_json<T: DeserializeOwned>(
+    transport: &mut dyn Transport,
+    request: &TransportRequest,
+) -> Result<T, ClientError> {
+    post_request(transport, request, read_json)
+}
+
+fn read_json<T: DeserializeOwned>(
+    status: u16,
+    content_length: Option<u64>,
+    body: &mut dyn Read,
+) -> Result<T, ClientError> {
+    // the error bodies are truncated anyway
+    if !(200..300).contains(&status) {
+        return Err(ClientError::Status {
+            status,
+            body: read_error_body(body),
+        });
+    }
+    if content_length.is_some_and(|length| length > MAX_RESPONSE_SIZE as u64) {
+        return Err(ClientError::ResponseTooLarge {
+            limit: MAX_RESPONSE_SIZE,
+        });
+    }
+    let mut body = LimitedReader::new(body, MAX_RESPONSE_SIZE);
+    match serde_json::from_reader(&mut body) {
+        StandardOk(value) => StandardOk(value),
+        Err(_) if body.is_exceeded() => Err(ClientError::ResponseTooLarge {
+            limit: body.limit(),
+        }),
+        Err(e) if e.is_io() => Err(ClientError::Transport(e.into())),
+        Err(e) => Err(ClientError::InvalidResponse {
+            status,
+            error: e.to_string(),
+        }),
+    }
+}
+
+// services answering with a 2xx status and a body of their own
+fn read_status(
+    status: u16,
+    _content_length: Option<u64>,
+    body: &mut dyn Read,
+) -> Result<(), ClientError> {
+    if !(200..300).contains(&status) {
+        return Err(ClientError::Status {
+            status,
+            body: read_error_body(body),
+        });
+    }
+    StandardOk(())
+}
+
+// as `post_json`, and the server must acknowledge the request
+fn post_acknowledged<T: DeserializeOwned + Acknowledgement>(
+    transport: &mut dyn Transport,
+    request: &TransportRequest,
+) -> Result<T, ClientError> {
+    let response = post_json::<T>(transport, request)?;
+    if !response.is_success() {
+        return Err(ClientError::Rejected {
+            message: response.message().map(|message| message.to_owned()),
+        });
+    }
+    StandardOk(response)
+}
 
-            let configuration = configuration.unwrap();
-            info!(
-                "[config downloader]: Remote configuration loaded successfully: {:?}",
-                configuration
+// as `post_acknowledged`, for the requests whose acknowledgement carries
+// nothing else (alert, IAmAlive): a 2xx answer too large to be read still
+// means that the server received the request, which is not sent again
+fn post_delivered(
+    transport: &mut dyn Transport,
+    request: &TransportRequest,
+) -> Result<ResponseAcknowledgement, ClientError> {
+    match post_acknowledged(transport, request) {
+        Err(ClientError::ResponseTooLarge { limit }) => {
+            warn!(
+                "{:?}: acknowledgement larger than {} bytes, taken as delivered",
+                request.kind, limit
             );
-            return Ok(configuration);
+            StandardOk(ResponseAcknowledgement {
+                success: true,
+                message: None,
+            })
         }
-        Err(e) => {
-            error!("[config downloader]: Error decoding response body: {}", e);
-            return Err(e.into());
+        result => result,
+    }
+}
+
+// sends the request and hands the status, the announced length and the body
+// stream to `read_response`
+fn post_request<T>(
+    transport: &mut dyn Transport,
+    request: &TransportRequest,
+    read_response: impl FnOnce(u16, Option<u64>, &mut dyn Read) -> Result<T, ClientError>,
+) -> Result<T, ClientError> {
+    let mut read_response = Some(read_response);
+    let mut value = None;
+    // the url of a notification service may contain a token
+    match request.kind {
+        RequestKind::Notification => info!("-> POST {}", origin(request.url)),
+        _ => info!("-> POST {}", request.url),
+    }
+    let result = transport.exchange(request, &mut |response| {
+        info!("<- {}", response.status);
+        let read_response = read_response
+            .take()
+            .ok_or(Error::msg("response already read"))?;
+        value = Some(read_response(
+            response.status,
+            response.content_length,
+            response.body,
+        )?);
+        StandardOk(())
+    });
+    match (result, value) {
+        (StandardOk(()), Some(value)) => StandardOk(value),
+        (StandardOk(()), None) => Err(ClientError::Transport(Error::msg("no response"))),
+        (Err(e), _) => {
+            let error = to_client_error(e);
+            error!("{}", error);
+            Err(error)
         }
     }
 }
 
-fn post_request(
-    payload: &[u8],
-    mut client: HttpClient<EspHttpConnection>,
-    url: &str,
-) -> Result<String, Error> {
-    let content_length_header = format!("{}", payload.len());
-    let headers = [
-        ("content-type", "application/json"),
-        ("content-length", &*content_length_header),
-    ];
-
-    let request = client.post(url, &headers);
-
-    if request.is_err() {
-        let message = format!("connection error: {:?}", request.err());
-        error!("{}", message);
-        return Err(Error::msg(message));
-    }
-    let mut request = request.unwrap();
-
-    if request.write_all(payload).is_err() {
-        let message = format!("connection error while trying to write all");
-        error!("{}", message);
-        return Err(Error::msg(message));
-    }
-    if request.flush().is_err() {
-        let message = format!("connection error while trying to flush");
-        error!("{}", message);
-        return Err(Error::msg(message));
-    }
-    info!("-> POST {}", url);
-    let response = request.submit();
-    if response.is_err() {
-        let message = format!("connection error while trying to read response");
-        error!("{}", message);
-        return Err(Error::msg(message));
-    }
-    let mut response = response.unwrap();
-
-    let status = response.status();
-    info!("<- {}", status);
-    let mut buf = [0u8; 4086];
-    let bytes_read = io::try_read_full(&mut response, &mut buf).map_err(|e| e.0);
-
-    if bytes_read.is_err() {
-        let message = format!(
-            "connection error while trying to read response: {:?}",
-            bytes_read.err()
-        );
-        error!("{}", message);
-        return Err(Error::msg(message));
-    } else {
-        let bytes_read = bytes_read.unwrap();
-        return match std::str::from_utf8(&buf[0..bytes_read]) {
-            Err(e) => Err(Error::msg(format!("{:?}", e))),
-            StandardOk(str) => Ok(str.to_owned()),
-        };
+// errors of `read_response` come back through the transport; a failed
+// certificate verification is kept apart from the other connection errors
+fn to_client_error(e: Error) -> ClientError {
+    let e = match e.downcast::<ClientError>() {
+        StandardOk(e) => return e,
+        Err(e) => e,
+    };
+    match e.downcast::<TlsVerificationError>() {
+        StandardOk(e) => ClientError::Tls(e),
+        Err(e) => ClientError::Transport(e),
     }
 }
 
-pub fn get_default_configuration(e: Error) -> Configuration {
+pub fn get_default_configuration(e: Error, server_endpoints: &ServerEndpoints) -> Configuration {
     error!(
         "Error while trying to load configuration from remote server: {:?}",
         e
     );
     Configuration {
-        alert_endpoint: DEFAULT_ALERT_URL.to_owned(),
+        alert_endpoint: Some(server_endpoints.alert_url.clone()),
         crontab: "* * * * *".to_owned(),
-        i_am_alive_endpoint: DEFAULT_I_AM_ALIVE_URL.to_owned(),
+        i_am_alive_endpoint: server_endpoints.i_am_alive_url.clone(),
         i_am_alive_interval_seconds: DEFAULT_I_AM_ALIVE_INTERVAL_SECONDS,
         timezone_offset: 0,
+        timezone: None,
+        arming_window: None,
+        schedules: Vec::new(),
+        retry_policies: RetryPolicies::default(),
+        alert_queue: AlertQueueConfiguration::default(),
+        alert_tls: None,
+        i_am_alive_tls: None,
+        notifiers: Vec::new(),
+        alert_targets: Vec::new(),
+        message_template: default_message_template(),
     }
 }
-
-pub fn register_device(mac_address: &str) -> anyhow::Result<(), anyhow::Error> {
-    let client = HttpClient::wrap(EspHttpConnection::new(&Default::default())?);
-
-    let payload = serde_json::to_string(&RegisterDeviceDTO::new(
-        mac_address.to_owned(),
-        DEVICE_TYPE.into(),
-        DEVICE_NAME.into(),
-        DEVICE_DESCRIPTION.into(),
-    ))
-    .unwrap();
-    let payload = payload.as_bytes();
-
-    info!("trying to send data...");
-    let result = post_request(payload, client, REGISTER_DEVICE_URL);
-    info!("data sent? {}", !result.is_err());
-    return match result {
-        Err(e) => Err(e.into()),
-        StandardOk(_) => Ok(()),
-    };
-}
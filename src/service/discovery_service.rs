@@ -0,0 +1,182 @@
+use crate::{
+    config::config::{
+        CONFIGURATION_URL, DEFAULT_ALERT_URL, DEFAULT_I_AM_ALIVE_URL, DEVICE_TYPE,
+        REGISTER_DEVICE_URL,
+    },
+    discovery::traits::{DeviceAdvertisement, ServiceDiscovery},
+    status::status_endpoint::STATUS_PATH,
+    util::url_util::with_origin,
+};
+use log::{info, warn};
+use std::time::Duration;
+
+// time given to the server to answer the mDNS query
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+// endpoints of the Elisys server used before the configuration is downloaded
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerEndpoints {
+    pub register_device_url: String,
+    pub configuration_url: String,
+    pub alert_url: String,
+    pub i_am_alive_url: String,
+}
+
+impl ServerEndpoints {
+    // the urls of config.rs
+    pub fn compile_time() -> ServerEndpoints {
+        ServerEndpoints {
+            register_device_url: REGISTER_DEVICE_URL.to_owned(),
+            configuration_url: CONFIGURATION_URL.to_owned(),
+            alert_url: DEFAULT_ALERT_URL.to_owned(),
+            i_am_alive_url: DEFAULT_I_AM_ALIVE_URL.to_owned(),
+        }
+    }
+
+    // the urls of config.rs, on the server at `server_url` when given
+    pub fn configured(server_url: Option<&str>) -> ServerEndpoints {
+        match server_url {
+            Some(origin) => ServerEndpoints::at(origin),
+            None => ServerEndpoints::compile_time(),
+        }
+    }
+
+    // the paths of config.rs on the server at `origin`
+    pub fn at(origin: &str) -> ServerEndpoints {
+        ServerEndpoints {
+            register_device_url: with_origin(REGISTER_DEVICE_URL, origin),
+            configuration_url: with_origin(CONFIGURATION_URL, origin),
+            alert_url: with_origin(DEFAULT_ALERT_URL, origin),
+            i_am_alive_url: with_origin(DEFAULT_I_AM_ALIVE_URL, origin),
+        }
+    }
+}
+
+// looks the server up on the local network, so that the device follows it
+// when its address changes
+pub struct DiscoveryService {
+    discovery: Box<dyn ServiceDiscovery>,
+    service_type: String,
+}
+
+impl DiscoveryService {
+    pub fn new(discovery: Box<dyn ServiceDiscovery>, service_type: &str) -> DiscoveryService {
+        DiscoveryService {
+            discovery,
+            service_type: service_type.to_owned(),
+        }
+    }
+
+    // origin of the server (e.g. `http://192.168.1.40:8080`), None when no
+    // server answered
+    pub fn discover_server(&mut self) -> Option<String> {
+        info!("[discovery] looking for {}...", self.service_type);
+        match self
+            .discovery
+            .discover(&self.service_type, DISCOVERY_TIMEOUT)
+        {
+            Ok(Some(server)) => {
+                info!("[discovery] server found at {}", server.origin());
+                Some(server.origin())
+            }
+            Ok(None) => {
+                warn!("[discovery] no {} found", self.service_type);
+                None
+            }
+            Err(e) => {
+                warn!("[discovery] {}", e);
+                None
+            }
+        }
+    }
+
+    // endpoints of the discovered server, or `fallback`
+    pub fn server_endpoints(&mut self, fallback: ServerEndpoints) -> ServerEndpoints {
+        match self.discover_server() {
+            Some(origin) => ServerEndpoints::at(&origin),
+            None => fallback,
+        }
+    }
+}
+
+// record of the device: `Motion Detector` with MAC 02:00:00:a1:b2:c3 is
+// advertised as `motion-detector-a1b2c3.local`, instance
+// `Motion Detector a1b2c3`
+pub fn device_advertisement(
+    service_type: &str,
+    device_name: &str,
+    mac_address: &str,
+    port: u16,
+) -> DeviceAdvertisement {
+    DeviceAdvertisement {
+        hostname: device_hostname(device_name, mac_address),
+        instance_name: format!("{} {}", device_name, mac_suffix(mac_address)),
+        service_type: service_type.to_owned(),
+        port,
+        txt: vec![
+            ("type".to_owned(), DEVICE_TYPE.to_owned()),
+            ("version".to_owned(), env!("CARGO_PKG_VERSION").to_owned()),
+            ("status".to_owned(), STATUS_PATH.to_owned()),
+            ("mac".to_owned(), mac_address.to_owned()),
+        ],
+    }
+}
+
+// `motion-detector-a1b2c3`: the words of the name followed by the end of the
+// MAC address, unique on the local network
+pub fn device_hostname(device_name: &str, mac_address: &str) -> String {
+    let mut hostname = String::new();
+    for word in device_name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        hostname.push_str(&word.to_ascii_lowercase());
+        hostname.push('-');
+    }
+    hostname.push_str(&mac_suffix(mac_address));
+    hostname
+}
+
+// last 6 hexadecimal digits of the MAC address
+fn mac_suffix(mac_address: &str) -> String {
+    let digits: String = mac_address
+        .chars()
+        .filter(char::is_ascii_hexdigit)
+        .map(|digit| digit.to_ascii_lowercase())
+        .collect();
+    digits[digits.len().saturating_sub(6)..].to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advertisement_ends_with_the_mac_address() {
+        let advertisement =
+            device_advertisement("_motion._tcp", "Motion Detector", "02:00:00:A1:B2:C3", 80);
+
+        assert_eq!(advertisement.hostname, "motion-detector-a1b2c3");
+        assert_eq!(advertisement.instance_name, "Motion Detector a1b2c3");
+        assert_eq!(advertisement.service_type, "_motion._tcp");
+        assert!(advertisement
+            .txt
+            .contains(&("mac".to_owned(), "02:00:00:A1:B2:C3".to_owned())));
+    }
+
+    #[test]
+    fn hostname_keeps_the_letters_and_digits_of_the_name() {
+        let advertisement =
+            device_advertisement("_motion._tcp", "  Hall #2 (Entrée) ", "0200:00a1b2c3", 80);
+
+        assert_eq!(advertisement.hostname, "hall-2-entr-e-a1b2c3");
+    }
+
+    #[test]
+    fn hostname_of_an_unnamed_device_is_the_mac_suffix() {
+        assert_eq!(
+            device_advertisement("_motion._tcp", "", "b2:c3", 80).hostname,
+            "b2c3"
+        );
+    }
+}
@@ -1,3 +1,8 @@
+pub mod alert_queue_service;
 pub mod client_service;
+pub mod discovery_service;
 pub mod orchestrator_service;
 pub mod peripheral_service;
+pub mod schedule_service;
+pub mod settings_service;
+pub mod state_machine;
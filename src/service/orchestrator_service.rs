@@ -1,154 +1,537 @@
 use super::{
-    client_service::{self, get_configuration},
+    alert_queue_service::{AlertQueue, QueuedAlert},
+    client_service::{ClientError, ClientService},
+    discovery_service::{DiscoveryService, ServerEndpoints},
     peripheral_service::PeripheralService,
+    schedule_service::ScheduleService,
+    settings_service::{DEVICE_NAME, DEVICE_SECRET, IS_REMOTE_CONFIGURATION_MANDATORY, SERVER_URL},
+    state_machine::{DetectorEvent, DetectorState, StateMachine},
 };
 use crate::{
-    config::config::{self, CONFIGURATION_URL},
-    dto::config_response::Configuration,
-    service::client_service::{get_default_configuration, register_device},
-    util::thread_util,
+    dto::{
+        config_response::{Configuration, ScheduleAction},
+        request_i_am_alive::RequestIAmAlive,
+    },
+    peripheral::traits::Storage,
+    service::client_service::get_default_configuration,
+    status::status_endpoint::StatusBoard,
+    transport::traits::Transport,
+    util::{clock_util::Clock, thread_util},
 };
-use chrono::{FixedOffset, Utc};
+use chrono::{DateTime, SecondsFormat, Utc};
 use core::result::Result::Ok as StandardOk;
-use cron::Schedule;
+#[cfg(feature = "hal")]
 use esp_idf_svc::sntp;
+#[cfg(feature = "hal")]
 use esp_idf_svc::sntp::SyncStatus;
 use log::{error, info, warn};
-use std::str::FromStr;
-use std::time::Instant;
-pub fn orchestrate() {
-    let mut peripheral_service = PeripheralService::new(config::WIFI_SSID, config::WIFI_PASS);
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const LOOP_SLEEP_TIME: u64 = 20;
+// interval between two replays of the alert queue while the server is down
+pub const REPLAY_INTERVAL_SECONDS: u64 = 10;
+// queued alerts sent per replay, so that a long queue does not keep the
+// sensor unsampled for several retry budgets
+const REPLAY_BATCH_SIZE: usize = 5;
+// minimum interval between two registrations after the server rejected the
+// device token
+pub const REREGISTRATION_INTERVAL_SECONDS: u64 = 60;
+// storage key of the token received with the registration
+const DEVICE_TOKEN_KEY: &str = "device_token";
+// connection failures in a row after which the server is looked up again
+const REDISCOVERY_FAILURES: u32 = 3;
+// minimum interval between two lookups from the detection loop
+pub const REDISCOVERY_INTERVAL_SECONDS: u64 = 60;
+
+pub fn orchestrate(
+    mut peripheral_service: PeripheralService,
+    transport: Box<dyn Transport>,
+    clock: Arc<dyn Clock>,
+    mut discovery_service: Option<DiscoveryService>,
+    status_board: StatusBoard,
+) {
+    let mut state_machine = StateMachine::new();
+    state_machine.handle(DetectorEvent::BootCompleted);
+    while !peripheral_service.retry_wifi_connection_if_necessary_and_return_status() {
+        thread_util::sleep_short(clock.as_ref());
+    }
+    state_machine.handle(DetectorEvent::NetworkConnected);
     let mac_address = peripheral_service.get_mac_address();
+    let settings = peripheral_service.settings();
+    let configured_endpoints = ServerEndpoints::configured(settings.get(&SERVER_URL).as_deref());
+    let is_remote_configuration_mandatory = settings.get(&IS_REMOTE_CONFIGURATION_MANDATORY);
+    let mut client_service = ClientService::new(transport, clock.clone());
+    client_service.set_device_name(&settings.get(&DEVICE_NAME));
+    client_service.set_device_secret(settings.get(&DEVICE_SECRET).as_deref());
+    let server_endpoints = match discovery_service.as_mut() {
+        Some(discovery_service) => discovery_service.server_endpoints(configured_endpoints),
+        None => configured_endpoints,
+    };
+    // used when the server cannot be reached for the registration
+    match peripheral_service.storage().get(DEVICE_TOKEN_KEY) {
+        StandardOk(device_token) => client_service.set_device_token(device_token),
+        Err(e) => warn!("could not read the device token: {}", e),
+    }
 
-    let register_device_result = register_device(&mac_address);
-    if register_device_result.is_err() {
-        error!(
-            "Failed to register the device: {:?}",
-            register_device_result
-        );
-    } else {
-        info!("Device registered successfully!");
+    match client_service.register_device(&server_endpoints.register_device_url, &mac_address) {
+        Err(ClientError::Rejected { message }) => {
+            error!("The server refused the registration: {:?}", message)
+        }
+        Err(e) => error!("Failed to register the device: {}", e),
+        StandardOk(_) => {
+            info!("Device registered successfully!");
+            store_device_token(&client_service, peripheral_service.storage());
+        }
     }
+    state_machine.handle(DetectorEvent::RegistrationCompleted);
 
-    let configuration: Result<Configuration, anyhow::Error> =
-        get_configuration(CONFIGURATION_URL, &mac_address);
+    let mut configuration: Result<Configuration, ClientError> =
+        client_service.get_configuration(&server_endpoints.configuration_url, &mac_address);
+    if client_service.is_token_rejected()
+        && client_service
+            .register_device(&server_endpoints.register_device_url, &mac_address)
+            .is_ok()
+    {
+        store_device_token(&client_service, peripheral_service.storage());
+        configuration =
+            client_service.get_configuration(&server_endpoints.configuration_url, &mac_address);
+    }
 
     let configuration = match configuration {
         Err(e) => Some({
-            if config::IS_REMOTE_CONFIGURATION_MANDATORY {
+            if is_remote_configuration_mandatory {
                 error!("Could not download the remote configuration. REMOTE CONFIGURATION DOWNLOAD IS MANDATORY. Terminating the application...");
                 return;
             }
             peripheral_service.led_blink_3_time_short();
-            get_default_configuration(e)
+            get_default_configuration(e.into(), &server_endpoints)
         }),
         StandardOk(config) => Some(config),
     };
 
     let configuration = configuration.unwrap();
-    info!(
-        "{}",
-        format!("configuration (remote || default): {:?}", &configuration)
-    );
-    let client_service = client_service::ClientService::new(
-        &configuration.alert_endpoint,
-        &configuration.i_am_alive_endpoint,
-    );
-
-    let mut detection = false;
-    let mut timer: u64 = 0;
+    info!("configuration (remote || default): {:?}", &configuration);
     peripheral_service.led_blink_1_time_long();
-    let start = Instant::now();
 
     synchronize_clock();
+    state_machine.handle(DetectorEvent::ConfigurationLoaded);
 
-    let mut schedule = Schedule::from_str(&config::DEFAULT_CRONTAB).unwrap();
-    let schedule_result = Schedule::from_str(&configuration.crontab);
-    if schedule_result.is_err() {
-        warn!("invalid crontab value");
-    } else {
-        schedule = schedule_result.unwrap();
+    let alert_queue = AlertQueue::load(peripheral_service.storage(), &configuration.alert_queue);
+    let mut detector = Detector::new(
+        configuration,
+        client_service,
+        &mac_address,
+        clock.clone(),
+        state_machine,
+        alert_queue,
+        discovery_service,
+    );
+    detector.set_status_board(status_board);
+    loop {
+        detector.step(&mut peripheral_service);
+        thread_util::sleep_time(clock.as_ref(), LOOP_SLEEP_TIME);
     }
-    let offset = FixedOffset::east_opt(5 * 60 * 60).unwrap();
-    let mut next_date_time = calculate_next_date_time(&schedule, &offset);
+}
 
-    info!("ESP32 TIME: {:?}", Utc::now().with_timezone(&offset));
-    let mut backup_date_time = "".to_owned();
-    loop {
-        send_i_am_alive_if_necessary(
-            start,
-            &configuration,
-            &mut timer,
-            &client_service,
-            &mac_address,
-            &mut peripheral_service,
+// detection loop: one `step` per loop iteration; time is read from the
+// injected clock so that the loop can also be driven by a scenario runner
+pub struct Detector {
+    configuration: Configuration,
+    client_service: ClientService,
+    mac_address: String,
+    clock: Arc<dyn Clock>,
+    state_machine: StateMachine,
+    schedule_service: ScheduleService,
+    active_schedule: Option<String>,
+    alert_queue: AlertQueue,
+    last_replay: Option<Duration>,
+    last_registration: Option<Duration>,
+    // looks the server up again after repeated connection failures
+    discovery_service: Option<DiscoveryService>,
+    last_discovery: Option<Duration>,
+    // read by the status endpoint
+    status_board: Option<StatusBoard>,
+    timer: u64,
+}
+
+impl Detector {
+    // `client_service` is configured with the endpoints of `configuration`
+    pub fn new(
+        configuration: Configuration,
+        mut client_service: ClientService,
+        mac_address: &str,
+        clock: Arc<dyn Clock>,
+        state_machine: StateMachine,
+        alert_queue: AlertQueue,
+        discovery_service: Option<DiscoveryService>,
+    ) -> Detector {
+        client_service.configure(&configuration);
+
+        let schedule_service = ScheduleService::from_configuration(&configuration);
+        let now = clock.now();
+        info!(
+            "ESP32 TIME: {:?} ({:?})",
+            schedule_service.time_zone().local_time(&now),
+            schedule_service.time_zone()
         );
+        Detector {
+            configuration,
+            client_service,
+            mac_address: mac_address.to_owned(),
+            clock,
+            state_machine,
+            schedule_service,
+            active_schedule: None,
+            alert_queue,
+            last_replay: None,
+            last_registration: None,
+            discovery_service,
+            last_discovery: None,
+            status_board: None,
+            timer: 0,
+        }
+    }
+
+    pub fn state(&self) -> DetectorState {
+        self.state_machine.state()
+    }
 
-        let now = Utc::now().with_timezone(&offset);
-        let now_date_time = now.to_rfc2822();
-        if now_date_time.eq(&next_date_time) || now_date_time == backup_date_time {
-            backup_date_time = now_date_time.clone();
-            next_date_time = calculate_next_date_time(&schedule, &offset);
+    pub fn set_status_board(&mut self, status_board: StatusBoard) {
+        self.status_board = Some(status_board);
+    }
 
-            if !peripheral_service.is_motion_detected() && detection {
-                info!("no detection");
-                detection = false;
-                peripheral_service.power_off_output_devices();
-            } else if peripheral_service.is_motion_detected() && !detection {
-                info!("---<< MOVEMENT DETECTED >>---");
-                while !peripheral_service.retry_wifi_connection_if_necessary_and_return_status() {
-                    peripheral_service.led_blink_3_time_long();
-                    thread_util::sleep_short();
-                }
-                if client_service.send_alert(&mac_address).is_err() {
-                    peripheral_service.led_blink_2_time_long();
-                    detection = false;
+    fn publish_status(&self, peripheral_service: &PeripheralService) {
+        let Some(status_board) = &self.status_board else {
+            return;
+        };
+        let mut status = status_board.lock().unwrap();
+        status.state = format!("{:?}", self.state());
+        status.active_schedule = self.active_schedule.clone();
+        status.queued_alerts = self.alert_queue.len();
+        status.dropped_alerts = self.alert_queue.dropped();
+        status.rssi = peripheral_service.get_rssi();
+        status.uptime_seconds = self.clock.elapsed().as_secs();
+    }
+
+    pub fn step(&mut self, peripheral_service: &mut PeripheralService) {
+        let now = self.clock.now();
+        let active_schedule = self.schedule_service.active_at(&now);
+        let action = active_schedule
+            .map(|schedule| schedule.action)
+            .unwrap_or(ScheduleAction::HeartbeatOnly);
+        let active_schedule_name = active_schedule.map(|schedule| schedule.name.clone());
+        if active_schedule_name != self.active_schedule {
+            info!("active schedule: {:?} ({:?})", active_schedule_name, action);
+            self.active_schedule = active_schedule_name;
+        }
+        self.publish_status(peripheral_service);
+
+        if self.client_service.connection_failures() >= REDISCOVERY_FAILURES
+            && self.is_discovery_due()
+        {
+            self.discover_server();
+        }
+        if self.client_service.is_token_rejected() && self.is_registration_due() {
+            self.register_again(peripheral_service);
+        }
+
+        let mut is_server_lost = false;
+        if action != ScheduleAction::Disarm {
+            is_server_lost = send_i_am_alive_if_necessary(
+                self.clock.elapsed(),
+                &self.configuration,
+                &mut self.timer,
+                &mut self.client_service,
+                &self.mac_address,
+                &self.alert_queue,
+                peripheral_service,
+            );
+            // alerts queued while the server was down are replayed also
+            // outside the arming schedule
+            if !self.alert_queue.is_empty() && !self.state().is_degraded() && self.is_replay_due() {
+                if peripheral_service.retry_wifi_connection_if_necessary_and_return_status() {
+                    self.replay_alert_queue(peripheral_service);
                 } else {
-                    peripheral_service.led_blink_1_time_short();
+                    self.last_replay = Some(self.clock.elapsed());
+                }
+            }
+        }
+
+        if action == ScheduleAction::HeartbeatOnly || action == ScheduleAction::Disarm {
+            if self.state() != DetectorState::Disarmed {
+                self.state_machine.handle(DetectorEvent::ScheduleDisarmed);
+                peripheral_service.power_off_output_devices();
+                self.client_service.report_motion(false);
+            }
+            return;
+        }
+
+        if self.state() == DetectorState::Disarmed {
+            self.state_machine.handle(DetectorEvent::ScheduleArmed);
+        }
+        let is_motion_detected = peripheral_service.is_motion_detected();
+        self.client_service.report_motion(is_motion_detected);
+        if is_server_lost
+            && matches!(
+                self.state(),
+                DetectorState::ArmedIdle | DetectorState::MotionActive
+            )
+        {
+            self.state_machine.handle(DetectorEvent::NetworkLost);
+        }
+        if self.state().is_degraded() {
+            self.step_degraded(is_motion_detected, &now, peripheral_service);
+            return;
+        }
+
+        if !is_motion_detected && self.state() == DetectorState::MotionActive {
+            info!("no detection");
+            self.state_machine.handle(DetectorEvent::MotionCleared);
+            peripheral_service.power_off_output_devices();
+        } else if is_motion_detected && self.state() == DetectorState::ArmedIdle {
+            info!("---<< MOVEMENT DETECTED >>---");
+            self.state_machine.handle(DetectorEvent::MotionDetected);
+            // the alert is queued first: when it cannot be delivered now it is
+            // replayed later, with the time of the detection
+            self.queue_alert(&now, peripheral_service);
+            // one reconnection attempt per step: while degraded, the connection
+            // is retried on the next steps
+            if !peripheral_service.retry_wifi_connection_if_necessary_and_return_status() {
+                self.state_machine.handle(DetectorEvent::NetworkLost);
+                peripheral_service.led_blink_3_time_long();
+                return;
+            }
+            if !self.replay_alert_queue(peripheral_service) {
+                peripheral_service.led_blink_2_time_long();
+                self.state_machine.handle(DetectorEvent::AlertFailed);
+            } else {
+                peripheral_service.led_blink_1_time_short();
+                if action == ScheduleAction::Arm {
                     peripheral_service.buzz_1_time_short();
-                    detection = true;
                 }
+                self.state_machine.handle(DetectorEvent::AlertDelivered);
+            }
+        }
+    }
+
+    // the motion keeps being sampled while offline: every new detection is
+    // queued and replayed once the server answers again, to a request of
+    // the replay or to a heartbeat
+    fn step_degraded(
+        &mut self,
+        is_motion_detected: bool,
+        now: &DateTime<Utc>,
+        peripheral_service: &mut PeripheralService,
+    ) {
+        if is_motion_detected && self.state() == DetectorState::DegradedIdle {
+            info!("---<< MOVEMENT DETECTED (offline) >>---");
+            self.state_machine.handle(DetectorEvent::MotionDetected);
+            self.queue_alert(now, peripheral_service);
+        } else if !is_motion_detected && self.state() == DetectorState::Degraded {
+            info!("no detection");
+            self.state_machine.handle(DetectorEvent::MotionCleared);
+            peripheral_service.power_off_output_devices();
+        }
+        if !peripheral_service.retry_wifi_connection_if_necessary_and_return_status() {
+            peripheral_service.led_blink_3_time_long();
+            return;
+        }
+        // right after a WiFi drop the server is tried at once, after a
+        // request without response at the replay interval
+        let is_server_reachable = self.client_service.connection_failures() == 0;
+        if !self.alert_queue.is_empty() && (is_server_reachable || self.is_replay_due()) {
+            self.replay_alert_queue(peripheral_service);
+        }
+        if self.client_service.connection_failures() == 0 {
+            self.state_machine.handle(DetectorEvent::NetworkConnected);
+        }
+    }
+
+    fn queue_alert(&mut self, now: &DateTime<Utc>, peripheral_service: &mut PeripheralService) {
+        let alert = QueuedAlert::new(now.to_rfc3339_opts(SecondsFormat::Secs, true));
+        self.alert_queue.push(alert, peripheral_service.storage());
+    }
+
+    fn is_replay_due(&self) -> bool {
+        match self.last_replay {
+            None => true,
+            Some(last_replay) => {
+                self.clock.elapsed().saturating_sub(last_replay)
+                    >= Duration::from_secs(REPLAY_INTERVAL_SECONDS)
+            }
+        }
+    }
+
+    fn is_discovery_due(&self) -> bool {
+        if self.discovery_service.is_none() {
+            return false;
+        }
+        match self.last_discovery {
+            None => true,
+            Some(last_discovery) => {
+                self.clock.elapsed().saturating_sub(last_discovery)
+                    >= Duration::from_secs(REDISCOVERY_INTERVAL_SECONDS)
+            }
+        }
+    }
+
+    // the server may have moved, e.g. to a new DHCP lease: the endpoints
+    // follow it when it is found at another address
+    fn discover_server(&mut self) {
+        self.last_discovery = Some(self.clock.elapsed());
+        let discovery_service = match self.discovery_service.as_mut() {
+            Some(discovery_service) => discovery_service,
+            None => return,
+        };
+        if let Some(server_origin) = discovery_service.discover_server() {
+            self.client_service.relocate_server(&server_origin);
+        }
+    }
+
+    fn is_registration_due(&self) -> bool {
+        match self.last_registration {
+            None => true,
+            Some(last_registration) => {
+                self.clock.elapsed().saturating_sub(last_registration)
+                    >= Duration::from_secs(REREGISTRATION_INTERVAL_SECONDS)
+            }
+        }
+    }
+
+    // the server rejected the device token: a new one is requested, the
+    // rejected requests are sent again on their next schedule
+    fn register_again(&mut self, peripheral_service: &mut PeripheralService) {
+        self.last_registration = Some(self.clock.elapsed());
+        match self.client_service.register_device_again(&self.mac_address) {
+            Err(e) => error!("Failed to register the device again: {}", e),
+            StandardOk(_) => {
+                info!("Device registered again");
+                store_device_token(&self.client_service, peripheral_service.storage());
             }
         }
+    }
 
-        thread_util::sleep_time(20);
+    // sends the queued alerts in order, stopping at the first one that
+    // reached no target. The alerts that reached no target go first, then
+    // those still missing some targets, so that a target that keeps failing
+    // does not hold back the new alerts. The alerts share one blocking
+    // budget. Returns true when every alert that had reached no target now
+    // reached one.
+    fn replay_alert_queue(&mut self, peripheral_service: &mut PeripheralService) -> bool {
+        self.last_replay = Some(self.clock.elapsed());
+        let deadline = self.client_service.alert_deadline();
+        let (undelivered, delivered): (Vec<usize>, Vec<usize>) = (0..self.alert_queue.len())
+            .partition(|index| {
+                !self
+                    .alert_queue
+                    .get(*index)
+                    .is_some_and(|alert| alert.is_delivered())
+            });
+        let mut is_alert_lost = false;
+        // removed at the end, so that the indices stay valid
+        let mut done = Vec::new();
+        for index in undelivered
+            .into_iter()
+            .chain(delivered)
+            .take(REPLAY_BATCH_SIZE)
+        {
+            let mut alert = match self.alert_queue.get(index) {
+                Some(alert) => alert.clone(),
+                None => break,
+            };
+            let result = self
+                .client_service
+                .send_alert(&self.mac_address, &mut alert, deadline);
+            let is_delivered = alert.is_delivered();
+            match result {
+                // sending it again would be refused as well
+                Err(e) if e.is_permanent() => {
+                    error!("alert {:?} cannot be delivered, discarded: {}", alert, e);
+                    is_alert_lost |= !is_delivered;
+                    done.push(index);
+                }
+                Err(_) => {
+                    self.alert_queue.replace(index, alert);
+                    if !is_delivered {
+                        break;
+                    }
+                }
+                StandardOk(_) => done.push(index),
+            }
+        }
+        done.sort_unstable();
+        for index in done.into_iter().rev() {
+            self.alert_queue.remove(index);
+        }
+        self.alert_queue.flush(peripheral_service.storage());
+        if !self.alert_queue.is_empty() {
+            info!(
+                "[alert queue] {} alert(s) left, {} dropped",
+                self.alert_queue.len(),
+                self.alert_queue.dropped()
+            );
+        }
+        !is_alert_lost && self.alert_queue.undelivered() == 0
     }
 }
 
+// returns true when the heartbeat got no response
 fn send_i_am_alive_if_necessary(
-    start: Instant,
+    duration: Duration,
     configuration: &Configuration,
     timer: &mut u64,
-    client_service: &client_service::ClientService,
-    mac_address: &String,
+    client_service: &mut ClientService,
+    mac_address: &str,
+    alert_queue: &AlertQueue,
     peripheral_service: &mut PeripheralService,
-) {
-    let duration = start.elapsed();
-    if duration.as_secs() % configuration.i_am_alive_interval_seconds == 0
-        && *timer != duration.as_secs()
-    {
-        if client_service.send_i_am_alive(mac_address).is_err() {
-            log::error!("failed to send is alive ack");
-            peripheral_service.led_blink_2_time_short();
-        }
+) -> bool {
+    // due once the interval has elapsed since the last heartbeat, so a step
+    // that misses the exact second delays the heartbeat instead of skipping it
+    if duration.as_secs() >= *timer + configuration.i_am_alive_interval_seconds {
+        let request = RequestIAmAlive::new(
+            mac_address.to_owned(),
+            alert_queue.len(),
+            alert_queue.dropped(),
+            peripheral_service.get_rssi(),
+            duration.as_secs(),
+        );
+        let result = client_service.send_i_am_alive(request);
         *timer = duration.as_secs();
+        match result {
+            Err(ClientError::Rejected { message }) => {
+                log::error!("is alive ack refused by the server: {:?}", message);
+                peripheral_service.led_blink_2_time_short();
+            }
+            Err(e) => {
+                log::error!("failed to send is alive ack: {}", e);
+                peripheral_service.led_blink_2_time_short();
+                return matches!(e, ClientError::Transport(_));
+            }
+            StandardOk(_) => {}
+        }
+    }
+    false
+}
+
+fn store_device_token(client_service: &ClientService, storage: &mut dyn Storage) {
+    let result = match client_service.device_token() {
+        Some(device_token) => storage.set(DEVICE_TOKEN_KEY, device_token),
+        None => storage.remove(DEVICE_TOKEN_KEY),
+    };
+    if let Err(e) = result {
+        warn!("could not store the device token: {}", e);
     }
 }
 
+#[cfg(feature = "hal")]
 fn synchronize_clock() {
     let sntp = sntp::EspSntp::new_default().unwrap();
     info!("SNTP initialized, waiting for status!");
     while sntp.get_sync_status() != SyncStatus::Completed {}
 }
 
-fn calculate_next_date_time(schedule: &Schedule, offset: &FixedOffset) -> String {
-    let next_date_time = schedule
-        .upcoming(Utc)
-        .take(1)
-        .into_iter()
-        .last()
-        .unwrap()
-        .with_timezone(offset)
-        .to_rfc2822();
-    next_date_time
+#[cfg(not(feature = "hal"))]
+fn synchronize_clock() {
+    info!("using the host clock, SNTP synchronization skipped");
 }
@@ -1,31 +1,64 @@
-use embedded_svc::wifi::{AuthMethod, ClientConfiguration, Configuration};
-use esp_idf_hal::{
-    gpio::{Gpio15, Gpio4, Gpio5, Input, Output, PinDriver},
-    peripherals::Peripherals,
+use super::settings_service::SettingsService;
+#[cfg(feature = "hal")]
+use super::settings_service::{DEVICE_NAME, WIFI_PASSWORD, WIFI_SSID};
+#[cfg(feature = "hal")]
+use crate::{
+    config::config::PROVISIONING_AP_PASSWORD,
+    peripheral::esp_peripheral::{EspNetworkLink, EspStorage},
+    provisioning::{
+        esp_provisioning_portal::EspProvisioningPortal,
+        provisioning_portal::{access_point_name, ProvisioningForm},
+    },
 };
+use crate::{
+    peripheral::traits::{Buzzer, MotionSensor, NetworkLink, StatusLed, Storage},
+    util::{clock_util::Clock, thread_util},
+};
+#[cfg(feature = "hal")]
+use esp_idf_hal::{gpio::PinDriver, peripherals::Peripherals, reset};
+#[cfg(feature = "hal")]
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
     nvs::EspDefaultNvsPartition,
-    wifi::{BlockingWifi, EspWifi, WifiDeviceId},
+    wifi::{BlockingWifi, EspWifi},
 };
-use log::info;
-
-use crate::util::thread_util;
+#[cfg(feature = "hal")]
+use log::{error, info, warn};
+use std::sync::Arc;
+#[cfg(feature = "hal")]
+use std::time::Duration;
 
 const TIME_SHORT: u64 = 20;
 const TIME_LONG: u64 = 1000;
+#[cfg(feature = "hal")]
+const NVS_NAMESPACE: &str = "motion_det";
+#[cfg(feature = "hal")]
+const SETTINGS_NVS_NAMESPACE: &str = "motion_set";
+// failed connections in a row at boot after which the provisioning access
+// point is opened
+#[cfg(feature = "hal")]
+const PROVISIONING_CONNECTION_FAILURES: u32 = 5;
+// the device restarts and tries its credentials again when nobody submitted
+// the form in this time, e.g. after a long outage of the access point
+#[cfg(feature = "hal")]
+const PROVISIONING_TIMEOUT: Duration = Duration::from_secs(600);
 
 pub struct PeripheralService {
-    led: PinDriver<'static, Gpio5, Output>,
-    buzzer: PinDriver<'static, Gpio15, Output>,
-    sensor: PinDriver<'static, Gpio4, Input>,
-    wifi: BlockingWifi<EspWifi<'static>>,
-    wifi_ssid: String,
-    wifi_password: String,
+    led: Box<dyn StatusLed>,
+    buzzer: Box<dyn Buzzer>,
+    sensor: Box<dyn MotionSensor>,
+    network: Box<dyn NetworkLink>,
+    storage: Box<dyn Storage>,
+    settings: SettingsService,
+    clock: Arc<dyn Clock>,
 }
 
 impl PeripheralService {
-    pub fn new(wifi_ssid: &str, wifi_password: &str) -> Self {
+    // the WiFi credentials are read from the settings; without them, or when
+    // the connection keeps failing, the provisioning access point is opened
+    // and the device restarts once the form is submitted
+    #[cfg(feature = "hal")]
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
         let peripherals = Peripherals::take().unwrap();
         let led = PinDriver::output(peripherals.pins.gpio5).unwrap();
         let sensor = PinDriver::input(peripherals.pins.gpio4).unwrap();
@@ -33,53 +66,88 @@ impl PeripheralService {
 
         let sys_loop = EspSystemEventLoop::take().unwrap();
         let nvs = EspDefaultNvsPartition::take().unwrap();
+        let storage = EspStorage::new(nvs.clone(), NVS_NAMESPACE).unwrap();
+        let mut settings = SettingsService::new(Box::new(
+            EspStorage::new(nvs.clone(), SETTINGS_NVS_NAMESPACE).unwrap(),
+        ));
 
-        let mut wifi = BlockingWifi::wrap(
+        let wifi = BlockingWifi::wrap(
             EspWifi::new(peripherals.modem, sys_loop.clone(), Some(nvs)).unwrap(),
             sys_loop,
         )
         .unwrap();
-        let mut wifi_connection = connect_wifi(&mut wifi, wifi_ssid, wifi_password);
-        while wifi_connection.is_err() {
-            thread_util::sleep_time(TIME_LONG);
-            wifi_connection = connect_wifi(&mut wifi, wifi_ssid, wifi_password);
+        let mut network = EspNetworkLink::new(
+            wifi,
+            &settings.get(&WIFI_SSID),
+            &settings.get(&WIFI_PASSWORD),
+        );
+        if settings.get(&WIFI_SSID).is_empty() {
+            info!("no WiFi credentials");
+            provision(&mut network, &mut settings);
+        }
+        let mut connection_failures = 0;
+        while network.connect().is_err() {
+            connection_failures += 1;
+            if connection_failures == PROVISIONING_CONNECTION_FAILURES {
+                warn!("cannot connect to the WiFi network");
+                provision(&mut network, &mut settings);
+            }
+            thread_util::sleep_time(clock.as_ref(), TIME_LONG);
         }
 
-        let peripheral_service = PeripheralService {
+        PeripheralService::from_parts(
+            Box::new(sensor),
+            Box::new(led),
+            Box::new(buzzer),
+            Box::new(network),
+            Box::new(storage),
+            settings,
+            clock,
+        )
+    }
+
+    pub fn from_parts(
+        sensor: Box<dyn MotionSensor>,
+        led: Box<dyn StatusLed>,
+        buzzer: Box<dyn Buzzer>,
+        network: Box<dyn NetworkLink>,
+        storage: Box<dyn Storage>,
+        settings: SettingsService,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        PeripheralService {
             led,
             buzzer,
             sensor,
-            wifi,
-            wifi_ssid: wifi_ssid.to_owned(),
-            wifi_password: wifi_password.to_owned(),
-        };
-        return peripheral_service;
+            network,
+            storage,
+            settings,
+            clock,
+        }
     }
 
     pub fn retry_wifi_connection_if_necessary_and_return_status(&mut self) -> bool {
-        if !self.wifi.is_connected().unwrap() {
-            if connect_wifi(&mut self.wifi, &self.wifi_ssid, &self.wifi_password).is_err() {
-                self.led_blink_3_time_long();
-                return false;
-            }
+        if !self.network.is_connected() && self.network.connect().is_err() {
+            self.led_blink_3_time_long();
+            return false;
         }
-        return true;
+        true
     }
 
     pub fn buzz_1_time_short(&mut self) {
-        self.buzzer.set_high().unwrap();
-        thread_util::sleep_time(TIME_SHORT);
-        self.buzzer.set_low().unwrap();
-        thread_util::sleep_time(TIME_SHORT);
+        self.buzzer.turn_on();
+        thread_util::sleep_time(self.clock.as_ref(), TIME_SHORT);
+        self.buzzer.turn_off();
+        thread_util::sleep_time(self.clock.as_ref(), TIME_SHORT);
     }
 
     pub fn power_off_output_devices(&mut self) {
-        self.led.set_low().unwrap();
-        self.buzzer.set_low().unwrap();
+        self.led.turn_off();
+        self.buzzer.turn_off();
     }
 
     pub fn is_motion_detected(&self) -> bool {
-        self.sensor.is_high()
+        self.sensor.is_motion_detected()
     }
 
     pub fn led_blink_3_time_short(&mut self) {
@@ -113,50 +181,52 @@ impl PeripheralService {
     }
 
     pub fn get_mac_address(&self) -> String {
-        let mav = &self
-            .wifi
-            .wifi()
-            .driver()
-            .get_mac(WifiDeviceId::Sta)
-            .unwrap();
-        let mac_address_obj =
-            macaddr::MacAddr6::new(mav[0], mav[1], mav[2], mav[3], mav[4], mav[5]);
-        let mac_address_value = mac_address_obj.to_string();
-        info!("MAC_ADDRESS: {:?}", mac_address_value);
-        mac_address_value
+        self.network.get_mac_address()
+    }
+
+    pub fn get_rssi(&self) -> Option<i8> {
+        self.network.get_rssi()
+    }
+
+    pub fn storage(&mut self) -> &mut dyn Storage {
+        self.storage.as_mut()
+    }
+
+    pub fn settings(&mut self) -> &mut SettingsService {
+        &mut self.settings
     }
 
     fn led_blink_1_time(&mut self, time: u64) {
-        self.led.set_high().unwrap();
-        thread_util::sleep_time(time);
-        self.led.set_low().unwrap();
-        thread_util::sleep_time(time);
+        self.led.turn_on();
+        thread_util::sleep_time(self.clock.as_ref(), time);
+        self.led.turn_off();
+        thread_util::sleep_time(self.clock.as_ref(), time);
     }
 }
 
-fn connect_wifi(
-    wifi: &mut BlockingWifi<EspWifi<'static>>,
-    ssid: &str,
-    password: &str,
-) -> anyhow::Result<()> {
-    let wifi_configuration: Configuration = Configuration::Client(ClientConfiguration {
-        ssid: ssid.into(),
-        bssid: None,
-        auth_method: AuthMethod::WPA2Personal,
-        password: password.into(),
-        channel: None,
-    });
-    info!("Connecting to SSID: {}", ssid);
-    wifi.set_configuration(&wifi_configuration)?;
-
-    wifi.start()?;
-    info!("Wifi started");
-
-    wifi.connect()?;
-    info!("Wifi connected: {}", ssid);
-
-    wifi.wait_netif_up()?;
-    info!("Wifi netif up");
-
-    Ok(())
+// opens the provisioning access point, saves the submitted form and restarts
+#[cfg(feature = "hal")]
+fn provision(network: &mut EspNetworkLink, settings: &mut SettingsService) -> ! {
+    let access_point_name =
+        access_point_name(&settings.get(&DEVICE_NAME), &network.get_mac_address());
+    // kept until the restart, so that the answer to the form is sent
+    let portal = EspProvisioningPortal::start(
+        network.wifi(),
+        &access_point_name,
+        PROVISIONING_AP_PASSWORD,
+        ProvisioningForm::from_settings(settings),
+    );
+    match &portal {
+        Ok(portal) => match portal.wait(PROVISIONING_TIMEOUT) {
+            Some(form) => match form.save(settings) {
+                Ok(()) => info!("settings saved, restarting..."),
+                Err(e) => error!("could not save the settings: {}", e),
+            },
+            None => info!("no settings submitted, restarting..."),
+        },
+        Err(e) => error!("could not open the provisioning access point: {}", e),
+    }
+    // time for the browser to receive the answer
+    std::thread::sleep(Duration::from_secs(2));
+    reset::restart();
 }
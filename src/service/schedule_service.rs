@@ -0,0 +1,316 @@
+use crate::{
+    config::config,
+    dto::config_response::{ArmingWindow, Configuration, NamedSchedule, ScheduleAction},
+    util::time_zone_util::ScheduleTimeZone,
+};
+use anyhow::Error;
+use chrono::{DateTime, Duration, Utc};
+use cron::Schedule;
+use log::{info, warn};
+use std::str::FromStr;
+
+// the parsed crontabs are boxed, they take a few hundred bytes each
+#[derive(Debug, Clone)]
+pub enum ArmingSchedule {
+    // armed during every second matched by the crontab
+    Crontab(Box<Schedule>),
+    // armed from each start tick until the following end tick
+    Window {
+        start: Box<Schedule>,
+        end: Box<Schedule>,
+    },
+    // armed for the duration after each start tick
+    Duration {
+        start: Box<Schedule>,
+        duration: Duration,
+    },
+}
+
+impl ArmingSchedule {
+    pub fn from_window(window: &ArmingWindow) -> Result<ArmingSchedule, Error> {
+        let start = Box::new(Schedule::from_str(&window.start)?);
+        match (&window.end, window.duration_seconds) {
+            (Some(end), _) => Ok(ArmingSchedule::Window {
+                start,
+                end: Box::new(Schedule::from_str(end)?),
+            }),
+            (None, Some(duration_seconds)) => Ok(ArmingSchedule::Duration {
+                start,
+                duration: Duration::seconds(duration_seconds as i64),
+            }),
+            (None, None) => Err(Error::msg(
+                "arming window without end expression or duration",
+            )),
+        }
+    }
+
+    pub fn from_crontab(crontab: &str) -> ArmingSchedule {
+        match Schedule::from_str(crontab) {
+            Ok(schedule) => ArmingSchedule::Crontab(Box::new(schedule)),
+            Err(e) => {
+                warn!("invalid crontab value {}: {}", crontab, e);
+                ArmingSchedule::Crontab(Box::new(
+                    Schedule::from_str(config::DEFAULT_CRONTAB).unwrap(),
+                ))
+            }
+        }
+    }
+
+    // the answer only depends on the instant, so a slow loop iteration
+    // cannot skip a tick
+    pub fn is_active_at(&self, time_zone: &ScheduleTimeZone, instant: &DateTime<Utc>) -> bool {
+        match self {
+            ArmingSchedule::Crontab(schedule) => time_zone.includes(schedule, instant),
+            ArmingSchedule::Window { start, end } => {
+                match time_zone.previous_at_or_before(start, instant) {
+                    None => false,
+                    Some(last_start) => match time_zone.previous_at_or_before(end, instant) {
+                        None => true,
+                        Some(last_end) => last_end < last_start,
+                    },
+                }
+            }
+            ArmingSchedule::Duration { start, duration } => {
+                match time_zone.previous_at_or_before(start, instant) {
+                    None => false,
+                    Some(last_start) => *instant < last_start + *duration,
+                }
+            }
+        }
+    }
+}
+
+// an arming schedule that applies its action while active
+#[derive(Debug, Clone)]
+pub struct ActionSchedule {
+    pub name: String,
+    pub action: ScheduleAction,
+    pub schedule: ArmingSchedule,
+}
+
+impl ActionSchedule {
+    pub fn from_named_schedule(named_schedule: &NamedSchedule) -> Result<ActionSchedule, Error> {
+        let schedule = match (&named_schedule.window, &named_schedule.crontab) {
+            (Some(window), _) => ArmingSchedule::from_window(window)?,
+            (None, Some(crontab)) => {
+                ArmingSchedule::Crontab(Box::new(Schedule::from_str(crontab)?))
+            }
+            (None, None) => return Err(Error::msg("schedule without crontab or window")),
+        };
+        Ok(ActionSchedule {
+            name: named_schedule.name.clone(),
+            action: named_schedule.action,
+            schedule,
+        })
+    }
+}
+
+pub struct ScheduleService {
+    schedules: Vec<ActionSchedule>,
+    time_zone: ScheduleTimeZone,
+}
+
+impl ScheduleService {
+    pub fn new(schedules: Vec<ActionSchedule>, time_zone: ScheduleTimeZone) -> ScheduleService {
+        ScheduleService {
+            schedules,
+            time_zone,
+        }
+    }
+
+    // invalid schedules are skipped; without valid named schedules the
+    // arming window or the crontab arms the sensor as before
+    pub fn from_configuration(configuration: &Configuration) -> ScheduleService {
+        let time_zone = ScheduleTimeZone::from_configuration(
+            configuration.timezone.as_deref(),
+            configuration.timezone_offset,
+        );
+        let mut schedules: Vec<ActionSchedule> = configuration
+            .schedules
+            .iter()
+            .filter_map(|named_schedule| {
+                match ActionSchedule::from_named_schedule(named_schedule) {
+                    Ok(schedule) => Some(schedule),
+                    Err(e) => {
+                        warn!("invalid schedule {:?}: {}", named_schedule, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        if schedules.is_empty() {
+            let (name, schedule) = match &configuration.arming_window {
+                Some(window) => match ArmingSchedule::from_window(window) {
+                    Ok(schedule) => ("armingWindow", schedule),
+                    Err(e) => {
+                        warn!("invalid arming window {:?}: {}", window, e);
+                        (
+                            "crontab",
+                            ArmingSchedule::from_crontab(&configuration.crontab),
+                        )
+                    }
+                },
+                None => (
+                    "crontab",
+                    ArmingSchedule::from_crontab(&configuration.crontab),
+                ),
+            };
+            schedules.push(ActionSchedule {
+                name: name.to_owned(),
+                action: ScheduleAction::Arm,
+                schedule,
+            });
+        }
+        for schedule in schedules.iter() {
+            info!(
+                "schedule {}: {:?} {:?} ({:?})",
+                schedule.name, schedule.action, schedule.schedule, time_zone
+            );
+        }
+        ScheduleService::new(schedules, time_zone)
+    }
+
+    // the active schedule with the highest precedence action, None when no
+    // schedule is active (the sensor is then disarmed, heartbeats are sent)
+    pub fn active_at(&self, instant: &DateTime<Utc>) -> Option<&ActionSchedule> {
+        self.schedules
+            .iter()
+            .filter(|schedule| schedule.schedule.is_active_at(&self.time_zone, instant))
+            .fold(
+                None,
+                |active: Option<&ActionSchedule>, schedule| match active {
+                    Some(active) if active.action >= schedule.action => Some(active),
+                    _ => Some(schedule),
+                },
+            )
+    }
+
+    pub fn time_zone(&self) -> &ScheduleTimeZone {
+        &self.time_zone
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn utc(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        // 2023-11-20 is a Monday
+        Utc.with_ymd_and_hms(2023, 11, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn configuration(fields: serde_json::Value) -> Configuration {
+        let mut configuration = json!({
+            "iAmAliveEndpoint": "http://localhost/IAmAlive",
+            "iAmAliveIntervalSeconds": 60,
+            "crontab": "* * 8-17 * * Mon-Fri *",
+            "timezoneOffsetSec": 0,
+        });
+        configuration
+            .as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        serde_json::from_value(configuration).unwrap()
+    }
+
+    fn action_at(service: &ScheduleService, instant: DateTime<Utc>) -> Option<ScheduleAction> {
+        service.active_at(&instant).map(|schedule| schedule.action)
+    }
+
+    #[test]
+    fn crontab_arms_during_the_matched_seconds() {
+        let service = ScheduleService::from_configuration(&configuration(json!({})));
+        assert_eq!(
+            action_at(&service, utc(20, 9, 0)),
+            Some(ScheduleAction::Arm)
+        );
+        assert_eq!(action_at(&service, utc(20, 18, 0)), None);
+        // Saturday
+        assert_eq!(action_at(&service, utc(25, 9, 0)), None);
+    }
+
+    #[test]
+    fn invalid_crontab_falls_back_to_the_default() {
+        let schedule = ArmingSchedule::from_crontab("not a crontab");
+        let expected = ArmingSchedule::from_crontab(config::DEFAULT_CRONTAB);
+        assert_eq!(format!("{:?}", schedule), format!("{:?}", expected));
+    }
+
+    #[test]
+    fn window_spans_midnight() {
+        let service = ScheduleService::from_configuration(&configuration(json!({
+            "armingWindow": { "start": "0 0 22 * * * *", "end": "0 0 6 * * * *" },
+        })));
+        assert_eq!(action_at(&service, utc(20, 21, 59)), None);
+        assert_eq!(
+            action_at(&service, utc(20, 22, 0)),
+            Some(ScheduleAction::Arm)
+        );
+        assert_eq!(
+            action_at(&service, utc(21, 5, 59)),
+            Some(ScheduleAction::Arm)
+        );
+        assert_eq!(action_at(&service, utc(21, 6, 0)), None);
+    }
+
+    #[test]
+    fn duration_arms_after_each_start() {
+        let service = ScheduleService::from_configuration(&configuration(json!({
+            "armingWindow": { "start": "0 30 12 * * * *", "durationSeconds": 600 },
+        })));
+        assert_eq!(action_at(&service, utc(20, 12, 29)), None);
+        assert_eq!(
+            action_at(&service, utc(20, 12, 35)),
+            Some(ScheduleAction::Arm)
+        );
+        assert_eq!(action_at(&service, utc(20, 12, 40)), None);
+    }
+
+    #[test]
+    fn window_without_end_falls_back_to_the_crontab() {
+        let service = ScheduleService::from_configuration(&configuration(json!({
+            "armingWindow": { "start": "0 0 22 * * * *" },
+        })));
+        assert_eq!(
+            action_at(&service, utc(20, 9, 0)),
+            Some(ScheduleAction::Arm)
+        );
+        assert_eq!(action_at(&service, utc(20, 23, 0)), None);
+    }
+
+    #[test]
+    fn highest_precedence_action_wins() {
+        let service = ScheduleService::from_configuration(&configuration(json!({
+            "schedules": [
+                { "name": "office hours", "action": "arm", "crontab": "* * 8-17 * * * *" },
+                { "name": "lunch", "action": "heartbeatOnly", "crontab": "* * 12 * * * *" },
+                { "name": "broken", "action": "disarm", "crontab": "never" },
+            ],
+        })));
+        assert_eq!(
+            action_at(&service, utc(20, 9, 0)),
+            Some(ScheduleAction::Arm)
+        );
+        assert_eq!(
+            action_at(&service, utc(20, 12, 30)),
+            Some(ScheduleAction::HeartbeatOnly)
+        );
+        assert_eq!(action_at(&service, utc(20, 20, 0)), None);
+    }
+
+    #[test]
+    fn schedules_follow_the_time_zone() {
+        let service = ScheduleService::from_configuration(&configuration(json!({
+            "timezone": "Europe/Rome",
+        })));
+        // 8:00 in Rome
+        assert_eq!(
+            action_at(&service, utc(20, 7, 0)),
+            Some(ScheduleAction::Arm)
+        );
+        assert_eq!(action_at(&service, utc(20, 17, 0)), None);
+    }
+}
@@ -0,0 +1,275 @@
+// Settings of the device that may differ from one site to another, stored
+// in their own NVS namespace (a directory on Linux) so that one firmware
+// build serves the whole fleet. A setting that was never written, or that
+// cannot be read, takes the value of its constant in config.rs.
+use crate::{config::config, peripheral::traits::Storage};
+use log::warn;
+
+// a typed setting; the key is also the NVS key, at most 15 characters
+pub struct Setting<T: SettingValue> {
+    pub key: &'static str,
+    default: fn() -> T,
+}
+
+pub const WIFI_SSID: Setting<String> = Setting {
+    key: "wifi_ssid",
+    default: || config::WIFI_SSID.to_owned(),
+};
+pub const WIFI_PASSWORD: Setting<String> = Setting {
+    key: "wifi_pass",
+    default: || config::WIFI_PASS.to_owned(),
+};
+// origin of the Elisys server (e.g. `http://192.168.1.40:8080`), with the
+// paths of the urls of config.rs; None keeps the urls of config.rs
+pub const SERVER_URL: Setting<Option<String>> = Setting {
+    key: "server_url",
+    default: || None,
+};
+pub const DEVICE_NAME: Setting<String> = Setting {
+    key: "device_name",
+    default: || config::DEVICE_NAME.to_owned(),
+};
+pub const DEVICE_SECRET: Setting<Option<String>> = Setting {
+    key: "device_secret",
+    default: || config::DEVICE_SECRET.map(str::to_owned),
+};
+pub const MQTT_BROKER_URL: Setting<Option<String>> = Setting {
+    key: "mqtt_broker",
+    default: || config::MQTT_BROKER_URL.map(str::to_owned),
+};
+pub const IS_REMOTE_CONFIGURATION_MANDATORY: Setting<bool> = Setting {
+    key: "config_required",
+    default: || config::IS_REMOTE_CONFIGURATION_MANDATORY,
+};
+
+// keys accepted by `set_from_str`, e.g. from the command line
+pub const KEYS: [&str; 7] = [
+    WIFI_SSID.key,
+    WIFI_PASSWORD.key,
+    SERVER_URL.key,
+    DEVICE_NAME.key,
+    DEVICE_SECRET.key,
+    MQTT_BROKER_URL.key,
+    IS_REMOTE_CONFIGURATION_MANDATORY.key,
+];
+
+// text form of a value in the storage
+pub trait SettingValue: Sized {
+    fn parse(value: &str) -> anyhow::Result<Self>;
+    fn format(&self) -> String;
+}
+
+impl SettingValue for String {
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        Ok(value.to_owned())
+    }
+
+    fn format(&self) -> String {
+        self.clone()
+    }
+}
+
+// None is stored as an empty value, which overrides a constant set in
+// config.rs
+impl SettingValue for Option<String> {
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        Ok(Some(value.to_owned()).filter(|value| !value.is_empty()))
+    }
+
+    fn format(&self) -> String {
+        self.clone().unwrap_or_default()
+    }
+}
+
+impl SettingValue for bool {
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        Ok(value.parse::<bool>()?)
+    }
+
+    fn format(&self) -> String {
+        self.to_string()
+    }
+}
+
+pub struct SettingsService {
+    storage: Box<dyn Storage>,
+}
+
+impl SettingsService {
+    pub fn new(storage: Box<dyn Storage>) -> SettingsService {
+        SettingsService { storage }
+    }
+
+    pub fn get<T: SettingValue>(&self, setting: &Setting<T>) -> T {
+        match self.storage.get(setting.key) {
+            Ok(Some(value)) => T::parse(&value).unwrap_or_else(|e| {
+                warn!("[settings] ignoring {}: {}", setting.key, e);
+                (setting.default)()
+            }),
+            Ok(None) => (setting.default)(),
+            Err(e) => {
+                warn!("[settings] cannot read {}: {}", setting.key, e);
+                (setting.default)()
+            }
+        }
+    }
+
+    pub fn set<T: SettingValue>(&mut self, setting: &Setting<T>, value: &T) -> anyhow::Result<()> {
+        self.storage.set(setting.key, &value.format())
+    }
+
+    // back to the constant of config.rs
+    pub fn reset<T: SettingValue>(&mut self, setting: &Setting<T>) -> anyhow::Result<()> {
+        self.storage.remove(setting.key)
+    }
+
+    // `value` is checked against the type of the setting before it is stored
+    pub fn set_from_str(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        match key {
+            key if key == IS_REMOTE_CONFIGURATION_MANDATORY.key => {
+                bool::parse(value)?;
+            }
+            key if KEYS.contains(&key) => {}
+            _ => return Err(anyhow::Error::msg(format!("unknown setting: {}", key))),
+        }
+        self.storage.set(key, value)
+    }
+}
+
+#[cfg(all(test, feature = "simulator"))]
+mod tests {
+    use super::*;
+    use crate::peripheral::host_peripheral::HostFileStorage;
+    use std::{env, fs, path::PathBuf, process};
+
+    // directory of its own for each test, removed when dropped
+    struct Directory(PathBuf);
+
+    impl Directory {
+        fn new(name: &str) -> Directory {
+            let path = env::temp_dir().join(format!("settings-{}-{}", process::id(), name));
+            let _ = fs::remove_dir_all(&path);
+            Directory(path)
+        }
+
+        fn settings(&self) -> SettingsService {
+            SettingsService::new(Box::new(HostFileStorage::new(self.0.clone()).unwrap()))
+        }
+    }
+
+    impl Drop for Directory {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn unset_settings_take_the_constants_of_config() {
+        let directory = Directory::new("defaults");
+        let settings = directory.settings();
+
+        assert_eq!(settings.get(&WIFI_SSID), config::WIFI_SSID);
+        assert_eq!(settings.get(&DEVICE_NAME), config::DEVICE_NAME);
+        assert_eq!(settings.get(&SERVER_URL), None);
+        assert_eq!(
+            settings.get(&MQTT_BROKER_URL).as_deref(),
+            config::MQTT_BROKER_URL
+        );
+        assert_eq!(
+            settings.get(&IS_REMOTE_CONFIGURATION_MANDATORY),
+            config::IS_REMOTE_CONFIGURATION_MANDATORY
+        );
+    }
+
+    #[test]
+    fn set_and_reset_round_trip() {
+        let directory = Directory::new("round-trip");
+        let mut settings = directory.settings();
+
+        settings.set(&DEVICE_NAME, &"Hall".to_owned()).unwrap();
+        settings
+            .set(&SERVER_URL, &Some("http://192.168.1.40:8080".to_owned()))
+            .unwrap();
+        settings
+            .set(&IS_REMOTE_CONFIGURATION_MANDATORY, &true)
+            .unwrap();
+        assert_eq!(settings.get(&DEVICE_NAME), "Hall");
+        assert_eq!(
+            settings.get(&SERVER_URL).as_deref(),
+            Some("http://192.168.1.40:8080")
+        );
+        assert!(settings.get(&IS_REMOTE_CONFIGURATION_MANDATORY));
+
+        settings.reset(&DEVICE_NAME).unwrap();
+        settings.reset(&SERVER_URL).unwrap();
+        assert_eq!(settings.get(&DEVICE_NAME), config::DEVICE_NAME);
+        assert_eq!(settings.get(&SERVER_URL), None);
+        // resetting an unset setting is not an error
+        settings.reset(&SERVER_URL).unwrap();
+    }
+
+    #[test]
+    fn empty_value_overrides_an_optional_constant() {
+        let directory = Directory::new("empty");
+        let mut settings = directory.settings();
+
+        settings.set(&MQTT_BROKER_URL, &None).unwrap();
+
+        assert_eq!(settings.get(&MQTT_BROKER_URL), None);
+    }
+
+    #[test]
+    fn invalid_text_is_refused() {
+        let directory = Directory::new("invalid");
+        let mut settings = directory.settings();
+
+        assert!(settings
+            .set_from_str(IS_REMOTE_CONFIGURATION_MANDATORY.key, "maybe")
+            .is_err());
+        assert!(settings.set_from_str("wifi_channel", "6").is_err());
+        assert_eq!(
+            settings.get(&IS_REMOTE_CONFIGURATION_MANDATORY),
+            config::IS_REMOTE_CONFIGURATION_MANDATORY
+        );
+
+        settings
+            .set_from_str(IS_REMOTE_CONFIGURATION_MANDATORY.key, "true")
+            .unwrap();
+        settings.set_from_str(WIFI_SSID.key, "office").unwrap();
+        assert!(settings.get(&IS_REMOTE_CONFIGURATION_MANDATORY));
+        assert_eq!(settings.get(&WIFI_SSID), "office");
+    }
+
+    #[test]
+    fn unreadable_value_takes_the_constant() {
+        let directory = Directory::new("unreadable");
+        let mut settings = directory.settings();
+
+        settings
+            .set_from_str(IS_REMOTE_CONFIGURATION_MANDATORY.key, "true")
+            .unwrap();
+        fs::write(
+            directory.0.join(IS_REMOTE_CONFIGURATION_MANDATORY.key),
+            "yes",
+        )
+        .unwrap();
+
+        assert_eq!(
+            settings.get(&IS_REMOTE_CONFIGURATION_MANDATORY),
+            config::IS_REMOTE_CONFIGURATION_MANDATORY
+        );
+    }
+
+    #[test]
+    fn values_survive_a_reload() {
+        let directory = Directory::new("reload");
+        directory
+            .settings()
+            .set(&WIFI_PASSWORD, &"secret password".to_owned())
+            .unwrap();
+
+        let settings = directory.settings();
+
+        assert_eq!(settings.get(&WIFI_PASSWORD), "secret password");
+    }
+}
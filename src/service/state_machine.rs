@@ -0,0 +1,263 @@
+use log::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetectorState {
+    Booting,
+    Connecting,
+    Registering,
+    Configuring,
+    ArmedIdle,
+    MotionActive,
+    Alerting,
+    Disarmed,
+    // network or server lost while the motion is active
+    Degraded,
+    // network or server lost, no motion
+    DegradedIdle,
+}
+
+impl DetectorState {
+    pub fn is_degraded(&self) -> bool {
+        matches!(self, DetectorState::Degraded | DetectorState::DegradedIdle)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetectorEvent {
+    BootCompleted,
+    NetworkConnected,
+    NetworkLost,
+    RegistrationCompleted,
+    ConfigurationLoaded,
+    ScheduleArmed,
+    ScheduleDisarmed,
+    MotionDetected,
+    MotionCleared,
+    AlertDelivered,
+    AlertFailed,
+}
+
+// Lifecycle of the device, from boot to the detection loop:
+//
+// Booting -> Connecting -> Registering -> Configuring -> ArmedIdle
+// ArmedIdle -> Alerting -> MotionActive -> ArmedIdle
+// Alerting -> MotionActive (alert failed, queued and replayed later)
+// Alerting/MotionActive -> Degraded -> MotionActive (network or server lost
+//     and then restored)
+// ArmedIdle -> DegradedIdle -> ArmedIdle (same, without motion)
+// Degraded <-> DegradedIdle (motion while offline, queued and replayed later)
+// ArmedIdle/Alerting/MotionActive/Degraded* <-> Disarmed (outside the arming
+//     schedule)
+pub fn transition(state: DetectorState, event: DetectorEvent) -> Option<DetectorState> {
+    use DetectorEvent::*;
+    use DetectorState::*;
+    match (state, event) {
+        (Booting, BootCompleted) => Some(Connecting),
+        (Connecting, NetworkConnected) => Some(Registering),
+        (Registering, RegistrationCompleted) => Some(Configuring),
+        (Configuring, ConfigurationLoaded) => Some(ArmedIdle),
+        (ArmedIdle, MotionDetected) => Some(Alerting),
+        (Alerting, AlertDelivered) => Some(MotionActive),
+        (Alerting, AlertFailed) => Some(MotionActive),
+        (Alerting, NetworkLost) => Some(Degraded),
+        (MotionActive, NetworkLost) => Some(Degraded),
+        (ArmedIdle, NetworkLost) => Some(DegradedIdle),
+        (Degraded, MotionCleared) => Some(DegradedIdle),
+        (DegradedIdle, MotionDetected) => Some(Degraded),
+        (Degraded, NetworkConnected) => Some(MotionActive),
+        (DegradedIdle, NetworkConnected) => Some(ArmedIdle),
+        (MotionActive, MotionCleared) => Some(ArmedIdle),
+        (ArmedIdle, ScheduleDisarmed) => Some(Disarmed),
+        (Alerting, ScheduleDisarmed) => Some(Disarmed),
+        (MotionActive, ScheduleDisarmed) => Some(Disarmed),
+        (Degraded, ScheduleDisarmed) => Some(Disarmed),
+        (DegradedIdle, ScheduleDisarmed) => Some(Disarmed),
+        (Disarmed, ScheduleArmed) => Some(ArmedIdle),
+        _ => None,
+    }
+}
+
+pub type TransitionListener = Box<dyn FnMut(DetectorState, DetectorEvent, DetectorState) + Send>;
+
+pub struct StateMachine {
+    state: DetectorState,
+    listener: Option<TransitionListener>,
+}
+
+impl StateMachine {
+    pub fn new() -> StateMachine {
+        StateMachine {
+            state: DetectorState::Booting,
+            listener: None,
+        }
+    }
+
+    // called after every transition, in addition to the log
+    pub fn set_listener(&mut self, listener: TransitionListener) {
+        self.listener = Some(listener);
+    }
+
+    pub fn state(&self) -> DetectorState {
+        self.state
+    }
+
+    // applies the event and logs the change; events that are not valid in
+    // the current state are logged and ignored
+    pub fn handle(&mut self, event: DetectorEvent) -> DetectorState {
+        match transition(self.state, event) {
+            Some(next_state) => {
+                info!("[state] {:?} -> {:?} ({:?})", self.state, next_state, event);
+                if let Some(listener) = self.listener.as_mut() {
+                    listener(self.state, event, next_state);
+                }
+                self.state = next_state;
+            }
+            None => warn!("[state] {:?} ignored in {:?}", event, self.state),
+        }
+        self.state
+    }
+}
+
+impl Default for StateMachine {
+    fn default() -> Self {
+        StateMachine::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DetectorEvent::*, DetectorState::*, *};
+    use std::sync::{Arc, Mutex};
+
+    fn run(events: &[DetectorEvent]) -> DetectorState {
+        let mut state_machine = StateMachine::new();
+        for event in events {
+            state_machine.handle(*event);
+        }
+        state_machine.state()
+    }
+
+    const BOOT: [DetectorEvent; 4] = [
+        BootCompleted,
+        NetworkConnected,
+        RegistrationCompleted,
+        ConfigurationLoaded,
+    ];
+
+    #[test]
+    fn boots_to_armed_idle() {
+        assert_eq!(run(&BOOT), ArmedIdle);
+    }
+
+    #[test]
+    fn alerts_once_per_motion() {
+        let mut events = BOOT.to_vec();
+        events.extend([MotionDetected, AlertDelivered]);
+        assert_eq!(run(&events), MotionActive);
+        events.push(MotionCleared);
+        assert_eq!(run(&events), ArmedIdle);
+    }
+
+    #[test]
+    fn failed_alert_keeps_the_motion_active() {
+        let mut events = BOOT.to_vec();
+        events.extend([MotionDetected, AlertFailed]);
+        assert_eq!(run(&events), MotionActive);
+    }
+
+    #[test]
+    fn network_loss_while_alerting_degrades() {
+        let mut events = BOOT.to_vec();
+        events.extend([MotionDetected, NetworkLost]);
+        assert_eq!(run(&events), Degraded);
+        events.push(NetworkConnected);
+        assert_eq!(run(&events), MotionActive);
+    }
+
+    #[test]
+    fn alert_failure_while_motion_is_still_active() {
+        let mut events = BOOT.to_vec();
+        events.extend([MotionDetected, AlertFailed]);
+        // the motion does not alert again until it cleared
+        events.push(MotionDetected);
+        assert_eq!(run(&events), MotionActive);
+        // the server stops answering before the motion clears
+        events.push(NetworkLost);
+        assert_eq!(run(&events), Degraded);
+        events.push(NetworkConnected);
+        assert_eq!(run(&events), MotionActive);
+        events.push(MotionCleared);
+        assert_eq!(run(&events), ArmedIdle);
+    }
+
+    #[test]
+    fn server_loss_while_idle_degrades() {
+        let mut events = BOOT.to_vec();
+        events.push(NetworkLost);
+        assert_eq!(run(&events), DegradedIdle);
+        events.push(NetworkConnected);
+        assert_eq!(run(&events), ArmedIdle);
+    }
+
+    #[test]
+    fn motion_cleared_while_degraded_recovers_idle() {
+        let mut events = BOOT.to_vec();
+        events.extend([MotionDetected, NetworkLost, MotionCleared]);
+        assert_eq!(run(&events), DegradedIdle);
+        events.push(NetworkConnected);
+        assert_eq!(run(&events), ArmedIdle);
+    }
+
+    #[test]
+    fn motion_while_degraded_recovers_active() {
+        let mut events = BOOT.to_vec();
+        events.extend([NetworkLost, MotionDetected]);
+        assert_eq!(run(&events), Degraded);
+        events.push(NetworkConnected);
+        assert_eq!(run(&events), MotionActive);
+    }
+
+    #[test]
+    fn disarms_from_every_armed_state() {
+        for state in [ArmedIdle, Alerting, MotionActive, Degraded, DegradedIdle] {
+            assert_eq!(transition(state, ScheduleDisarmed), Some(Disarmed));
+        }
+        assert!(Degraded.is_degraded() && DegradedIdle.is_degraded());
+        assert!(!MotionActive.is_degraded());
+    }
+
+    #[test]
+    fn follows_the_arming_schedule() {
+        let mut events = BOOT.to_vec();
+        events.push(ScheduleDisarmed);
+        assert_eq!(run(&events), Disarmed);
+        events.push(MotionDetected);
+        assert_eq!(run(&events), Disarmed);
+        events.push(ScheduleArmed);
+        assert_eq!(run(&events), ArmedIdle);
+    }
+
+    #[test]
+    fn ignores_events_out_of_order() {
+        assert_eq!(run(&[MotionDetected]), Booting);
+        assert_eq!(run(&[BootCompleted, ConfigurationLoaded]), Connecting);
+    }
+
+    #[test]
+    fn notifies_the_listener_of_transitions_only() {
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let recorded = transitions.clone();
+        let mut state_machine = StateMachine::new();
+        state_machine.set_listener(Box::new(move |from, event, to| {
+            recorded.lock().unwrap().push((from, event, to));
+        }));
+
+        state_machine.handle(MotionDetected);
+        state_machine.handle(BootCompleted);
+
+        assert_eq!(
+            *transitions.lock().unwrap(),
+            vec![(Booting, BootCompleted, Connecting)]
+        );
+    }
+}
@@ -0,0 +1,539 @@
+use crate::{
+    config::config::{DEFAULT_CRONTAB, DEFAULT_I_AM_ALIVE_INTERVAL_SECONDS},
+    util::signature_util::{self, NONCE_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER},
+};
+use anyhow::Error;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+pub const REGISTER_DEVICE_PATH: &str = "/api/v1/device/register";
+pub const CONFIGURATION_PATH: &str = "/api/v1/motion-sensor/configuration";
+pub const ALERT_PATH: &str = "/alert";
+pub const I_AM_ALIVE_PATH: &str = "/IAmAlive";
+const CONTROL_BEHAVIOUR_PATH: &str = "/__control/behaviour";
+const CONTROL_CONFIGURATION_PATH: &str = "/__control/configuration";
+const CONTROL_REQUESTS_PATH: &str = "/__control/requests";
+const CONTROL_RESET_PATH: &str = "/__control/reset";
+const CONTROL_TOKENS_PATH: &str = "/__control/tokens";
+const CONTROL_SIGNATURES_PATH: &str = "/__control/signatures";
+const SUCCESS_BODY: &str = "{\"success\":true}";
+const INVALID_TOKEN_BODY: &str = "{\"success\":false,\"message\":\"invalid token\"}";
+const INVALID_SIGNATURE_BODY: &str = "{\"success\":false,\"message\":\"invalid signature\"}";
+const MALFORMED_BODY: &str = "{\"success\":tru";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum Endpoint {
+    RegisterDevice,
+    Configuration,
+    Alert,
+    IAmAlive,
+}
+
+impl Endpoint {
+    fn from_path(path: &str) -> Option<Endpoint> {
+        match path {
+            REGISTER_DEVICE_PATH => Some(Endpoint::RegisterDevice),
+            CONFIGURATION_PATH => Some(Endpoint::Configuration),
+            ALERT_PATH => Some(Endpoint::Alert),
+            I_AM_ALIVE_PATH => Some(Endpoint::IAmAlive),
+            _ => None,
+        }
+    }
+}
+
+// how an endpoint answers: `Drop` closes the connection without a response,
+// `Rejected` answers 200 with `"success": false`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Behaviour {
+    Ok,
+    Status { code: u16 },
+    Rejected { message: Option<String> },
+    Delay { millis: u64 },
+    // success body sent with `transfer-encoding: chunked`
+    Chunked,
+    // success body padded to the given size
+    Oversized { bytes: usize },
+    MalformedJson,
+    Drop,
+}
+
+// `delivered` is true when the request was acknowledged with success
+#[derive(Serialize, Debug, Clone)]
+pub struct RecordedRequest {
+    pub endpoint: Endpoint,
+    pub body: String,
+    pub delivered: bool,
+}
+
+#[derive(Deserialize)]
+struct BehaviourRequest {
+    endpoint: Endpoint,
+    behaviour: Behaviour,
+}
+
+#[derive(Deserialize)]
+struct TokensRequest {
+    required: bool,
+}
+
+#[derive(Deserialize)]
+struct SignaturesRequest {
+    secret: Option<String>,
+}
+
+#[derive(Default)]
+struct State {
+    behaviours: HashMap<Endpoint, Behaviour>,
+    configuration: Option<Value>,
+    requests: Vec<RecordedRequest>,
+    // connections accepted since the start (or the last reset)
+    connections: usize,
+    // when set, the requests other than the registration need the token of
+    // the last registration
+    is_token_required: bool,
+    token: Option<String>,
+    issued_tokens: usize,
+    // when set, the alerts and heartbeats must be signed with this secret
+    // and carry a nonce greater than the last one
+    device_secret: Option<String>,
+    last_nonce: u64,
+}
+
+impl State {
+    fn issue_token(&mut self) -> String {
+        self.issued_tokens += 1;
+        let token = format!("token-{}", self.issued_tokens);
+        self.token = Some(token.clone());
+        token
+    }
+
+    fn is_authorized(&self, authorization: Option<&str>) -> bool {
+        if !self.is_token_required {
+            return true;
+        }
+        match (
+            &self.token,
+            authorization.and_then(|a| a.strip_prefix("Bearer ")),
+        ) {
+            (Some(token), Some(presented)) => token == presented,
+            _ => false,
+        }
+    }
+
+    // the timestamp is not checked, since the scenarios run on a virtual clock
+    fn is_signature_valid(&mut self, request: &HttpRequest) -> bool {
+        let secret = match &self.device_secret {
+            Some(secret) => secret,
+            None => return true,
+        };
+        let header = |name: &str| request.headers.get(name).map(|value| value.as_str());
+        let (timestamp, nonce, signature) = match (
+            header(TIMESTAMP_HEADER).and_then(|value| value.parse::<i64>().ok()),
+            header(NONCE_HEADER).and_then(|value| value.parse::<u64>().ok()),
+            header(SIGNATURE_HEADER),
+        ) {
+            (Some(timestamp), Some(nonce), Some(signature)) => (timestamp, nonce, signature),
+            _ => return false,
+        };
+        if nonce <= self.last_nonce
+            || !signature_util::is_valid(
+                secret.as_bytes(),
+                timestamp,
+                nonce,
+                request.body.as_bytes(),
+                signature,
+            )
+        {
+            return false;
+        }
+        self.last_nonce = nonce;
+        true
+    }
+
+    // the issued token is revoked: the device has to register again
+    fn require_tokens(&mut self, is_token_required: bool) {
+        self.is_token_required = is_token_required;
+        self.token = None;
+    }
+}
+
+// Stand-in for the Elisys server: implements the endpoints used by
+// `client_service`, records every request body and answers according to the
+// behaviour configured for each endpoint. The behaviour can be changed from
+// Rust or through the `/__control/*` endpoints.
+pub struct StandInServer {
+    address: SocketAddr,
+    state: Arc<Mutex<State>>,
+}
+
+impl StandInServer {
+    pub fn start(address: &str) -> Result<StandInServer, Error> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let thread_state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                thread_state.lock().unwrap().connections += 1;
+                let connection_state = thread_state.clone();
+                thread::spawn(move || {
+                    if let Err(e) = handle(stream, address, &connection_state) {
+                        warn!("[stand-in server] {}", e);
+                    }
+                });
+            }
+        });
+        info!("[stand-in server] listening on http://{}", address);
+
+        Ok(StandInServer { address, state })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.address, path)
+    }
+
+    pub fn set_behaviour(&self, endpoint: Endpoint, behaviour: Behaviour) {
+        self.state
+            .lock()
+            .unwrap()
+            .behaviours
+            .insert(endpoint, behaviour);
+    }
+
+    // payload returned by the configuration endpoint, in place of the default
+    pub fn set_configuration(&self, configuration: Value) {
+        self.state.lock().unwrap().configuration = Some(configuration);
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    pub fn take_requests(&self) -> Vec<RecordedRequest> {
+        std::mem::take(&mut self.state.lock().unwrap().requests)
+    }
+
+    pub fn connections(&self) -> usize {
+        self.state.lock().unwrap().connections
+    }
+
+    // revokes the issued token and rejects with 401 the requests without the
+    // token of a new registration
+    pub fn require_tokens(&self) {
+        self.state.lock().unwrap().require_tokens(true);
+    }
+
+    // the alerts and heartbeats are rejected with 403 unless signed with
+    // `device_secret`
+    pub fn set_device_secret(&self, device_secret: Option<&str>) {
+        self.state.lock().unwrap().device_secret = device_secret.map(|secret| secret.to_owned());
+    }
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    host: Option<String>,
+    authorization: Option<String>,
+    // every header, with lowercase names
+    headers: HashMap<String, String>,
+    body: String,
+}
+
+// None when the client closed the connection
+fn read_request(reader: &mut BufReader<TcpStream>) -> Result<Option<HttpRequest>, Error> {
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_owned();
+    let path = parts.next().unwrap_or("/").to_owned();
+
+    let mut content_length = 0;
+    let mut host = None;
+    let mut authorization = None;
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.to_ascii_lowercase(), value.trim().to_owned());
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse::<usize>()?;
+            } else if name.eq_ignore_ascii_case("host") {
+                host = Some(value.trim().to_owned());
+            } else if name.eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_owned());
+            }
+        }
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Some(HttpRequest {
+        method,
+        path,
+        host,
+        authorization,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    }))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Error",
+    }
+}
+
+fn write_response(stream: &mut TcpStream, status: u16, body: &str) -> Result<(), Error> {
+    let response = format!(
+        "HTTP/1.1 {} {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes())?;
+    stream.flush()?;
+    Ok(())
+}
+
+// the body is split in chunks of a few bytes, to exercise the decoding
+fn write_chunked_response(stream: &mut TcpStream, status: u16, body: &str) -> Result<(), Error> {
+    let mut response = format!(
+        "HTTP/1.1 {} {}\r\ncontent-type: application/json\r\ntransfer-encoding: chunked\r\n\r\n",
+        status,
+        reason(status)
+    );
+    for chunk in body.as_bytes().chunks(7) {
+        response.push_str(&format!("{:x}\r\n", chunk.len()));
+        response.push_str(&String::from_utf8_lossy(chunk));
+        response.push_str("\r\n");
+    }
+    response.push_str("0\r\n\r\n");
+    stream.write_all(response.as_bytes())?;
+    stream.flush()?;
+    Ok(())
+}
+
+// requests are served on the same connection until the client closes it or
+// a `Drop` behaviour closes it
+fn handle(stream: TcpStream, address: SocketAddr, state: &Mutex<State>) -> Result<(), Error> {
+    let mut reader = BufReader::new(stream);
+    while let Some(request) = read_request(&mut reader)? {
+        if !handle_request(reader.get_mut(), &request, address, state)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// returns false when the connection must be closed
+fn handle_request(
+    stream: &mut TcpStream,
+    request: &HttpRequest,
+    address: SocketAddr,
+    state: &Mutex<State>,
+) -> Result<bool, Error> {
+    info!(
+        "[stand-in server] {} {} {}",
+        request.method, request.path, request.body
+    );
+
+    if request.path.starts_with("/__control/") {
+        handle_control(stream, request, state)?;
+        return Ok(true);
+    }
+
+    let endpoint = match Endpoint::from_path(&request.path) {
+        Some(endpoint) => endpoint,
+        None => {
+            write_response(stream, 404, "{\"success\":false}")?;
+            return Ok(true);
+        }
+    };
+
+    let is_authorized = endpoint == Endpoint::RegisterDevice
+        || state
+            .lock()
+            .unwrap()
+            .is_authorized(request.authorization.as_deref());
+    if !is_authorized {
+        state.lock().unwrap().requests.push(RecordedRequest {
+            endpoint,
+            body: request.body.clone(),
+            delivered: false,
+        });
+        write_response(stream, 401, INVALID_TOKEN_BODY)?;
+        return Ok(true);
+    }
+    let is_signature_valid = !matches!(endpoint, Endpoint::Alert | Endpoint::IAmAlive)
+        || state.lock().unwrap().is_signature_valid(request);
+    if !is_signature_valid {
+        state.lock().unwrap().requests.push(RecordedRequest {
+            endpoint,
+            body: request.body.clone(),
+            delivered: false,
+        });
+        write_response(stream, 403, INVALID_SIGNATURE_BODY)?;
+        return Ok(true);
+    }
+
+    let (behaviour, configuration) = {
+        let state = state.lock().unwrap();
+        (
+            state
+                .behaviours
+                .get(&endpoint)
+                .cloned()
+                .unwrap_or(Behaviour::Ok),
+            state.configuration.clone(),
+        )
+    };
+    let delivered = matches!(
+        behaviour,
+        Behaviour::Ok | Behaviour::Delay { .. } | Behaviour::Chunked | Behaviour::Oversized { .. }
+    );
+    state.lock().unwrap().requests.push(RecordedRequest {
+        endpoint,
+        body: request.body.clone(),
+        delivered,
+    });
+
+    let success_body = match endpoint {
+        // the token of a refused registration is never sent
+        Endpoint::RegisterDevice if delivered => {
+            let token = state.lock().unwrap().issue_token();
+            serde_json::json!({ "success": true, "token": token }).to_string()
+        }
+        Endpoint::Configuration => configuration
+            .unwrap_or(default_configuration(
+                &request.host.clone().unwrap_or(address.to_string()),
+            ))
+            .to_string(),
+        _ => SUCCESS_BODY.to_owned(),
+    };
+    match behaviour {
+        Behaviour::Ok => write_response(stream, 200, &success_body)?,
+        Behaviour::Status { code } => write_response(stream, code, "{\"success\":false}")?,
+        Behaviour::Delay { millis } => {
+            thread::sleep(Duration::from_millis(millis));
+            write_response(stream, 200, &success_body)?
+        }
+        Behaviour::Rejected { message } => {
+            let body = serde_json::json!({ "success": false, "message": message });
+            write_response(stream, 200, &body.to_string())?
+        }
+        Behaviour::Chunked => write_chunked_response(stream, 200, &success_body)?,
+        Behaviour::Oversized { bytes } => {
+            let padding = "x".repeat(bytes.saturating_sub(success_body.len() + 13));
+            let body = format!(
+                "{},\"padding\":\"{}\"}}",
+                &success_body[..success_body.len() - 1],
+                padding
+            );
+            write_response(stream, 200, &body)?
+        }
+        Behaviour::MalformedJson => write_response(stream, 200, MALFORMED_BODY)?,
+        Behaviour::Drop => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn handle_control(
+    stream: &mut TcpStream,
+    request: &HttpRequest,
+    state: &Mutex<State>,
+) -> Result<(), Error> {
+    match request.path.as_str() {
+        CONTROL_BEHAVIOUR_PATH => match serde_json::from_str::<BehaviourRequest>(&request.body) {
+            Ok(behaviour_request) => {
+                state
+                    .lock()
+                    .unwrap()
+                    .behaviours
+                    .insert(behaviour_request.endpoint, behaviour_request.behaviour);
+                write_response(stream, 200, SUCCESS_BODY)
+            }
+            Err(e) => write_bad_request(stream, e),
+        },
+        CONTROL_CONFIGURATION_PATH => match serde_json::from_str::<Value>(&request.body) {
+            Ok(configuration) => {
+                state.lock().unwrap().configuration = Some(configuration);
+                write_response(stream, 200, SUCCESS_BODY)
+            }
+            Err(e) => write_bad_request(stream, e),
+        },
+        CONTROL_REQUESTS_PATH => {
+            let requests = serde_json::to_string(&state.lock().unwrap().requests)?;
+            write_response(stream, 200, &requests)
+        }
+        CONTROL_TOKENS_PATH => match serde_json::from_str::<TokensRequest>(&request.body) {
+            Ok(tokens_request) => {
+                state
+                    .lock()
+                    .unwrap()
+                    .require_tokens(tokens_request.required);
+                write_response(stream, 200, SUCCESS_BODY)
+            }
+            Err(e) => write_bad_request(stream, e),
+        },
+        CONTROL_SIGNATURES_PATH => match serde_json::from_str::<SignaturesRequest>(&request.body) {
+            Ok(signatures_request) => {
+                state.lock().unwrap().device_secret = signatures_request.secret;
+                write_response(stream, 200, SUCCESS_BODY)
+            }
+            Err(e) => write_bad_request(stream, e),
+        },
+        CONTROL_RESET_PATH => {
+            *state.lock().unwrap() = State::default();
+            write_response(stream, 200, SUCCESS_BODY)
+        }
+        _ => write_response(stream, 404, "{\"success\":false}"),
+    }
+}
+
+fn write_bad_request(stream: &mut TcpStream, e: serde_json::Error) -> Result<(), Error> {
+    let body = serde_json::json!({ "success": false, "error": e.to_string() });
+    write_response(stream, 400, &body.to_string())
+}
+
+// endpoints point back to the host the device used to reach this server
+fn default_configuration(host: &str) -> Value {
+    serde_json::json!({
+        "alertEndpoint": format!("http://{}{}", host, ALERT_PATH),
+        "iAmAliveEndpoint": format!("http://{}{}", host, I_AM_ALIVE_PATH),
+        "iAmAliveIntervalSeconds": DEFAULT_I_AM_ALIVE_INTERVAL_SECONDS,
+        "crontab": DEFAULT_CRONTAB,
+        "timezoneOffsetSec": 0,
+    })
+}
@@ -0,0 +1,25 @@
+use super::status_endpoint::{status_body, StatusBoard, STATUS_PATH};
+use embedded_svc::{http::Method, io::Write};
+use esp_idf_svc::http::server::{Configuration, EspHttpServer};
+
+// the ESP-IDF HTTP server, stopped when dropped
+pub struct EspStatusServer {
+    _server: EspHttpServer<'static>,
+}
+
+impl EspStatusServer {
+    pub fn start(port: u16, status_board: StatusBoard) -> anyhow::Result<EspStatusServer> {
+        let mut server = EspHttpServer::new(&Configuration {
+            http_port: port,
+            ..Default::default()
+        })?;
+        server.fn_handler(STATUS_PATH, Method::Get, move |request| {
+            let body = status_body(&status_board);
+            let mut response =
+                request.into_response(200, None, &[("content-type", "application/json")])?;
+            response.write_all(body.as_bytes())?;
+            Ok(())
+        })?;
+        Ok(EspStatusServer { _server: server })
+    }
+}
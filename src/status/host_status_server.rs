@@ -0,0 +1,53 @@
+use super::status_endpoint::{status_body, StatusBoard, STATUS_PATH};
+use log::{info, warn};
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+};
+
+// one connection at a time, closed after the response
+pub struct HostStatusServer {
+    address: SocketAddr,
+}
+
+impl HostStatusServer {
+    pub fn start(port: u16, status_board: StatusBoard) -> anyhow::Result<HostStatusServer> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        let address = listener.local_addr()?;
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = answer(stream, &status_board) {
+                    warn!("[status] {}", e);
+                }
+            }
+        });
+        info!("[status] listening on http://{}{}", address, STATUS_PATH);
+        Ok(HostStatusServer { address })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.address.port()
+    }
+}
+
+fn answer(stream: TcpStream, status_board: &StatusBoard) -> anyhow::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(STATUS_PATH)) => ("200 OK", status_body(status_board)),
+        _ => ("404 Not Found", "{}".to_owned()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let stream = reader.get_mut();
+    stream.write_all(response.as_bytes())?;
+    stream.flush()?;
+    Ok(())
+}
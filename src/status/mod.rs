@@ -0,0 +1,5 @@
+#[cfg(feature = "hal")]
+pub mod esp_status_server;
+#[cfg(feature = "simulator")]
+pub mod host_status_server;
+pub mod status_endpoint;
@@ -0,0 +1,14 @@
+// HTTP endpoint of the device answering GET /status with its `DeviceStatus`
+// in JSON, so that an operator who found the device with mDNS can check it:
+// the ESP-IDF HTTP server serves it on the chip, a small thread on Linux.
+use crate::dto::device_status::DeviceStatus;
+use std::sync::{Arc, Mutex};
+
+pub const STATUS_PATH: &str = "/status";
+
+// written by the detection loop, read by the status server
+pub type StatusBoard = Arc<Mutex<DeviceStatus>>;
+
+pub fn status_body(status_board: &StatusBoard) -> String {
+    serde_json::to_string(&*status_board.lock().unwrap()).unwrap()
+}
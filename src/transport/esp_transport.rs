@@ -0,0 +1,208 @@
+use super::{
+    mqtt_transport::{
+        MessageChunks, MessageId, MqttClient, MqttSession, MqttTopics, MqttTransport, OFFLINE,
+    },
+    traits::{Transport, TransportRequest, TransportResponse},
+};
+use crate::util::{
+    response_util::{EmbeddedReader, MAX_RESPONSE_SIZE},
+    tls_util::EspTlsTrust,
+    url_util::{host, origin},
+};
+use anyhow::Error;
+use embedded_svc::{
+    http::{client::Client as HttpClient, Headers, Status},
+    io::Write,
+    mqtt::client::{Client as _, Details, Event, Message, Publish, QoS},
+};
+use esp_idf_svc::{
+    http::client::EspHttpConnection,
+    mqtt::client::{EspMqttClient, LwtConfiguration, MqttClientConfiguration},
+};
+use log::{info, warn};
+use std::{collections::HashMap, io, sync::Arc, time::Duration};
+
+// HTTP(S) with the ESP-IDF client, one client per origin: the client keeps
+// the connection alive between requests and reconnects when the server has
+// closed it. The CA and the pins of the origin are checked on every
+// connection (`EspTlsTrust`).
+pub struct EspHttpTransport {
+    clients: HashMap<String, OriginClient>,
+}
+
+// the client comes first so that its connection is closed before the trust
+// it refers to is dropped
+struct OriginClient {
+    client: HttpClient<EspHttpConnection>,
+    trust: EspTlsTrust,
+}
+
+impl EspHttpTransport {
+    pub fn new() -> EspHttpTransport {
+        EspHttpTransport {
+            clients: HashMap::new(),
+        }
+    }
+}
+
+impl Default for EspHttpTransport {
+    fn default() -> Self {
+        EspHttpTransport::new()
+    }
+}
+
+impl Transport for EspHttpTransport {
+    fn exchange(
+        &mut self,
+        request: &TransportRequest,
+        read_response: &mut dyn FnMut(TransportResponse) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let is_https = request.url.starts_with("https://");
+        let origin = origin(request.url).to_owned();
+        let mut origin_client = match self.clients.remove(&origin) {
+            Some(origin_client) if origin_client.trust.settings() == request.tls => origin_client,
+            _ => {
+                info!("opening a connection to {}", origin);
+                let trust = EspTlsTrust::new(request.tls)?;
+                let client =
+                    HttpClient::wrap(EspHttpConnection::new(&trust.http_configuration(is_https))?);
+                OriginClient { client, trust }
+            }
+        };
+        let OriginClient { client, trust } = &mut origin_client;
+        let result = trust.run(host(request.url), || send(client, request, read_response));
+        // a client left in the middle of a response is not reused
+        if result.is_ok() {
+            self.clients.insert(origin, origin_client);
+        }
+        result
+    }
+}
+
+fn send(
+    client: &mut HttpClient<EspHttpConnection>,
+    request: &TransportRequest,
+    read_response: &mut dyn FnMut(TransportResponse) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let content_length_header = format!("{}", request.payload.len());
+    let other_headers = request.headers();
+    let mut headers = vec![
+        ("content-type", request.content_type),
+        ("content-length", &*content_length_header),
+    ];
+    headers.extend(
+        other_headers
+            .iter()
+            .map(|(name, value)| (*name, value.as_str())),
+    );
+    let mut http_request = client
+        .post(request.url, &headers)
+        .map_err(|e| Error::msg(format!("{:?}", e)))?;
+    http_request
+        .write_all(request.payload)
+        .map_err(|e| Error::msg(format!("error while trying to write all: {:?}", e)))?;
+    http_request
+        .flush()
+        .map_err(|e| Error::msg(format!("error while trying to flush: {:?}", e)))?;
+    // the ESP-IDF client does not tell a certificate failure from a
+    // connection failure
+    let response = http_request
+        .submit()
+        .map_err(|e| Error::msg(format!("error while trying to read response: {:?}", e)))?;
+
+    let status = response.status();
+    let content_length = response.content_len();
+    // the ESP-IDF client already decodes chunked bodies
+    let mut body = EmbeddedReader(response);
+    read_response(TransportResponse {
+        status,
+        content_length,
+        body: &mut body,
+    })?;
+    io::copy(
+        &mut io::Read::take(&mut body, MAX_RESPONSE_SIZE as u64),
+        &mut io::sink(),
+    )?;
+    Ok(())
+}
+
+// esp-mqtt client: it runs on its own task and reconnects by itself
+pub struct EspMqttTransportClient {
+    client: EspMqttClient<'static>,
+}
+
+impl EspMqttTransportClient {
+    pub fn start(
+        broker_url: &str,
+        topics: &MqttTopics,
+        session: Arc<MqttSession>,
+    ) -> Result<EspMqttTransportClient, Error> {
+        let client_id = topics.client_id();
+        let configuration = MqttClientConfiguration {
+            client_id: Some(&client_id),
+            keep_alive_interval: Some(Duration::from_secs(30)),
+            lwt: Some(LwtConfiguration {
+                topic: &topics.status,
+                payload: OFFLINE.as_bytes(),
+                qos: QoS::AtLeastOnce,
+                retain: true,
+            }),
+            // used by mqtts:// urls
+            crt_bundle_attach: Some(esp_idf_sys::esp_crt_bundle_attach),
+            ..Default::default()
+        };
+        let topics = topics.clone();
+        let mut chunks = MessageChunks::default();
+        let client = EspMqttClient::new(broker_url, &configuration, move |event| match event {
+            Ok(Event::Connected(_)) => session.on_connected(),
+            Ok(Event::Disconnected) => session.on_disconnected(),
+            Ok(Event::Published(id)) => session.on_acknowledged(*id),
+            Ok(Event::Received(message)) => {
+                // the messages larger than the buffer of esp-mqtt come in chunks
+                let (offset, total_size) = match message.details() {
+                    Details::Complete => (0, message.data().len()),
+                    Details::InitialChunk(chunk) => (0, chunk.total_data_size),
+                    Details::SubsequentChunk(chunk) => {
+                        (chunk.current_data_offset, chunk.total_data_size)
+                    }
+                };
+                if let Some((topic, payload)) =
+                    chunks.push(message.topic(), offset, total_size, message.data())
+                {
+                    session.on_message(&topics, &topic, &payload)
+                }
+            }
+            Ok(_) => (),
+            Err(e) => warn!("[mqtt] {:?}", e),
+        })?;
+        Ok(EspMqttTransportClient { client })
+    }
+}
+
+impl MqttClient for EspMqttTransportClient {
+    fn publish(&mut self, topic: &str, retain: bool, payload: &[u8]) -> anyhow::Result<MessageId> {
+        Ok(self
+            .client
+            .publish(topic, QoS::AtLeastOnce, retain, payload)?)
+    }
+
+    fn subscribe(&mut self, topic: &str) -> anyhow::Result<()> {
+        self.client.subscribe(topic, QoS::AtLeastOnce)?;
+        Ok(())
+    }
+}
+
+// MQTT transport publishing on `topics`
+pub fn mqtt_transport(
+    broker_url: &str,
+    topics: MqttTopics,
+) -> Result<MqttTransport<EspMqttTransportClient>, Error> {
+    let session = Arc::new(MqttSession::new());
+    let client = EspMqttTransportClient::start(broker_url, &topics, session.clone())?;
+    Ok(MqttTransport::new(
+        client,
+        topics,
+        session,
+        Box::new(EspHttpTransport::new()),
+    ))
+}
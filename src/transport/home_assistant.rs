@@ -0,0 +1,181 @@
+// Home Assistant MQTT discovery: retained messages on
+// <discovery prefix>/<component>/<device>/<entity>/config announce the
+// entities of the device, so that it appears without any YAML:
+//
+// motion        binary_sensor `motion`, state on <prefix>/<device>/motion
+// connectivity  binary_sensor `connectivity`, state on <prefix>/<device>/status
+// rssi          sensor (diagnostic), `rssi` of the heartbeat
+// uptime        sensor (diagnostic), `uptimeSeconds` of the heartbeat
+//
+// The unique ids are built from the MAC address of the device.
+use super::mqtt_transport::{MqttTopics, MOTION_OFF, MOTION_ON, OFFLINE, ONLINE};
+use serde_json::{json, Value};
+
+// (topic, payload) of the discovery messages, none when the discovery is
+// disabled
+pub fn discovery_messages(topics: &MqttTopics) -> Vec<(String, String)> {
+    let discovery_prefix = match &topics.discovery_prefix {
+        Some(discovery_prefix) => discovery_prefix,
+        None => return Vec::new(),
+    };
+    let node_id = format!("motion_detector_{}", topics.device_id);
+    let device = json!({
+        "identifiers": [node_id],
+        "connections": [["mac", topics.mac_address.to_lowercase()]],
+        "name": format!("{} {}", topics.device_name, topics.device_id),
+        "manufacturer": "Elisys",
+        "model": "ESP32 Motion Detector",
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+    // the entities other than the connectivity are unavailable while the
+    // device is offline
+    let availability = json!({
+        "availability_topic": topics.status,
+        "payload_available": ONLINE,
+        "payload_not_available": OFFLINE,
+    });
+
+    let entities = [
+        (
+            "binary_sensor",
+            "motion",
+            json!({
+                "name": "Motion",
+                "device_class": "motion",
+                "state_topic": topics.motion,
+                "payload_on": MOTION_ON,
+                "payload_off": MOTION_OFF,
+            }),
+            true,
+        ),
+        (
+            "binary_sensor",
+            "connectivity",
+            json!({
+                "name": "Connectivity",
+                "device_class": "connectivity",
+                "entity_category": "diagnostic",
+                "state_topic": topics.status,
+                "payload_on": ONLINE,
+                "payload_off": OFFLINE,
+            }),
+            false,
+        ),
+        (
+            "sensor",
+            "rssi",
+            json!({
+                "name": "RSSI",
+                "device_class": "signal_strength",
+                "entity_category": "diagnostic",
+                "state_class": "measurement",
+                "unit_of_measurement": "dBm",
+                "state_topic": topics.heartbeat,
+                "value_template": "{{ value_json.rssi }}",
+            }),
+            true,
+        ),
+        (
+            "sensor",
+            "uptime",
+            json!({
+                "name": "Uptime",
+                "device_class": "duration",
+                "entity_category": "diagnostic",
+                "state_class": "total_increasing",
+                "unit_of_measurement": "s",
+                "state_topic": topics.heartbeat,
+                "value_template": "{{ value_json.uptimeSeconds }}",
+            }),
+            true,
+        ),
+    ];
+
+    entities
+        .into_iter()
+        .map(
+            |(component, entity, mut payload, is_available_when_online)| {
+                payload["unique_id"] = Value::from(format!("{}_{}", node_id, entity));
+                payload["object_id"] = Value::from(format!("{}_{}", node_id, entity));
+                payload["device"] = device.clone();
+                if is_available_when_online {
+                    merge(&mut payload, &availability);
+                }
+                let topic = format!(
+                    "{}/{}/{}/{}/config",
+                    discovery_prefix, component, node_id, entity
+                );
+                (topic, payload.to_string())
+            },
+        )
+        .collect()
+}
+
+fn merge(payload: &mut Value, fields: &Value) {
+    if let (Some(payload), Some(fields)) = (payload.as_object_mut(), fields.as_object()) {
+        for (key, value) in fields {
+            payload.insert(key.clone(), value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topics(discovery_prefix: Option<&str>) -> MqttTopics {
+        MqttTopics::new("elisys", discovery_prefix, "hallway", "02:00:00:AA:BB:01")
+    }
+
+    fn payloads(topics: &MqttTopics) -> Vec<(String, Value)> {
+        discovery_messages(topics)
+            .into_iter()
+            .map(|(topic, payload)| (topic, serde_json::from_str(&payload).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn disabled_without_a_discovery_prefix() {
+        assert!(discovery_messages(&topics(None)).is_empty());
+    }
+
+    #[test]
+    fn announces_every_entity() {
+        let messages = payloads(&topics(Some("homeassistant")));
+        let topics: Vec<&str> = messages.iter().map(|(topic, _)| topic.as_str()).collect();
+        assert_eq!(
+            topics,
+            [
+                "homeassistant/binary_sensor/motion_detector_020000aabb01/motion/config",
+                "homeassistant/binary_sensor/motion_detector_020000aabb01/connectivity/config",
+                "homeassistant/sensor/motion_detector_020000aabb01/rssi/config",
+                "homeassistant/sensor/motion_detector_020000aabb01/uptime/config",
+            ]
+        );
+    }
+
+    #[test]
+    fn entities_share_the_device() {
+        for (_, payload) in payloads(&topics(Some("homeassistant"))) {
+            let device = &payload["device"];
+            assert_eq!(device["identifiers"][0], "motion_detector_020000aabb01");
+            assert_eq!(device["connections"][0][1], "02:00:00:aa:bb:01");
+            assert_eq!(device["name"], "hallway 020000aabb01");
+            assert_eq!(payload["unique_id"], payload["object_id"]);
+        }
+    }
+
+    #[test]
+    fn motion_state_and_availability() {
+        let topics = topics(Some("homeassistant"));
+        let messages = payloads(&topics);
+        let motion = &messages[0].1;
+        assert_eq!(motion["state_topic"], topics.motion);
+        assert_eq!(motion["payload_on"], MOTION_ON);
+        assert_eq!(motion["availability_topic"], topics.status);
+        // the connectivity reports the status itself, it is never unavailable
+        let connectivity = &messages[1].1;
+        assert_eq!(connectivity["state_topic"], topics.status);
+        assert!(connectivity.get("availability_topic").is_none());
+    }
+}
@@ -0,0 +1,305 @@
+use super::mqtt_transport::{
+    MessageId, MqttClient, MqttSession, MqttTopics, MqttTransport, ACKNOWLEDGEMENT_TIMEOUT,
+    MAX_MESSAGE_SIZE, OFFLINE,
+};
+use super::traits::{Transport, TransportRequest, TransportResponse};
+use crate::util::{
+    http_util::{self, HttpConnection, StaleConnection, Url},
+    response_util::MAX_RESPONSE_SIZE,
+    tls_util::TlsSettings,
+};
+use anyhow::Error;
+use log::info;
+use log::warn;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{self, Read},
+};
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+// HTTP(S) over keep-alive connections, one per origin
+pub struct HostHttpTransport {
+    connections: HashMap<String, (TlsSettings, HttpConnection)>,
+}
+
+impl HostHttpTransport {
+    pub fn new() -> HostHttpTransport {
+        HostHttpTransport {
+            connections: HashMap::new(),
+        }
+    }
+}
+
+impl Default for HostHttpTransport {
+    fn default() -> Self {
+        HostHttpTransport::new()
+    }
+}
+
+impl Transport for HostHttpTransport {
+    fn exchange(
+        &mut self,
+        request: &TransportRequest,
+        read_response: &mut dyn FnMut(TransportResponse) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let url = http_util::parse_url(request.url)?;
+        let origin = url.origin();
+        let (mut connection, is_reused) = match self.connections.remove(&origin) {
+            Some((tls, connection)) if tls == *request.tls => (connection, true),
+            _ => (http_util::connect(&url, request.tls)?, false),
+        };
+        let result = match send(&mut connection, &url, request, read_response) {
+            // the server closed the idle connection before reading the request
+            Err(e) if is_reused && e.is::<StaleConnection>() => {
+                info!("reconnecting to {}", origin);
+                connection = http_util::connect(&url, request.tls)?;
+                send(&mut connection, &url, request, read_response)
+            }
+            result => result,
+        };
+        if result.is_ok() && connection.is_reusable() {
+            self.connections
+                .insert(origin, (request.tls.clone(), connection));
+        }
+        result
+    }
+}
+
+fn send(
+    connection: &mut HttpConnection,
+    url: &Url,
+    request: &TransportRequest,
+    read_response: &mut dyn FnMut(TransportResponse) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut response = connection.post(
+        url,
+        request.content_type,
+        &request.headers(),
+        request.payload,
+    )?;
+    read_response(TransportResponse {
+        status: response.status,
+        content_length: response.content_length,
+        body: response.body.as_mut(),
+    })?;
+    // what is left of the body (e.g. trailing white space), so that the
+    // connection can be reused
+    io::copy(
+        &mut response.body.as_mut().take(MAX_RESPONSE_SIZE as u64),
+        &mut io::sink(),
+    )?;
+    Ok(())
+}
+
+// Answers every request with the status and body returned by `respond`,
+// without network: lets the detection loop run with no server at all.
+pub struct MockTransport {
+    respond: Box<Responder>,
+}
+
+// url and payload of the request to status and body of the response
+type Responder = dyn FnMut(&str, &[u8]) -> Result<(u16, String), Error>;
+
+impl MockTransport {
+    pub fn new(
+        respond: impl FnMut(&str, &[u8]) -> Result<(u16, String), Error> + 'static,
+    ) -> MockTransport {
+        MockTransport {
+            respond: Box::new(respond),
+        }
+    }
+}
+
+impl Transport for MockTransport {
+    fn exchange(
+        &mut self,
+        request: &TransportRequest,
+        read_response: &mut dyn FnMut(TransportResponse) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let (status, body) = (self.respond)(request.url, request.payload)?;
+        read_response(TransportResponse {
+            status,
+            content_length: Some(body.len() as u64),
+            body: &mut body.as_bytes(),
+        })
+    }
+}
+
+// rumqttc client, its event loop runs on a thread and reconnects by itself
+pub struct HostMqttClient {
+    client: rumqttc::Client,
+    session: Arc<MqttSession>,
+    written: Arc<WrittenMessages>,
+    // messages published so far
+    published: u64,
+}
+
+// rumqttc picks the packet identifier when its event loop writes the
+// message, and does not return it from `publish`: the identifiers of the new
+// messages are taken from the outgoing events, in publish order
+#[derive(Default)]
+struct WrittenMessages {
+    state: Mutex<WrittenState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct WrittenState {
+    // written and not acknowledged: written again after a reconnection,
+    // without being a new message
+    in_flight: HashSet<u16>,
+    // identifiers of the new messages, from the `first`-th one published
+    ids: VecDeque<u16>,
+    first: u64,
+}
+
+impl WrittenMessages {
+    fn on_written(&self, id: u16) {
+        let mut state = self.state.lock().unwrap();
+        if state.in_flight.insert(id) {
+            state.ids.push_back(id);
+            self.changed.notify_all();
+        }
+    }
+
+    fn on_acknowledged(&self, id: u16) {
+        self.state.lock().unwrap().in_flight.remove(&id);
+    }
+
+    // identifier of the `index`-th message published, once written; the
+    // identifiers of the messages before it that nobody claimed in time are
+    // returned as well
+    fn wait_id(&self, index: u64, timeout: Duration) -> (Option<u16>, Vec<u16>) {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        let mut unclaimed = Vec::new();
+        loop {
+            while state.first < index {
+                match state.ids.pop_front() {
+                    Some(id) => unclaimed.push(id),
+                    None => break,
+                }
+                state.first += 1;
+            }
+            if state.first == index {
+                if let Some(id) = state.ids.pop_front() {
+                    state.first += 1;
+                    return (Some(id), unclaimed);
+                }
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return (None, unclaimed);
+            }
+            state = self.changed.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+}
+
+impl HostMqttClient {
+    // `broker_url` is `mqtt://host[:port]`
+    pub fn start(
+        broker_url: &str,
+        topics: &MqttTopics,
+        session: Arc<MqttSession>,
+    ) -> Result<HostMqttClient, Error> {
+        let authority = broker_url
+            .strip_prefix("mqtt://")
+            .ok_or(Error::msg(format!(
+                "unsupported broker url: {}",
+                broker_url
+            )))?
+            .trim_end_matches('/');
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse::<u16>()?),
+            None => (authority, 1883),
+        };
+        let mut options = rumqttc::MqttOptions::new(topics.client_id(), host, port);
+        options.set_keep_alive(Duration::from_secs(30));
+        options.set_last_will(rumqttc::LastWill::new(
+            &topics.status,
+            OFFLINE,
+            rumqttc::QoS::AtLeastOnce,
+            true,
+        ));
+        // room for the topic besides the configuration
+        options.set_max_packet_size(MAX_MESSAGE_SIZE + 1024, MAX_MESSAGE_SIZE + 1024);
+        let (client, mut connection) = rumqttc::Client::new(options, 10);
+        let topics = topics.clone();
+        let written = Arc::new(WrittenMessages::default());
+        let event_session = session.clone();
+        let event_written = written.clone();
+        thread::spawn(move || {
+            let session = event_session;
+            let written = event_written;
+            for event in connection.iter() {
+                match event {
+                    Ok(rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_))) => {
+                        session.on_connected()
+                    }
+                    Ok(rumqttc::Event::Outgoing(rumqttc::Outgoing::Publish(id))) => {
+                        written.on_written(id)
+                    }
+                    Ok(rumqttc::Event::Incoming(rumqttc::Packet::PubAck(acknowledgement))) => {
+                        written.on_acknowledged(acknowledgement.pkid);
+                        session.on_acknowledged(acknowledgement.pkid as MessageId)
+                    }
+                    Ok(rumqttc::Event::Incoming(rumqttc::Packet::Publish(publish))) => {
+                        session.on_message(&topics, &publish.topic, &publish.payload)
+                    }
+                    Ok(_) => (),
+                    Err(e) => {
+                        warn!("[mqtt] {}", e);
+                        session.on_disconnected();
+                        thread::sleep(Duration::from_secs(1));
+                    }
+                }
+            }
+        });
+        Ok(HostMqttClient {
+            client,
+            session,
+            written,
+            published: 0,
+        })
+    }
+}
+
+impl MqttClient for HostMqttClient {
+    fn publish(&mut self, topic: &str, retain: bool, payload: &[u8]) -> anyhow::Result<MessageId> {
+        self.client
+            .publish(topic, rumqttc::QoS::AtLeastOnce, retain, payload)?;
+        let index = self.published;
+        self.published += 1;
+        let (id, unclaimed) = self.written.wait_id(index, ACKNOWLEDGEMENT_TIMEOUT);
+        for id in unclaimed {
+            self.session.abandon(id as MessageId);
+        }
+        id.map(|id| id as MessageId)
+            .ok_or(Error::msg(format!("{}: message not written", topic)))
+    }
+
+    fn subscribe(&mut self, topic: &str) -> anyhow::Result<()> {
+        self.client.subscribe(topic, rumqttc::QoS::AtLeastOnce)?;
+        Ok(())
+    }
+}
+
+// MQTT transport publishing on `topics`
+pub fn mqtt_transport(
+    broker_url: &str,
+    topics: MqttTopics,
+) -> Result<MqttTransport<HostMqttClient>, Error> {
+    let session = Arc::new(MqttSession::new());
+    let client = HostMqttClient::start(broker_url, &topics, session.clone())?;
+    Ok(MqttTransport::new(
+        client,
+        topics,
+        session,
+        Box::new(HostHttpTransport::new()),
+    ))
+}
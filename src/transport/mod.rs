@@ -0,0 +1,7 @@
+#[cfg(feature = "hal")]
+pub mod esp_transport;
+pub mod home_assistant;
+#[cfg(feature = "simulator")]
+pub mod host_transport;
+pub mod mqtt_transport;
+pub mod traits;
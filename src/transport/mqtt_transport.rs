@@ -0,0 +1,678 @@
+// Requests sent as MQTT messages on per-device topics, in place of HTTP:
+//
+// <prefix>/<device>/register       device description (retained)
+// <prefix>/<device>/alert          `RequestAlert`
+// <prefix>/<device>/heartbeat      `RequestIAmAlive`
+// <prefix>/<device>/status         `online`, or the retained last will `offline`
+// <prefix>/<device>/motion         `ON` / `OFF`, retained
+// <prefix>/<device>/configuration  subscribed: the configuration, retained by the server
+//
+// Notifications to third-party services go through `http_transport`.
+// With a discovery prefix the Home Assistant discovery messages are
+// published as well (see `home_assistant`).
+//
+// Messages are published with QoS 1 and count as delivered once the broker
+// acknowledges them. The MQTT client of the platform (esp-mqtt on the chip,
+// rumqttc on Linux) reconnects by itself and reports its events to a shared
+// `MqttSession`.
+//
+// The retained last will tells the subscribers that the device is gone, so
+// nobody has to watch the heartbeats for that. They are still published:
+// they are the `RequestIAmAlive` of the HTTP mode and carry what the last
+// will cannot (RSSI, uptime, queued and dropped alerts, alert deliveries).
+use super::{
+    home_assistant,
+    traits::{RequestKind, Transport, TransportRequest, TransportResponse},
+};
+use crate::util::response_util::MAX_RESPONSE_SIZE;
+use anyhow::Error;
+use log::{info, warn};
+use std::{
+    collections::HashSet,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+pub const ONLINE: &str = "online";
+pub const OFFLINE: &str = "offline";
+pub const MOTION_ON: &str = "ON";
+pub const MOTION_OFF: &str = "OFF";
+// answer handed to `ClientService` for an acknowledged message
+const SUCCESS_BODY: &str = "{\"success\":true}";
+pub const ACKNOWLEDGEMENT_TIMEOUT: Duration = Duration::from_secs(5);
+// time left to the broker to deliver the retained configuration
+const CONFIGURATION_TIMEOUT: Duration = Duration::from_secs(5);
+// largest message received, the configuration
+pub const MAX_MESSAGE_SIZE: usize = MAX_RESPONSE_SIZE;
+
+// packet identifier of a QoS 1 message
+pub type MessageId = u32;
+
+#[derive(Debug, Clone)]
+pub struct MqttTopics {
+    pub device_id: String,
+    pub mac_address: String,
+    // shown by Home Assistant
+    pub device_name: String,
+    pub register: String,
+    pub alert: String,
+    pub heartbeat: String,
+    pub status: String,
+    pub motion: String,
+    pub configuration: String,
+    // Home Assistant discovery prefix, when the discovery is enabled
+    pub discovery_prefix: Option<String>,
+}
+
+impl MqttTopics {
+    // the device is identified by its MAC address, without separators
+    pub fn new(
+        prefix: &str,
+        discovery_prefix: Option<&str>,
+        device_name: &str,
+        mac_address: &str,
+    ) -> MqttTopics {
+        let device_id: String = mac_address
+            .chars()
+            .filter(|c| c.is_ascii_hexdigit())
+            .collect::<String>()
+            .to_lowercase();
+        let topic = |name: &str| format!("{}/{}/{}", prefix, device_id, name);
+        MqttTopics {
+            register: topic("register"),
+            alert: topic("alert"),
+            heartbeat: topic("heartbeat"),
+            status: topic("status"),
+            motion: topic("motion"),
+            configuration: topic("configuration"),
+            discovery_prefix: discovery_prefix.map(|prefix| prefix.to_owned()),
+            device_id,
+            mac_address: mac_address.to_owned(),
+            device_name: device_name.to_owned(),
+        }
+    }
+
+    pub fn client_id(&self) -> String {
+        format!("motion-detector-{}", self.device_id)
+    }
+
+    // Home Assistant publishes `online` there when it starts
+    pub fn home_assistant_status(&self) -> Option<String> {
+        self.discovery_prefix
+            .as_ref()
+            .map(|prefix| format!("{}/status", prefix))
+    }
+}
+
+// publish and subscribe of the platform client; both return once the packet
+// is queued, the acknowledgements come back through the session with the
+// identifier returned by `publish`
+pub trait MqttClient {
+    fn publish(&mut self, topic: &str, retain: bool, payload: &[u8]) -> anyhow::Result<MessageId>;
+    fn subscribe(&mut self, topic: &str) -> anyhow::Result<()>;
+}
+
+#[derive(Default)]
+struct SessionState {
+    is_connected: bool,
+    // set on every connection, cleared once the transport subscribed and
+    // published its status again
+    is_new_connection: bool,
+    // set when Home Assistant restarted and lost the discovery messages
+    is_discovery_due: bool,
+    // acknowledged messages not yet claimed by `wait_acknowledgement`
+    acknowledged: HashSet<MessageId>,
+    // messages given up on: their late acknowledgement is dropped, so that
+    // it cannot confirm a later message with the same identifier
+    abandoned: HashSet<MessageId>,
+    configuration: Option<String>,
+}
+
+// events of the platform client, shared with the transport
+#[derive(Default)]
+pub struct MqttSession {
+    state: Mutex<SessionState>,
+    changed: Condvar,
+}
+
+impl MqttSession {
+    pub fn new() -> MqttSession {
+        MqttSession::default()
+    }
+
+    pub fn on_connected(&self) {
+        info!("[mqtt] connected");
+        self.update(|state| {
+            state.is_connected = true;
+            state.is_new_connection = true;
+        });
+    }
+
+    pub fn on_disconnected(&self) {
+        let was_connected = self.state.lock().unwrap().is_connected;
+        if was_connected {
+            warn!("[mqtt] disconnected");
+        }
+        self.update(|state| state.is_connected = false);
+    }
+
+    pub fn on_acknowledged(&self, id: MessageId) {
+        self.update(|state| {
+            if !state.abandoned.remove(&id) {
+                state.acknowledged.insert(id);
+            }
+        });
+    }
+
+    // the configuration is read once, at boot: a configuration published
+    // later only replaces it in memory, and takes effect after a restart,
+    // when the broker delivers it again as the retained message
+    pub fn on_message(&self, topics: &MqttTopics, topic: &str, payload: &[u8]) {
+        if Some(topic) == topics.home_assistant_status().as_deref() {
+            if payload == ONLINE.as_bytes() {
+                info!("[mqtt] Home Assistant started, discovery due");
+                self.update(|state| state.is_discovery_due = true);
+            }
+            return;
+        }
+        if topic != topics.configuration {
+            return;
+        }
+        info!("[mqtt] configuration received on {}", topic);
+        let configuration = String::from_utf8_lossy(payload).into_owned();
+        self.update(|state| state.configuration = Some(configuration));
+    }
+
+    // true when the broker acknowledged the message within `timeout`;
+    // otherwise the message is abandoned
+    fn wait_acknowledgement(&self, id: MessageId, timeout: Duration) -> bool {
+        if self
+            .wait(timeout, |state| {
+                state.acknowledged.remove(&id).then_some(())
+            })
+            .is_some()
+        {
+            return true;
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.acknowledged.remove(&id) {
+            return true;
+        }
+        state.abandoned.insert(id);
+        false
+    }
+
+    // the acknowledgement of the message is no longer awaited
+    pub fn abandon(&self, id: MessageId) {
+        self.update(|state| {
+            if !state.acknowledged.remove(&id) {
+                state.abandoned.insert(id);
+            }
+        });
+    }
+
+    fn is_connected(&self) -> bool {
+        self.state.lock().unwrap().is_connected
+    }
+
+    fn update(&self, change: impl FnOnce(&mut SessionState)) {
+        change(&mut self.state.lock().unwrap());
+        self.changed.notify_all();
+    }
+
+    // waits until `is_done` holds, at most `timeout`
+    fn wait<T>(
+        &self,
+        timeout: Duration,
+        mut is_done: impl FnMut(&mut SessionState) -> Option<T>,
+    ) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(value) = is_done(&mut state) {
+                return Some(value);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            state = self.changed.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+}
+
+pub struct MqttTransport<C: MqttClient> {
+    client: C,
+    topics: MqttTopics,
+    session: Arc<MqttSession>,
+    http_transport: Box<dyn Transport>,
+    // last motion reported by the detector, and last one published
+    motion: Option<bool>,
+    published_motion: Option<bool>,
+    acknowledgement_timeout: Duration,
+    configuration_timeout: Duration,
+}
+
+impl<C: MqttClient> MqttTransport<C> {
+    pub fn new(
+        client: C,
+        topics: MqttTopics,
+        session: Arc<MqttSession>,
+        http_transport: Box<dyn Transport>,
+    ) -> MqttTransport<C> {
+        MqttTransport {
+            client,
+            topics,
+            session,
+            http_transport,
+            motion: None,
+            published_motion: None,
+            acknowledgement_timeout: ACKNOWLEDGEMENT_TIMEOUT,
+            configuration_timeout: CONFIGURATION_TIMEOUT,
+        }
+    }
+
+    // subscriptions, discovery messages, the `online` status and the motion
+    // state are renewed on every connection
+    fn prepare_connection(&mut self) -> Result<(), Error> {
+        self.session
+            .wait(self.acknowledgement_timeout, |state| {
+                state.is_connected.then_some(())
+            })
+            .ok_or(Error::msg("not connected to the MQTT broker"))?;
+        let (is_new_connection, is_discovery_due) = {
+            let state = self.session.state.lock().unwrap();
+            (state.is_new_connection, state.is_discovery_due)
+        };
+        if is_new_connection {
+            self.client.subscribe(&self.topics.configuration)?;
+            if let Some(home_assistant_status) = self.topics.home_assistant_status() {
+                self.client.subscribe(&home_assistant_status)?;
+            }
+        }
+        if is_new_connection || is_discovery_due {
+            self.publish_discovery()?;
+            self.session.update(|state| state.is_discovery_due = false);
+        }
+        if is_new_connection {
+            self.published_motion = None;
+            let status = self.topics.status.clone();
+            self.publish(&status, true, ONLINE.as_bytes())?;
+            self.session.update(|state| state.is_new_connection = false);
+        }
+        if let Some(motion) = self.motion.filter(|_| self.motion != self.published_motion) {
+            let topic = self.topics.motion.clone();
+            let payload = if motion { MOTION_ON } else { MOTION_OFF };
+            self.publish(&topic, true, payload.as_bytes())?;
+            self.published_motion = Some(motion);
+        }
+        Ok(())
+    }
+
+    fn publish_discovery(&mut self) -> Result<(), Error> {
+        let messages = home_assistant::discovery_messages(&self.topics);
+        if !messages.is_empty() {
+            info!("[mqtt] publishing {} discovery message(s)", messages.len());
+        }
+        for (topic, payload) in messages {
+            self.publish(&topic, true, payload.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    // QoS 1: returns once the broker acknowledged this message
+    fn publish(&mut self, topic: &str, retain: bool, payload: &[u8]) -> Result<(), Error> {
+        let id = self.client.publish(topic, retain, payload)?;
+        if !self
+            .session
+            .wait_acknowledgement(id, self.acknowledgement_timeout)
+        {
+            return Err(Error::msg(format!(
+                "{}: no acknowledgement from the broker",
+                topic
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl<C: MqttClient> Transport for MqttTransport<C> {
+    fn exchange(
+        &mut self,
+        request: &TransportRequest,
+        read_response: &mut dyn FnMut(TransportResponse) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        if request.kind == RequestKind::Notification {
+            return self.http_transport.exchange(request, read_response);
+        }
+        self.prepare_connection()?;
+        let (topic, retain) = match request.kind {
+            RequestKind::Configuration => {
+                let configuration = self
+                    .session
+                    .wait(self.configuration_timeout, |state| {
+                        state.configuration.clone()
+                    })
+                    .ok_or(Error::msg(format!(
+                        "no configuration retained on {}",
+                        self.topics.configuration
+                    )))?;
+                return respond(&configuration, read_response);
+            }
+            RequestKind::RegisterDevice => (self.topics.register.clone(), true),
+            RequestKind::Alert => (self.topics.alert.clone(), false),
+            RequestKind::IAmAlive => (self.topics.heartbeat.clone(), false),
+            RequestKind::Notification => unreachable!(),
+        };
+        info!("[mqtt] -> {}", topic);
+        self.publish(&topic, retain, request.payload)?;
+        respond(SUCCESS_BODY, read_response)
+    }
+
+    // published right away when connected, on the next connection otherwise
+    fn report_motion(&mut self, is_motion_detected: bool) -> anyhow::Result<()> {
+        self.motion = Some(is_motion_detected);
+        if self.motion == self.published_motion || !self.session.is_connected() {
+            return Ok(());
+        }
+        self.prepare_connection()
+    }
+}
+
+fn respond(
+    body: &str,
+    read_response: &mut dyn FnMut(TransportResponse) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    read_response(TransportResponse {
+        status: 200,
+        content_length: Some(body.len() as u64),
+        body: &mut body.as_bytes(),
+    })
+}
+
+// Reassembles the messages that the client hands over in chunks (esp-mqtt
+// splits the messages larger than its buffer); only the first chunk carries
+// the topic.
+#[derive(Default)]
+pub struct MessageChunks {
+    topic: String,
+    payload: Vec<u8>,
+    // the rest of the current message is ignored
+    is_dropped: bool,
+}
+
+impl MessageChunks {
+    // returns the whole message once its last chunk arrived; a message
+    // larger than `MAX_MESSAGE_SIZE`, or with a missing chunk, is dropped
+    pub fn push(
+        &mut self,
+        topic: Option<&str>,
+        offset: usize,
+        total_size: usize,
+        data: &[u8],
+    ) -> Option<(String, Vec<u8>)> {
+        if offset == 0 {
+            self.topic = topic.unwrap_or("").to_owned();
+            self.payload.clear();
+            self.is_dropped = total_size > MAX_MESSAGE_SIZE;
+            if self.is_dropped {
+                warn!(
+                    "[mqtt] {}: message of {} bytes dropped",
+                    self.topic, total_size
+                );
+            }
+        }
+        if self.is_dropped || offset != self.payload.len() {
+            self.is_dropped = true;
+            return None;
+        }
+        self.payload.extend_from_slice(data);
+        if self.payload.len() < total_size {
+            return None;
+        }
+        self.is_dropped = true;
+        Some((
+            std::mem::take(&mut self.topic),
+            std::mem::take(&mut self.payload),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::tls_util::TlsSettings;
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    // acknowledges its messages at once unless told otherwise
+    struct MockClient {
+        session: Arc<MqttSession>,
+        is_acknowledging: bool,
+        // acknowledgement of an earlier message, arriving with the next one
+        late_acknowledgement: Option<MessageId>,
+        next_id: MessageId,
+        published: Vec<(String, Vec<u8>)>,
+        subscribed: Vec<String>,
+    }
+
+    impl MqttClient for MockClient {
+        fn publish(
+            &mut self,
+            topic: &str,
+            _retain: bool,
+            payload: &[u8],
+        ) -> anyhow::Result<MessageId> {
+            self.next_id += 1;
+            self.published.push((topic.to_owned(), payload.to_vec()));
+            if let Some(id) = self.late_acknowledgement.take() {
+                self.session.on_acknowledged(id);
+            }
+            if self.is_acknowledging {
+                self.session.on_acknowledged(self.next_id);
+            }
+            Ok(self.next_id)
+        }
+
+        fn subscribe(&mut self, topic: &str) -> anyhow::Result<()> {
+            self.subscribed.push(topic.to_owned());
+            Ok(())
+        }
+    }
+
+    struct NoHttpTransport;
+
+    impl Transport for NoHttpTransport {
+        fn exchange(
+            &mut self,
+            _request: &TransportRequest,
+            _read_response: &mut dyn FnMut(TransportResponse) -> anyhow::Result<()>,
+        ) -> anyhow::Result<()> {
+            Err(Error::msg("no HTTP in this test"))
+        }
+    }
+
+    fn transport() -> MqttTransport<MockClient> {
+        let topics = MqttTopics::new("elisys", None, "Motion Detector", "02:00:00:AA:BB:01");
+        let session = Arc::new(MqttSession::new());
+        let client = MockClient {
+            session: session.clone(),
+            is_acknowledging: true,
+            late_acknowledgement: None,
+            next_id: 0,
+            published: Vec::new(),
+            subscribed: Vec::new(),
+        };
+        let mut transport = MqttTransport::new(client, topics, session, Box::new(NoHttpTransport));
+        transport.acknowledgement_timeout = TIMEOUT;
+        transport.configuration_timeout = TIMEOUT;
+        transport
+    }
+
+    // sends a request of `kind` and returns the body of the answer
+    fn exchange(
+        transport: &mut MqttTransport<MockClient>,
+        kind: RequestKind,
+    ) -> anyhow::Result<String> {
+        let tls = TlsSettings::default();
+        let request = TransportRequest {
+            kind,
+            url: "",
+            content_type: "application/json",
+            authorization: None,
+            signature: None,
+            payload: b"{}",
+            tls: &tls,
+        };
+        let mut body = String::new();
+        transport.exchange(&request, &mut |response| {
+            response.body.read_to_string(&mut body)?;
+            Ok(())
+        })?;
+        Ok(body)
+    }
+
+    fn published_on(transport: &MqttTransport<MockClient>, topic: &str) -> Vec<String> {
+        transport
+            .client
+            .published
+            .iter()
+            .filter(|(published_topic, _)| published_topic == topic)
+            .map(|(_, payload)| String::from_utf8_lossy(payload).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn alert_is_delivered_once_acknowledged() {
+        let mut transport = transport();
+        transport.session.on_connected();
+
+        let body = exchange(&mut transport, RequestKind::Alert).unwrap();
+
+        assert_eq!(body, SUCCESS_BODY);
+        let alert_topic = transport.topics.alert.clone();
+        assert_eq!(published_on(&transport, &alert_topic), ["{}"]);
+    }
+
+    #[test]
+    fn alert_without_acknowledgement_fails() {
+        let mut transport = transport();
+        transport.session.on_connected();
+        exchange(&mut transport, RequestKind::IAmAlive).unwrap();
+        transport.client.is_acknowledging = false;
+
+        assert!(exchange(&mut transport, RequestKind::Alert).is_err());
+    }
+
+    #[test]
+    fn late_acknowledgement_does_not_confirm_the_next_message() {
+        let mut transport = transport();
+        transport.session.on_connected();
+        exchange(&mut transport, RequestKind::IAmAlive).unwrap();
+        transport.client.is_acknowledging = false;
+        assert!(exchange(&mut transport, RequestKind::Alert).is_err());
+        transport.client.late_acknowledgement = Some(transport.client.next_id);
+
+        assert!(exchange(&mut transport, RequestKind::Alert).is_err());
+    }
+
+    #[test]
+    fn request_fails_when_not_connected() {
+        let mut transport = transport();
+
+        assert!(exchange(&mut transport, RequestKind::Alert).is_err());
+        assert!(transport.client.published.is_empty());
+    }
+
+    #[test]
+    fn configuration_is_the_retained_message() {
+        let mut transport = transport();
+        transport.session.on_connected();
+        let topics = transport.topics.clone();
+        transport
+            .session
+            .on_message(&topics, &topics.configuration, b"{\"crontab\":\"*\"}");
+
+        let body = exchange(&mut transport, RequestKind::Configuration).unwrap();
+
+        assert_eq!(body, "{\"crontab\":\"*\"}");
+        assert_eq!(transport.client.subscribed, [topics.configuration]);
+    }
+
+    #[test]
+    fn configuration_times_out_without_retained_message() {
+        let mut transport = transport();
+        transport.session.on_connected();
+
+        assert!(exchange(&mut transport, RequestKind::Configuration).is_err());
+    }
+
+    #[test]
+    fn reconnection_subscribes_and_publishes_online_again() {
+        let mut transport = transport();
+        let topics = transport.topics.clone();
+        transport.session.on_connected();
+        exchange(&mut transport, RequestKind::IAmAlive).unwrap();
+        exchange(&mut transport, RequestKind::IAmAlive).unwrap();
+        assert_eq!(published_on(&transport, &topics.status), [ONLINE]);
+
+        transport.session.on_disconnected();
+        transport.session.on_connected();
+        exchange(&mut transport, RequestKind::IAmAlive).unwrap();
+
+        assert_eq!(published_on(&transport, &topics.status), [ONLINE, ONLINE]);
+        assert_eq!(
+            transport.client.subscribed,
+            [topics.configuration.clone(), topics.configuration]
+        );
+    }
+
+    #[test]
+    fn motion_is_published_again_after_a_reconnection() {
+        let mut transport = transport();
+        let topics = transport.topics.clone();
+        transport.session.on_connected();
+        transport.report_motion(true).unwrap();
+        transport.report_motion(true).unwrap();
+        assert_eq!(published_on(&transport, &topics.motion), [MOTION_ON]);
+
+        transport.session.on_disconnected();
+        // kept until the broker is back
+        transport.report_motion(false).unwrap();
+        assert_eq!(published_on(&transport, &topics.motion), [MOTION_ON]);
+        transport.session.on_connected();
+        transport.report_motion(false).unwrap();
+
+        assert_eq!(
+            published_on(&transport, &topics.motion),
+            [MOTION_ON, MOTION_OFF]
+        );
+    }
+
+    #[test]
+    fn chunks_are_reassembled() {
+        let mut chunks = MessageChunks::default();
+
+        assert_eq!(chunks.push(Some("a/b"), 0, 6, b"abc"), None);
+        assert_eq!(
+            chunks.push(None, 3, 6, b"def"),
+            Some(("a/b".to_owned(), b"abcdef".to_vec()))
+        );
+        assert_eq!(
+            chunks.push(Some("a/c"), 0, 2, b"gh"),
+            Some(("a/c".to_owned(), b"gh".to_vec()))
+        );
+    }
+
+    #[test]
+    fn chunks_of_an_oversized_or_incomplete_message_are_dropped() {
+        let mut chunks = MessageChunks::default();
+        let size = MAX_MESSAGE_SIZE + 1;
+
+        assert_eq!(chunks.push(Some("a/b"), 0, size, b"abc"), None);
+        assert_eq!(chunks.push(None, 3, size, &vec![0; size - 3]), None);
+        assert_eq!(chunks.push(Some("a/b"), 0, 6, b"abc"), None);
+        // the chunk at offset 3 is missing
+        assert_eq!(chunks.push(None, 4, 6, b"ef"), None);
+    }
+}
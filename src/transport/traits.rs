@@ -0,0 +1,68 @@
+// Request/response exchange used by `ClientService`: the ESP-IDF HTTP client
+// implements it on the chip, a keep-alive HTTP client and a mock implement it
+// on Linux. Another protocol only needs another implementation.
+use crate::util::{signature_util::RequestSignature, tls_util::TlsSettings};
+use std::io::Read;
+
+// what the request is for: HTTP transports only use the url, the others map
+// the kind to their own addressing (e.g. an MQTT topic)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestKind {
+    RegisterDevice,
+    Configuration,
+    Alert,
+    IAmAlive,
+    // alert sent directly to a notification service (Discord, ntfy...),
+    // always over HTTP
+    Notification,
+}
+
+pub struct TransportRequest<'a> {
+    pub kind: RequestKind,
+    pub url: &'a str,
+    pub content_type: &'a str,
+    // value of the `authorization` header, if any
+    pub authorization: Option<&'a str>,
+    // HMAC of the payload, sent in headers by the HTTP transports
+    pub signature: Option<&'a RequestSignature>,
+    pub payload: &'a [u8],
+    pub tls: &'a TlsSettings,
+}
+
+impl TransportRequest<'_> {
+    // headers besides the content type and length
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = Vec::new();
+        if let Some(authorization) = self.authorization {
+            headers.push(("authorization", authorization.to_owned()));
+        }
+        if let Some(signature) = self.signature {
+            headers.extend(signature.headers());
+        }
+        headers
+    }
+}
+
+pub struct TransportResponse<'a> {
+    pub status: u16,
+    pub content_length: Option<u64>,
+    pub body: &'a mut dyn Read,
+}
+
+pub trait Transport {
+    // sends the request and hands the response to `read_response`; the
+    // connection is kept for the next request when the response was read
+    // successfully, and opened again lazily otherwise
+    fn exchange(
+        &mut self,
+        request: &TransportRequest,
+        read_response: &mut dyn FnMut(TransportResponse) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()>;
+
+    // called on every sample of the motion sensor; transports that keep a
+    // state for their subscribers (MQTT) publish its changes, the others
+    // ignore it
+    fn report_motion(&mut self, _is_motion_detected: bool) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
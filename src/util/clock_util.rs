@@ -0,0 +1,93 @@
+use chrono::{DateTime, Utc};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Source of time for the orchestrator and the peripherals: wall clock for
+// cron gating, monotonic time for intervals, and sleeping. The virtual
+// implementation only moves when it is advanced or slept on.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+    // monotonic time elapsed since the clock was created
+    fn elapsed(&self) -> Duration;
+    fn sleep(&self, duration: Duration);
+}
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+struct VirtualTime {
+    now: DateTime<Utc>,
+    elapsed: Duration,
+}
+
+pub struct VirtualClock {
+    time: Mutex<VirtualTime>,
+}
+
+impl VirtualClock {
+    pub fn new(now: DateTime<Utc>) -> VirtualClock {
+        VirtualClock {
+            time: Mutex::new(VirtualTime {
+                now,
+                elapsed: Duration::ZERO,
+            }),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut time = self.time.lock().unwrap();
+        time.now += chrono::Duration::from_std(duration).unwrap();
+        time.elapsed += duration;
+    }
+
+    // moves the wall clock only, as an NTP correction would
+    pub fn jump(&self, seconds: i64) {
+        let mut time = self.time.lock().unwrap();
+        time.now += chrono::Duration::seconds(seconds);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.time.lock().unwrap().now
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.time.lock().unwrap().elapsed
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
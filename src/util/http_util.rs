@@ -0,0 +1,366 @@
+// Minimal HTTP/1.1 client with keep-alive connections, used by the Linux host
+// transport in place of `EspHttpConnection`. `https://` URLs are served by
+// rustls, with the web PKI roots in place of the ESP-IDF certificate bundle.
+use super::tls_util::TlsSettings;
+use super::tls_util::TlsVerificationError;
+use super::url_util;
+use anyhow::Error;
+use std::sync::Arc;
+use std::{
+    fmt::{self, Display, Formatter},
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+const TIMEOUT_SECONDS: u64 = 10;
+const MAX_HEADER_LINE_SIZE: u64 = 8 * 1024;
+
+// status and headers are read, the body is left on the connection
+pub struct HttpResponse<'a> {
+    pub status: u16,
+    pub content_length: Option<u64>,
+    pub body: Box<dyn Read + 'a>,
+}
+
+// the connection was closed by the server while idle: the request was not
+// received and can be sent again on a new connection
+#[derive(Debug)]
+pub struct StaleConnection;
+
+impl Display for StaleConnection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "connection closed by the server")
+    }
+}
+
+impl std::error::Error for StaleConnection {}
+
+trait Stream: Read + Write + Send {}
+
+impl<S: Read + Write + Send> Stream for S {}
+
+// Keep-alive connection to one origin (scheme, host and port). A response
+// must be read to its end before the next request is sent.
+pub struct HttpConnection {
+    reader: BufReader<Box<dyn Stream>>,
+    is_reusable: bool,
+}
+
+pub struct Url {
+    pub is_https: bool,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Url {
+    // connections are kept per origin
+    pub fn origin(&self) -> String {
+        let scheme = if self.is_https { "https" } else { "http" };
+        format!("{}://{}", scheme, self.authority())
+    }
+
+    // host and port, an IPv6 host in brackets
+    fn authority(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+pub fn parse_url(url: &str) -> Result<Url, Error> {
+    let (is_https, rest) = match url.strip_prefix("https://") {
+        Some(rest) => (true, rest),
+        None => (
+            false,
+            url.strip_prefix("http://")
+                .ok_or(Error::msg(format!("unsupported url: {}", url)))?,
+        ),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match url_util::split_authority(authority) {
+        (host, Some(port)) => (host, port.parse::<u16>()?),
+        (host, None) => (host, if is_https { 443 } else { 80 }),
+    };
+    Ok(Url {
+        is_https,
+        host: host.to_owned(),
+        port,
+        path: path.to_owned(),
+    })
+}
+
+// `tls` applies to `https://` URLs only
+pub fn connect(url: &Url, tls: &TlsSettings) -> Result<HttpConnection, Error> {
+    let address = (url.host.as_str(), url.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or(Error::msg(format!("cannot resolve {}", url.host)))?;
+    let stream = TcpStream::connect_timeout(&address, Duration::from_secs(TIMEOUT_SECONDS))?;
+    stream.set_read_timeout(Some(Duration::from_secs(TIMEOUT_SECONDS)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(TIMEOUT_SECONDS)))?;
+    let stream: Box<dyn Stream> = if url.is_https {
+        Box::new(connect_tls(stream, &url.host, tls)?)
+    } else {
+        Box::new(stream)
+    };
+    Ok(HttpConnection {
+        reader: BufReader::new(stream),
+        is_reusable: true,
+    })
+}
+
+impl HttpConnection {
+    pub fn post(
+        &mut self,
+        url: &Url,
+        content_type: &str,
+        headers: &[(&str, String)],
+        payload: &[u8],
+    ) -> Result<HttpResponse<'_>, Error> {
+        let headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}\r\n", name, value))
+            .collect();
+        let head = format!(
+            "POST {} HTTP/1.1\r\nhost: {}\r\ncontent-type: {}\r\ncontent-length: {}\r\n{}\r\n",
+            url.path,
+            url.authority(),
+            content_type,
+            payload.len(),
+            headers
+        );
+        let stream = self.reader.get_mut();
+        let written = stream
+            .write_all(head.as_bytes())
+            .and_then(|_| stream.write_all(payload))
+            .and_then(|_| stream.flush());
+        if let Err(e) = written {
+            self.is_reusable = false;
+            return Err(match e.kind() {
+                io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset => {
+                    StaleConnection.into()
+                }
+                _ => e.into(),
+            });
+        }
+
+        // the connection is reusable again once the body is read to its end
+        self.is_reusable = false;
+        match self.reader.fill_buf() {
+            Ok([]) => return Err(StaleConnection.into()),
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => {
+                return Err(StaleConnection.into())
+            }
+            Err(e) => return Err(e.into()),
+            Ok(_) => (),
+        }
+        let status_line = read_line(&mut self.reader)?;
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .ok_or(Error::msg("malformed status line"))?
+            .parse::<u16>()?;
+
+        let mut content_length = None;
+        let mut is_chunked = false;
+        let mut is_closed = false;
+        loop {
+            let line = read_line(&mut self.reader)?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                let value = value.trim();
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = Some(value.parse::<u64>()?);
+                } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                    is_chunked = value.eq_ignore_ascii_case("chunked");
+                } else if name.eq_ignore_ascii_case("connection") {
+                    is_closed = value.eq_ignore_ascii_case("close");
+                }
+            }
+        }
+
+        let is_reusable = &mut self.is_reusable;
+        let reader = &mut self.reader;
+        let body: Box<dyn Read + '_> = match (is_chunked, content_length) {
+            (true, _) => Box::new(EndOfBody::new(
+                ChunkedReader::new(reader),
+                is_reusable,
+                !is_closed,
+            )),
+            (false, Some(content_length)) => Box::new(EndOfBody::new(
+                reader.take(content_length),
+                is_reusable,
+                !is_closed,
+            )),
+            // the body ends with the connection
+            (false, None) => Box::new(reader),
+        };
+        Ok(HttpResponse {
+            status,
+            content_length,
+            body,
+        })
+    }
+
+    // false once the server closed the connection, or when the last response
+    // was not read to its end
+    pub fn is_reusable(&self) -> bool {
+        self.is_reusable
+    }
+}
+
+// marks the connection reusable when the body has been read to its end
+struct EndOfBody<'a, R: Read> {
+    inner: R,
+    is_reusable: &'a mut bool,
+    is_keep_alive: bool,
+}
+
+impl<'a, R: Read> EndOfBody<'a, R> {
+    fn new(inner: R, is_reusable: &'a mut bool, is_keep_alive: bool) -> EndOfBody<'a, R> {
+        EndOfBody {
+            inner,
+            is_reusable,
+            is_keep_alive,
+        }
+    }
+}
+
+impl<'a, R: Read> Read for EndOfBody<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        if bytes_read == 0 && !buf.is_empty() {
+            *self.is_reusable = self.is_keep_alive;
+        }
+        Ok(bytes_read)
+    }
+}
+
+// handshake with the CA of `tls` (or the web PKI roots), then public key
+// pinning; verification failures are reported as `TlsVerificationError`
+fn connect_tls(
+    stream: TcpStream,
+    host: &str,
+    tls: &TlsSettings,
+) -> Result<rustls::StreamOwned<rustls::ClientConnection, TcpStream>, Error> {
+    let mut roots = rustls::RootCertStore::empty();
+    match &tls.ca_pem {
+        Some(ca_pem) => {
+            for certificate in rustls_pemfile::certs(&mut ca_pem.as_bytes())? {
+                roots.add(&rustls::Certificate(certificate))?;
+            }
+        }
+        None => roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|root| {
+            rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                root.subject,
+                root.spki,
+                root.name_constraints,
+            )
+        })),
+    }
+    let configuration = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let server_name = rustls::ServerName::try_from(host)?;
+    let connection = rustls::ClientConnection::new(Arc::new(configuration), server_name)?;
+    let mut stream = rustls::StreamOwned::new(connection, stream);
+    while stream.conn.is_handshaking() {
+        if let Err(e) = stream.conn.complete_io(&mut stream.sock) {
+            let tls_error = e
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<rustls::Error>());
+            return match tls_error {
+                Some(tls_error) => {
+                    Err(TlsVerificationError(format!("{}: {}", host, tls_error)).into())
+                }
+                None => Err(e.into()),
+            };
+        }
+    }
+    let chain: Vec<&[u8]> = stream
+        .conn
+        .peer_certificates()
+        .unwrap_or_default()
+        .iter()
+        .map(|certificate| certificate.0.as_slice())
+        .collect();
+    tls.verify_pins(host, &chain)?;
+    Ok(stream)
+}
+
+// header or chunk size line, without the line terminator
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    reader.take(MAX_HEADER_LINE_SIZE).read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "truncated response line",
+        ));
+    }
+    Ok(line.trim_end().to_owned())
+}
+
+// decodes a `transfer-encoding: chunked` body
+pub struct ChunkedReader<R: BufRead> {
+    inner: R,
+    remaining: u64,
+    is_done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn new(inner: R) -> ChunkedReader<R> {
+        ChunkedReader {
+            inner,
+            remaining: 0,
+            is_done: false,
+        }
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.is_done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            let line = read_line(&mut self.inner)?;
+            let size = line.split(';').next().unwrap_or("").trim();
+            self.remaining = u64::from_str_radix(size, 16).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid chunk size: {}", line),
+                )
+            })?;
+            if self.remaining == 0 {
+                // trailers, up to the empty line
+                while !read_line(&mut self.inner)?.is_empty() {}
+                self.is_done = true;
+                return Ok(0);
+            }
+        }
+        let length = buf.len().min(self.remaining as usize);
+        let bytes_read = self.inner.read(&mut buf[..length])?;
+        if bytes_read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated chunk",
+            ));
+        }
+        self.remaining -= bytes_read as u64;
+        if self.remaining == 0 {
+            read_line(&mut self.inner)?;
+        }
+        Ok(bytes_read)
+    }
+}
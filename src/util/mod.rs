@@ -1 +1,10 @@
+pub mod clock_util;
+#[cfg(feature = "simulator")]
+pub mod http_util;
+pub mod response_util;
+pub mod retry_util;
+pub mod signature_util;
 pub mod thread_util;
+pub mod time_zone_util;
+pub mod tls_util;
+pub mod url_util;
@@ -0,0 +1,125 @@
+// Streaming access to response bodies: the body is read through a
+// `LimitedReader`, so that an oversized body is rejected instead of being
+// truncated or exhausting the heap.
+use std::io::{self, Read};
+
+// enough for a configuration with several schedules and endpoints
+pub const MAX_RESPONSE_SIZE: usize = 16 * 1024;
+// part of an error body kept for the logs
+const MAX_ERROR_BODY_SIZE: usize = 256;
+
+pub struct LimitedReader<R: Read> {
+    inner: R,
+    remaining: usize,
+    limit: usize,
+    is_exceeded: bool,
+}
+
+impl<R: Read> LimitedReader<R> {
+    pub fn new(inner: R, limit: usize) -> LimitedReader<R> {
+        LimitedReader {
+            inner,
+            remaining: limit,
+            limit,
+            is_exceeded: false,
+        }
+    }
+
+    // true once the body turned out to be longer than the limit
+    pub fn is_exceeded(&self) -> bool {
+        self.is_exceeded
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            // the limit is reached: the body is accepted only if it ends here
+            let mut probe = [0u8; 1];
+            if self.inner.read(&mut probe)? == 0 {
+                return Ok(0);
+            }
+            self.is_exceeded = true;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("response body larger than {} bytes", self.limit),
+            ));
+        }
+        let length = buf.len().min(self.remaining);
+        let bytes_read = self.inner.read(&mut buf[..length])?;
+        self.remaining -= bytes_read;
+        Ok(bytes_read)
+    }
+}
+
+// beginning of the body of an error response, for the logs
+pub fn read_error_body(reader: &mut dyn Read) -> String {
+    let mut body = Vec::new();
+    let _ = reader
+        .take(MAX_ERROR_BODY_SIZE as u64)
+        .read_to_end(&mut body);
+    String::from_utf8_lossy(&body).into_owned()
+}
+
+// `std::io::Read` over the body of an ESP-IDF HTTP response
+#[cfg(feature = "hal")]
+pub struct EmbeddedReader<R>(pub R);
+
+#[cfg(feature = "hal")]
+impl<R: embedded_svc::io::Read> Read for EmbeddedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0
+            .read(buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(body: &[u8], limit: usize) -> (io::Result<Vec<u8>>, bool) {
+        let mut reader = LimitedReader::new(body, limit);
+        let mut read = Vec::new();
+        let result = reader.read_to_end(&mut read).map(|_| read);
+        (result, reader.is_exceeded())
+    }
+
+    #[test]
+    fn reads_a_body_within_the_limit() {
+        let (result, is_exceeded) = read(b"{\"success\":true}", 64);
+        assert_eq!(result.unwrap(), b"{\"success\":true}");
+        assert!(!is_exceeded);
+    }
+
+    #[test]
+    fn accepts_a_body_of_the_exact_limit() {
+        let (result, is_exceeded) = read(b"0123456789", 10);
+        assert_eq!(result.unwrap(), b"0123456789");
+        assert!(!is_exceeded);
+    }
+
+    #[test]
+    fn refuses_a_longer_body() {
+        let (result, is_exceeded) = read(b"0123456789a", 10);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(is_exceeded);
+    }
+
+    #[test]
+    fn error_body_is_truncated() {
+        let body = "x".repeat(2 * MAX_ERROR_BODY_SIZE);
+        assert_eq!(
+            read_error_body(&mut body.as_bytes()),
+            body[..MAX_ERROR_BODY_SIZE]
+        );
+        assert_eq!(read_error_body(&mut &b"not found"[..]), "not found");
+    }
+}
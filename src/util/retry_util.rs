@@ -0,0 +1,290 @@
+use super::clock_util::Clock;
+use log::{info, warn};
+use serde::Deserialize;
+use std::{fmt::Display, time::Duration};
+
+// Exponential backoff between attempts: the n-th retry waits
+// min(maxDelayMillis, baseDelayMillis * 2^(n-1)), reduced by up to `jitter`
+// (0.0..=1.0) of itself. No retry is scheduled once `maxBlockingMillis` would
+// be exceeded, so a caller is blocked at most for that time plus the timeout
+// of one attempt.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay_millis: u64,
+    pub max_delay_millis: u64,
+    pub jitter: f64,
+    pub max_blocking_millis: u64,
+}
+
+// sent once at boot, before the detection loop
+pub const REGISTRATION_RETRY_POLICY: RetryPolicy = RetryPolicy {
+    max_attempts: 5,
+    base_delay_millis: 1000,
+    max_delay_millis: 16000,
+    jitter: 0.2,
+    max_blocking_millis: 60000,
+};
+pub const CONFIGURATION_RETRY_POLICY: RetryPolicy = RetryPolicy {
+    max_attempts: 5,
+    base_delay_millis: 1000,
+    max_delay_millis: 16000,
+    jitter: 0.2,
+    max_blocking_millis: 60000,
+};
+// sent from the detection loop: short, so that the sensor keeps being sampled
+pub const ALERT_RETRY_POLICY: RetryPolicy = RetryPolicy {
+    max_attempts: 3,
+    base_delay_millis: 500,
+    max_delay_millis: 2000,
+    jitter: 0.2,
+    max_blocking_millis: 3000,
+};
+pub const I_AM_ALIVE_RETRY_POLICY: RetryPolicy = RetryPolicy {
+    max_attempts: 2,
+    base_delay_millis: 500,
+    max_delay_millis: 500,
+    jitter: 0.2,
+    max_blocking_millis: 1000,
+};
+
+impl RetryPolicy {
+    // delay before the given retry (1 for the first retry), without jitter
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u64
+            .checked_shl(retry.saturating_sub(1))
+            .unwrap_or(u64::MAX);
+        let delay = self.base_delay_millis.saturating_mul(factor);
+        Duration::from_millis(delay.min(self.max_delay_millis))
+    }
+
+    // `random` in 0.0..1.0 picks the jitter
+    pub fn delay(&self, retry: u32, random: f64) -> Duration {
+        let jitter = self.jitter.clamp(0.0, 1.0) * random.clamp(0.0, 1.0);
+        self.backoff(retry).mul_f64(1.0 - jitter)
+    }
+}
+
+// errors that sending the same request again cannot fix are not retried
+pub trait Retryable {
+    fn is_retryable(&self) -> bool;
+}
+
+impl Retryable for anyhow::Error {
+    fn is_retryable(&self) -> bool {
+        true
+    }
+}
+
+// runs `attempt` until it succeeds, fails with an error that is not
+// retryable, the attempts are exhausted or the blocking budget is spent;
+// returns the last error
+pub fn retry<T, E: Retryable + Display>(
+    clock: &dyn Clock,
+    policy: &RetryPolicy,
+    name: &str,
+    attempt: impl FnMut() -> Result<T, E>,
+) -> Result<T, E> {
+    let deadline = clock.elapsed() + Duration::from_millis(policy.max_blocking_millis);
+    retry_until(clock, policy, name, deadline, attempt)
+}
+
+// `retry` with a budget ending at `deadline` (a `clock.elapsed()` value), so
+// that several requests can share it
+pub fn retry_until<T, E: Retryable + Display>(
+    clock: &dyn Clock,
+    policy: &RetryPolicy,
+    name: &str,
+    deadline: Duration,
+    mut attempt: impl FnMut() -> Result<T, E>,
+) -> Result<T, E> {
+    let mut random = Random::new(clock);
+    let mut attempt_number = 1;
+    loop {
+        let error = match attempt() {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        if !error.is_retryable() {
+            warn!("[{}] attempt {} failed: {}", name, attempt_number, error);
+            return Err(error);
+        }
+        if attempt_number >= policy.max_attempts {
+            warn!("[{}] attempt {} failed, giving up", name, attempt_number);
+            return Err(error);
+        }
+        let delay = policy.delay(attempt_number, random.next());
+        if clock.elapsed() + delay > deadline {
+            warn!(
+                "[{}] attempt {} failed, no time left for a retry",
+                name, attempt_number
+            );
+            return Err(error);
+        }
+        info!(
+            "[{}] attempt {} failed, retrying in {:?}",
+            name, attempt_number, delay
+        );
+        clock.sleep(delay);
+        attempt_number += 1;
+    }
+}
+
+// xorshift64 seeded from the clock: enough to spread the retries of devices
+// restarted together, without an entropy source
+struct Random {
+    state: u64,
+}
+
+impl Random {
+    fn new(clock: &dyn Clock) -> Random {
+        let seed = clock.now().timestamp_nanos_opt().unwrap_or(0) as u64
+            ^ clock.elapsed().as_nanos() as u64;
+        Random { state: seed | 1 }
+    }
+
+    fn next(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::clock_util::VirtualClock;
+    use chrono::{TimeZone, Utc};
+    use std::cell::Cell;
+
+    struct TestError {
+        is_retryable: bool,
+    }
+
+    impl Retryable for TestError {
+        fn is_retryable(&self) -> bool {
+            self.is_retryable
+        }
+    }
+
+    impl Display for TestError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "retryable: {}", self.is_retryable)
+        }
+    }
+
+    const POLICY: RetryPolicy = RetryPolicy {
+        max_attempts: 4,
+        base_delay_millis: 100,
+        max_delay_millis: 300,
+        jitter: 0.0,
+        max_blocking_millis: 10000,
+    };
+
+    fn clock() -> VirtualClock {
+        VirtualClock::new(Utc.with_ymd_and_hms(2023, 11, 20, 10, 0, 0).unwrap())
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        assert_eq!(POLICY.backoff(1), Duration::from_millis(100));
+        assert_eq!(POLICY.backoff(2), Duration::from_millis(200));
+        assert_eq!(POLICY.backoff(3), Duration::from_millis(300));
+        assert_eq!(POLICY.backoff(100), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn jitter_shortens_the_delay() {
+        let policy = RetryPolicy {
+            jitter: 0.5,
+            ..POLICY
+        };
+        assert_eq!(policy.delay(2, 0.0), Duration::from_millis(200));
+        assert_eq!(policy.delay(2, 0.5), Duration::from_millis(150));
+        // out of range values are clamped
+        assert_eq!(policy.delay(2, 2.0), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn retries_until_success() {
+        let clock = clock();
+        let attempts = Cell::new(0);
+        let result = retry(&clock, &POLICY, "test", || {
+            attempts.set(attempts.get() + 1);
+            match attempts.get() {
+                3 => Ok(attempts.get()),
+                _ => Err(TestError { is_retryable: true }),
+            }
+        });
+        assert!(matches!(result, Ok(3)));
+        assert_eq!(clock.elapsed(), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn gives_up_after_the_last_attempt() {
+        let clock = clock();
+        let attempts = Cell::new(0);
+        let result: Result<(), TestError> = retry(&clock, &POLICY, "test", || {
+            attempts.set(attempts.get() + 1);
+            Err(TestError { is_retryable: true })
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 4);
+        assert_eq!(clock.elapsed(), Duration::from_millis(600));
+    }
+
+    #[test]
+    fn does_not_retry_a_permanent_error() {
+        let clock = clock();
+        let attempts = Cell::new(0);
+        let result: Result<(), TestError> = retry(&clock, &POLICY, "test", || {
+            attempts.set(attempts.get() + 1);
+            Err(TestError {
+                is_retryable: false,
+            })
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+        assert_eq!(clock.elapsed(), Duration::ZERO);
+    }
+
+    #[test]
+    fn stops_when_the_blocking_budget_is_spent() {
+        let clock = clock();
+        let policy = RetryPolicy {
+            max_blocking_millis: 350,
+            ..POLICY
+        };
+        let attempts = Cell::new(0);
+        let result: Result<(), TestError> = retry(&clock, &policy, "test", || {
+            attempts.set(attempts.get() + 1);
+            // each attempt takes as long as its timeout
+            clock.advance(Duration::from_millis(50));
+            Err(TestError { is_retryable: true })
+        });
+        assert!(result.is_err());
+        // 50 + 100 + 50, then 200 more would exceed 350
+        assert_eq!(attempts.get(), 2);
+        assert!(clock.elapsed() <= Duration::from_millis(350));
+    }
+
+    #[test]
+    fn requests_share_a_deadline() {
+        let clock = clock();
+        let deadline = Duration::from_millis(350);
+        let attempts = Cell::new(0);
+        for _ in 0..2 {
+            let result: Result<(), TestError> =
+                retry_until(&clock, &POLICY, "test", deadline, || {
+                    attempts.set(attempts.get() + 1);
+                    Err(TestError { is_retryable: true })
+                });
+            assert!(result.is_err());
+        }
+        // 0, 100 and 300 for the first request, 300 for the second one
+        assert_eq!(attempts.get(), 4);
+        assert_eq!(clock.elapsed(), Duration::from_millis(300));
+    }
+}
@@ -0,0 +1,202 @@
+// HMAC-SHA256 signature of the alert and IAmAlive bodies, with the secret
+// shared by the device and the server. The signed message is the canonical
+// form
+//
+// v1\n<timestamp>\n<nonce>\n<body>
+//
+// where the timestamp is in seconds since the epoch, the nonce is a decimal
+// integer greater than every nonce sent before by the device, and the body
+// is sent byte for byte as signed. The timestamp, the nonce and the base64
+// signature are sent in the `x-elisys-*` headers; the server rejects a wrong
+// signature, an old timestamp and a nonce it has already seen.
+//
+// docs/signature_test_vectors.json gives, for each test vector, the secret,
+// the timestamp, the nonce and the body, their canonical form and the
+// expected signature, to check a server implementation; the tests below
+// check the device against them.
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+pub const TIMESTAMP_HEADER: &str = "x-elisys-timestamp";
+pub const NONCE_HEADER: &str = "x-elisys-nonce";
+pub const SIGNATURE_HEADER: &str = "x-elisys-signature";
+const CANONICAL_VERSION: &str = "v1";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestSignature {
+    pub timestamp: i64,
+    pub nonce: u64,
+    // base64 HMAC-SHA256 of the canonical form
+    pub signature: String,
+}
+
+impl RequestSignature {
+    pub fn headers(&self) -> [(&'static str, String); 3] {
+        [
+            (TIMESTAMP_HEADER, self.timestamp.to_string()),
+            (NONCE_HEADER, self.nonce.to_string()),
+            (SIGNATURE_HEADER, self.signature.clone()),
+        ]
+    }
+}
+
+// signs the requests of one device; the nonce starts from the time of the
+// first request in milliseconds, so that it keeps increasing across reboots
+// without being stored
+pub struct RequestSigner {
+    secret: Vec<u8>,
+    last_nonce: u64,
+}
+
+impl RequestSigner {
+    pub fn new(secret: &[u8]) -> RequestSigner {
+        RequestSigner {
+            secret: secret.to_vec(),
+            last_nonce: 0,
+        }
+    }
+
+    pub fn sign(&mut self, timestamp_millis: i64, body: &[u8]) -> RequestSignature {
+        let nonce = (self.last_nonce + 1).max(timestamp_millis.max(0) as u64);
+        self.last_nonce = nonce;
+        let timestamp = timestamp_millis.div_euclid(1000);
+        RequestSignature {
+            timestamp,
+            nonce,
+            signature: signature(&self.secret, timestamp, nonce, body),
+        }
+    }
+}
+
+pub fn signature(secret: &[u8], timestamp: i64, nonce: u64, body: &[u8]) -> String {
+    STANDARD.encode(mac(secret, timestamp, nonce, body).finalize().into_bytes())
+}
+
+// constant-time comparison, for the servers written in Rust (the stand-in)
+pub fn is_valid(secret: &[u8], timestamp: i64, nonce: u64, body: &[u8], signature: &str) -> bool {
+    match STANDARD.decode(signature) {
+        Ok(signature) => mac(secret, timestamp, nonce, body)
+            .verify_slice(&signature)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+fn mac(secret: &[u8], timestamp: i64, nonce: u64, body: &[u8]) -> Hmac<Sha256> {
+    // HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+    mac.update(format!("{}\n{}\n{}\n", CANONICAL_VERSION, timestamp, nonce).as_bytes());
+    mac.update(body);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct TestVectors {
+        vectors: Vec<TestVector>,
+    }
+
+    #[derive(Deserialize)]
+    struct TestVector {
+        description: String,
+        secret: String,
+        timestamp: i64,
+        nonce: u64,
+        body: String,
+        canonical: String,
+        signature: String,
+    }
+
+    fn vectors() -> Vec<TestVector> {
+        serde_json::from_str::<TestVectors>(include_str!("../../docs/signature_test_vectors.json"))
+            .unwrap()
+            .vectors
+    }
+
+    #[test]
+    fn matches_the_test_vectors() {
+        for vector in vectors() {
+            assert_eq!(
+                signature(
+                    vector.secret.as_bytes(),
+                    vector.timestamp,
+                    vector.nonce,
+                    vector.body.as_bytes()
+                ),
+                vector.signature,
+                "{}",
+                vector.description
+            );
+        }
+    }
+
+    #[test]
+    fn canonical_form_of_the_test_vectors() {
+        for vector in vectors() {
+            assert_eq!(
+                vector.canonical,
+                format!(
+                    "{}\n{}\n{}\n{}",
+                    CANONICAL_VERSION, vector.timestamp, vector.nonce, vector.body
+                ),
+                "{}",
+                vector.description
+            );
+            // the signature is the HMAC of the canonical form alone
+            let mut mac = Hmac::<Sha256>::new_from_slice(vector.secret.as_bytes()).unwrap();
+            mac.update(vector.canonical.as_bytes());
+            assert_eq!(
+                STANDARD.encode(mac.finalize().into_bytes()),
+                vector.signature,
+                "{}",
+                vector.description
+            );
+        }
+    }
+
+    #[test]
+    fn verifies_the_test_vectors() {
+        for vector in vectors() {
+            let is_valid = |secret: &str, nonce: u64, body: &str| {
+                is_valid(
+                    secret.as_bytes(),
+                    vector.timestamp,
+                    nonce,
+                    body.as_bytes(),
+                    &vector.signature,
+                )
+            };
+            assert!(is_valid(&vector.secret, vector.nonce, &vector.body));
+            assert!(!is_valid("wrong secret", vector.nonce, &vector.body));
+            assert!(!is_valid(&vector.secret, vector.nonce + 1, &vector.body));
+            assert!(!is_valid(&vector.secret, vector.nonce, "{}"));
+        }
+        assert!(!is_valid(b"secret", 0, 0, b"", "not base64!"));
+    }
+
+    #[test]
+    fn nonces_keep_increasing() {
+        let mut signer = RequestSigner::new(b"device secret");
+        let first = signer.sign(1700474400000, b"{}");
+        assert_eq!(first.timestamp, 1700474400);
+        assert_eq!(first.nonce, 1700474400000);
+        // same millisecond, then a clock set back
+        assert_eq!(signer.sign(1700474400000, b"{}").nonce, 1700474400001);
+        assert_eq!(signer.sign(1600000000000, b"{}").nonce, 1700474400002);
+        assert_eq!(signer.sign(1700474401000, b"{}").nonce, 1700474401000);
+    }
+
+    #[test]
+    fn headers_carry_the_signature() {
+        let signature = RequestSigner::new(b"device secret").sign(1700474400000, b"{}");
+        let headers = signature.headers();
+        assert_eq!(headers[0], (TIMESTAMP_HEADER, "1700474400".to_owned()));
+        assert_eq!(headers[1], (NONCE_HEADER, "1700474400000".to_owned()));
+        assert_eq!(headers[2], (SIGNATURE_HEADER, signature.signature.clone()));
+    }
+}
@@ -0,0 +1,174 @@
+use chrono::{DateTime, Duration, FixedOffset, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+use log::warn;
+
+// Time zone in which the crontab is evaluated: an IANA name (daylight
+// saving aware) when the configuration provides a known one, otherwise the
+// fixed offset sent by the server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScheduleTimeZone {
+    Fixed(FixedOffset),
+    Named(Tz),
+}
+
+impl ScheduleTimeZone {
+    pub fn from_configuration(timezone: Option<&str>, timezone_offset: i32) -> ScheduleTimeZone {
+        if let Some(name) = timezone {
+            match name.parse::<Tz>() {
+                Ok(tz) => return ScheduleTimeZone::Named(tz),
+                Err(_) => warn!(
+                    "unknown timezone {}, falling back to the offset of {} seconds",
+                    name, timezone_offset
+                ),
+            }
+        }
+        match FixedOffset::east_opt(timezone_offset) {
+            Some(offset) => ScheduleTimeZone::Fixed(offset),
+            None => {
+                warn!("invalid timezone offset {}, using UTC", timezone_offset);
+                ScheduleTimeZone::Fixed(FixedOffset::east_opt(0).unwrap())
+            }
+        }
+    }
+
+    pub fn local_time(&self, instant: &DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self {
+            ScheduleTimeZone::Fixed(offset) => instant.with_timezone(offset),
+            ScheduleTimeZone::Named(tz) => instant.with_timezone(tz).fixed_offset(),
+        }
+    }
+
+    // first tick of the schedule strictly after the instant
+    pub fn next_after(
+        &self,
+        schedule: &Schedule,
+        instant: &DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        match self {
+            ScheduleTimeZone::Fixed(offset) => next_after(schedule, instant, offset),
+            ScheduleTimeZone::Named(tz) => next_after(schedule, instant, tz),
+        }
+    }
+
+    // last tick of the schedule at or before the instant
+    pub fn previous_at_or_before(
+        &self,
+        schedule: &Schedule,
+        instant: &DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        match self {
+            ScheduleTimeZone::Fixed(offset) => previous_at_or_before(schedule, instant, offset),
+            ScheduleTimeZone::Named(tz) => previous_at_or_before(schedule, instant, tz),
+        }
+    }
+
+    pub fn includes(&self, schedule: &Schedule, instant: &DateTime<Utc>) -> bool {
+        match self {
+            ScheduleTimeZone::Fixed(offset) => schedule.includes(instant.with_timezone(offset)),
+            ScheduleTimeZone::Named(tz) => schedule.includes(instant.with_timezone(tz)),
+        }
+    }
+}
+
+fn next_after<Z: TimeZone>(
+    schedule: &Schedule,
+    instant: &DateTime<Utc>,
+    time_zone: &Z,
+) -> Option<DateTime<Utc>> {
+    schedule
+        .after(&instant.with_timezone(time_zone))
+        .next()
+        .map(|date_time| date_time.with_timezone(&Utc))
+}
+
+fn previous_at_or_before<Z: TimeZone>(
+    schedule: &Schedule,
+    instant: &DateTime<Utc>,
+    time_zone: &Z,
+) -> Option<DateTime<Utc>> {
+    // cron looks strictly before the given second
+    let bound = instant.with_nanosecond(0).unwrap() + Duration::seconds(1);
+    schedule
+        .after(&bound.with_timezone(time_zone))
+        .next_back()
+        .map(|date_time| date_time.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    // every second from 8:00 to 8:59, local time
+    fn eight_o_clock() -> Schedule {
+        Schedule::from_str("* * 8 * * * *").unwrap()
+    }
+
+    #[test]
+    fn prefers_a_known_time_zone_name() {
+        assert_eq!(
+            ScheduleTimeZone::from_configuration(Some("Europe/Rome"), 3600),
+            ScheduleTimeZone::Named(chrono_tz::Europe::Rome)
+        );
+        assert_eq!(
+            ScheduleTimeZone::from_configuration(Some("Mars/Olympus"), 3600),
+            ScheduleTimeZone::Fixed(FixedOffset::east_opt(3600).unwrap())
+        );
+        assert_eq!(
+            ScheduleTimeZone::from_configuration(None, 100000),
+            ScheduleTimeZone::Fixed(FixedOffset::east_opt(0).unwrap())
+        );
+    }
+
+    #[test]
+    fn named_time_zone_follows_daylight_saving() {
+        let time_zone = ScheduleTimeZone::from_configuration(Some("Europe/Rome"), 3600);
+        let schedule = eight_o_clock();
+        // 8:30 in Rome: UTC+1 in winter, UTC+2 in summer
+        assert!(time_zone.includes(&schedule, &utc(2023, 1, 16, 7, 30)));
+        assert!(time_zone.includes(&schedule, &utc(2023, 7, 17, 6, 30)));
+        assert!(!time_zone.includes(&schedule, &utc(2023, 7, 17, 7, 30)));
+    }
+
+    #[test]
+    fn fixed_offset_ignores_daylight_saving() {
+        let time_zone = ScheduleTimeZone::from_configuration(None, 3600);
+        let schedule = eight_o_clock();
+        assert!(time_zone.includes(&schedule, &utc(2023, 1, 16, 7, 30)));
+        assert!(time_zone.includes(&schedule, &utc(2023, 7, 17, 7, 30)));
+        assert_eq!(
+            time_zone.local_time(&utc(2023, 7, 17, 7, 30)).to_rfc3339(),
+            "2023-07-17T08:30:00+01:00"
+        );
+    }
+
+    #[test]
+    fn finds_the_ticks_around_an_instant() {
+        let time_zone = ScheduleTimeZone::from_configuration(Some("Europe/Rome"), 0);
+        let schedule = Schedule::from_str("0 0 8 * * * *").unwrap();
+        let tick = utc(2023, 7, 17, 6, 0);
+        assert_eq!(
+            time_zone.next_after(&schedule, &utc(2023, 7, 17, 5, 0)),
+            Some(tick)
+        );
+        assert_eq!(
+            time_zone.next_after(&schedule, &tick),
+            Some(utc(2023, 7, 18, 6, 0))
+        );
+        // the tick itself counts as at or before
+        assert_eq!(
+            time_zone.previous_at_or_before(&schedule, &tick),
+            Some(tick)
+        );
+        assert_eq!(
+            time_zone.previous_at_or_before(&schedule, &utc(2023, 7, 17, 5, 59)),
+            Some(utc(2023, 7, 16, 6, 0))
+        );
+    }
+}
@@ -0,0 +1,461 @@
+// TLS settings of an endpoint: the trusted CAs (the ESP-IDF bundle, or the
+// web PKI roots on the host, unless a PEM is configured) and the optional
+// SHA-256 pins of the server public key (SubjectPublicKeyInfo), in base64 as
+// produced by:
+//
+// openssl x509 -in cert.pem -pubkey -noout | openssl pkey -pubin -outform der \
+//     | openssl dgst -sha256 -binary | base64
+use crate::{
+    config::config::{SERVER_CA_PEM, SERVER_SPKI_SHA256},
+    dto::config_response::TlsConfiguration,
+};
+use anyhow::Error;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::warn;
+use sha2::{Digest, Sha256};
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TlsSettings {
+    // PEM of the CA (or self-signed certificate) trusted in place of the bundle
+    pub ca_pem: Option<String>,
+    pub pins: Vec<[u8; 32]>,
+    // true when pins were configured, even if none of them could be decoded:
+    // the connection is then refused rather than left unpinned
+    pub is_pinned: bool,
+}
+
+impl TlsSettings {
+    pub fn new(ca_pem: Option<&str>, spki_sha256: &[&str]) -> TlsSettings {
+        let pins = spki_sha256
+            .iter()
+            .filter_map(|pin| match decode_pin(pin) {
+                Ok(pin) => Some(pin),
+                Err(e) => {
+                    warn!("ignoring public key pin {}: {}", pin, e);
+                    None
+                }
+            })
+            .collect();
+        TlsSettings {
+            ca_pem: ca_pem.map(|ca_pem| ca_pem.to_owned()),
+            pins,
+            is_pinned: !spki_sha256.is_empty(),
+        }
+    }
+
+    // settings of the registration and configuration endpoints
+    pub fn server() -> TlsSettings {
+        TlsSettings::new(SERVER_CA_PEM, SERVER_SPKI_SHA256)
+    }
+
+    // an endpoint without TLS configuration uses the settings of the server
+    pub fn from_configuration(configuration: Option<&TlsConfiguration>) -> TlsSettings {
+        match configuration {
+            None => TlsSettings::server(),
+            Some(configuration) => {
+                let pins: Vec<&str> = configuration
+                    .spki_sha256
+                    .iter()
+                    .map(|pin| pin.as_str())
+                    .collect();
+                TlsSettings::new(configuration.ca_pem.as_deref(), &pins)
+            }
+        }
+    }
+
+    // `chain` is the DER of the certificates sent by the server, leaf first;
+    // the connection is accepted when one of them has a pinned public key
+    pub fn verify_pins(&self, host: &str, chain: &[&[u8]]) -> Result<(), TlsVerificationError> {
+        if !self.is_pinned {
+            return Ok(());
+        }
+        let mut found = Vec::new();
+        for certificate in chain {
+            let hash = spki_sha256(certificate)
+                .map_err(|e| TlsVerificationError(format!("{}: {}", host, e)))?;
+            if self.pins.contains(&hash) {
+                return Ok(());
+            }
+            found.push(STANDARD.encode(hash));
+        }
+        Err(TlsVerificationError(format!(
+            "{}: no pinned public key in the certificate chain (found {:?})",
+            host, found
+        )))
+    }
+}
+
+// the server certificate could not be verified: wrong CA, expired
+// certificate, host name mismatch or pinned key missing
+#[derive(Debug)]
+pub struct TlsVerificationError(pub String);
+
+impl Display for TlsVerificationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "TLS verification failed for {}", self.0)
+    }
+}
+
+impl std::error::Error for TlsVerificationError {}
+
+pub fn spki_sha256(certificate: &[u8]) -> Result<[u8; 32], Error> {
+    let spki = subject_public_key_info(certificate)?;
+    Ok(Sha256::digest(spki).into())
+}
+
+fn decode_pin(pin: &str) -> Result<[u8; 32], Error> {
+    let bytes = STANDARD.decode(pin.trim())?;
+    bytes
+        .try_into()
+        .map_err(|bytes: Vec<u8>| Error::msg(format!("{} bytes instead of 32", bytes.len())))
+}
+
+// Certificate ::= SEQUENCE { tbsCertificate, signatureAlgorithm, signature }
+// TBSCertificate ::= SEQUENCE { [0] version OPTIONAL, serialNumber,
+//     signature, issuer, validity, subject, subjectPublicKeyInfo, ... }
+fn subject_public_key_info(certificate: &[u8]) -> Result<&[u8], Error> {
+    let (certificate, _) = read_element(certificate, 0x30)?;
+    let (tbs_certificate, _) = read_element(content(certificate), 0x30)?;
+    let mut rest = content(tbs_certificate);
+    if rest.first() == Some(&0xa0) {
+        rest = read_element(rest, 0xa0)?.1;
+    }
+    // serial number, signature algorithm, issuer, validity, subject
+    for tag in [0x02, 0x30, 0x30, 0x30, 0x30] {
+        rest = read_element(rest, tag)?.1;
+    }
+    let (spki, _) = read_element(rest, 0x30)?;
+    Ok(spki)
+}
+
+// returns the element (header included) and the input that follows it
+fn read_element(input: &[u8], expected_tag: u8) -> Result<(&[u8], &[u8]), Error> {
+    let malformed = || Error::msg("malformed certificate");
+    let tag = *input.first().ok_or_else(malformed)?;
+    if tag != expected_tag {
+        return Err(Error::msg(format!(
+            "malformed certificate: tag {:#x} instead of {:#x}",
+            tag, expected_tag
+        )));
+    }
+    let first_length_byte = *input.get(1).ok_or_else(malformed)?;
+    let length = if first_length_byte < 0x80 {
+        first_length_byte as usize
+    } else {
+        let length_bytes = (first_length_byte & 0x7f) as usize;
+        if length_bytes == 0 || length_bytes > 4 {
+            return Err(malformed());
+        }
+        input
+            .get(2..2 + length_bytes)
+            .ok_or_else(malformed)?
+            .iter()
+            .fold(0usize, |length, byte| (length << 8) | *byte as usize)
+    };
+    let end = header_length(input)
+        .checked_add(length)
+        .ok_or_else(malformed)?;
+    if end > input.len() {
+        return Err(malformed());
+    }
+    Ok(input.split_at(end))
+}
+
+// content of an element returned by `read_element`
+fn content(element: &[u8]) -> &[u8] {
+    &element[header_length(element)..]
+}
+
+fn header_length(element: &[u8]) -> usize {
+    match element[1] {
+        length if length < 0x80 => 2,
+        length => 2 + (length & 0x7f) as usize,
+    }
+}
+
+// Trust settings applied by the ESP-IDF HTTP client to every connection it
+// opens, reconnections included. The client neither takes a CA per request
+// nor exposes the server certificate, but esp-tls hands the mbedtls
+// configuration of each handshake to `crt_bundle_attach`: `attach` installs
+// there the configured CA (or the bundle) and a verify callback checking the
+// pins, so they are verified on the connection that carries the request.
+#[cfg(feature = "hal")]
+pub struct EspTlsTrust {
+    settings: TlsSettings,
+    // the configured CA, parsed once; null when the bundle is used
+    ca_chain: *mut esp_idf_sys::mbedtls_x509_crt,
+}
+
+#[cfg(feature = "hal")]
+impl EspTlsTrust {
+    pub fn new(settings: &TlsSettings) -> Result<EspTlsTrust, Error> {
+        let mut trust = EspTlsTrust {
+            settings: settings.clone(),
+            ca_chain: std::ptr::null_mut(),
+        };
+        if let Some(ca_pem) = &settings.ca_pem {
+            // the PEM parser of mbedtls expects the terminating NUL
+            let ca_pem = std::ffi::CString::new(ca_pem.as_str())?;
+            let ca_pem = ca_pem.as_bytes_with_nul();
+            trust.ca_chain = Box::into_raw(Box::default());
+            let result = unsafe {
+                esp_idf_sys::mbedtls_x509_crt_init(trust.ca_chain);
+                esp_idf_sys::mbedtls_x509_crt_parse(trust.ca_chain, ca_pem.as_ptr(), ca_pem.len())
+            };
+            if result != 0 {
+                return Err(Error::msg(format!(
+                    "cannot parse the CA certificate: mbedtls error {:#x}",
+                    result.unsigned_abs()
+                )));
+            }
+        }
+        Ok(trust)
+    }
+
+    pub fn settings(&self) -> &TlsSettings {
+        &self.settings
+    }
+
+    pub fn http_configuration(&self, is_https: bool) -> esp_idf_svc::http::client::Configuration {
+        esp_idf_svc::http::client::Configuration {
+            crt_bundle_attach: if is_https { Some(attach) } else { None },
+            ..Default::default()
+        }
+    }
+
+    // runs `exchange`, whose connections are verified with this trust; a
+    // connection refused because of the pins fails with the
+    // `TlsVerificationError`, the ESP-IDF client only reports a failed
+    // connection
+    pub fn run<T>(
+        &self,
+        host: &str,
+        exchange: impl FnOnce() -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        ACTIVE_TRUST.with(|active| {
+            *active.borrow_mut() = Some(ActiveTrust {
+                settings: self.settings.clone(),
+                ca_chain: self.ca_chain,
+                host: host.to_owned(),
+                chain: Vec::new(),
+                error: None,
+            })
+        });
+        let result = exchange();
+        let active = ACTIVE_TRUST.with(|active| active.borrow_mut().take());
+        match (result, active.and_then(|active| active.error)) {
+            (Err(_), Some(error)) => Err(error.into()),
+            (result, _) => result,
+        }
+    }
+}
+
+// the connections referring to the CA must be closed first
+#[cfg(feature = "hal")]
+impl Drop for EspTlsTrust {
+    fn drop(&mut self) {
+        if !self.ca_chain.is_null() {
+            unsafe {
+                esp_idf_sys::mbedtls_x509_crt_free(self.ca_chain);
+                drop(Box::from_raw(self.ca_chain));
+            }
+        }
+    }
+}
+
+// trust of the exchange running on this thread: the callbacks of esp-tls
+// take no argument of ours, and run on the thread of the request
+#[cfg(feature = "hal")]
+struct ActiveTrust {
+    settings: TlsSettings,
+    ca_chain: *mut esp_idf_sys::mbedtls_x509_crt,
+    host: String,
+    // DER of the certificates verified so far in the current handshake
+    chain: Vec<Vec<u8>>,
+    error: Option<TlsVerificationError>,
+}
+
+#[cfg(feature = "hal")]
+thread_local! {
+    static ACTIVE_TRUST: std::cell::RefCell<Option<ActiveTrust>> = std::cell::RefCell::new(None);
+}
+
+#[cfg(feature = "hal")]
+extern "C" {
+    // verify callback installed by `esp_crt_bundle_attach`, which checks the
+    // chain against the bundle (global in esp_crt_bundle.c, not in its header)
+    fn esp_crt_verify_callback(
+        buf: *mut core::ffi::c_void,
+        certificate: *mut esp_idf_sys::mbedtls_x509_crt,
+        depth: core::ffi::c_int,
+        flags: *mut u32,
+    ) -> core::ffi::c_int;
+}
+
+// called by esp-tls on the configuration of each handshake, after it has made
+// the verification of the server certificate mandatory
+#[cfg(feature = "hal")]
+unsafe extern "C" fn attach(conf: *mut core::ffi::c_void) -> esp_idf_sys::esp_err_t {
+    ACTIVE_TRUST.with(|active| {
+        let mut active = active.borrow_mut();
+        let active = match active.as_mut() {
+            Some(active) => active,
+            // a connection opened outside of `EspTlsTrust::run` is refused
+            None => return esp_idf_sys::ESP_FAIL,
+        };
+        let conf = conf as *mut esp_idf_sys::mbedtls_ssl_config;
+        if active.ca_chain.is_null() {
+            let result = esp_idf_sys::esp_crt_bundle_attach(conf as *mut core::ffi::c_void);
+            if result != esp_idf_sys::ESP_OK {
+                return result;
+            }
+        } else {
+            esp_idf_sys::mbedtls_ssl_conf_ca_chain(conf, active.ca_chain, std::ptr::null_mut());
+        }
+        if active.settings.is_pinned {
+            active.chain.clear();
+            esp_idf_sys::mbedtls_ssl_conf_verify(conf, Some(verify_pins), std::ptr::null_mut());
+        }
+        esp_idf_sys::ESP_OK
+    })
+}
+
+// mbedtls calls it for each certificate of the chain, from the top down to
+// the server certificate (depth 0), where the pins are checked
+#[cfg(feature = "hal")]
+unsafe extern "C" fn verify_pins(
+    _: *mut core::ffi::c_void,
+    certificate: *mut esp_idf_sys::mbedtls_x509_crt,
+    depth: core::ffi::c_int,
+    flags: *mut u32,
+) -> core::ffi::c_int {
+    ACTIVE_TRUST.with(|active| {
+        let mut active = active.borrow_mut();
+        let active = match active.as_mut() {
+            Some(active) => active,
+            None => return esp_idf_sys::MBEDTLS_ERR_X509_FATAL_ERROR,
+        };
+        // replaces the verify callback of the bundle
+        if active.ca_chain.is_null() {
+            let result = esp_crt_verify_callback(std::ptr::null_mut(), certificate, depth, flags);
+            if result != 0 {
+                return result;
+            }
+        }
+        let raw = &(*certificate).raw;
+        if !raw.p.is_null() {
+            active
+                .chain
+                .push(core::slice::from_raw_parts(raw.p, raw.len as usize).to_vec());
+        }
+        if depth == 0 {
+            active.chain.reverse();
+            let chain: Vec<&[u8]> = active.chain.iter().map(|der| der.as_slice()).collect();
+            if let Err(e) = active.settings.verify_pins(&active.host, &chain) {
+                *flags |= esp_idf_sys::MBEDTLS_X509_BADCERT_NOT_TRUSTED;
+                active.error = Some(e);
+            }
+            active.chain.clear();
+        }
+        0
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // self-signed P-256 certificate of `stand-in.local`
+    const CERTIFICATE_PEM: &str = "
+        MIIBiTCCAS+gAwIBAgIUUoInkAwdzOiGax666TDGnKGGwLgwCgYIKoZIzj0EAwIw
+        GTEXMBUGA1UEAwwOc3RhbmQtaW4ubG9jYWwwIBcNMjYxMDE4MTE0ODIzWhgPMjEy
+        NjA5MjQxMTQ4MjNaMBkxFzAVBgNVBAMMDnN0YW5kLWluLmxvY2FsMFkwEwYHKoZI
+        zj0CAQYIKoZIzj0DAQcDQgAEnbmORdVSGqoBcJh6cVhJF+1UdoOOHLZ3mL+0xTim
+        AreoMvo8GR8Q1Muft6roTHFjOlksp0/t1poVwK4qO3NdiaNTMFEwHQYDVR0OBBYE
+        FPkk8Ksje7hCR1kduP3GNQJJTvAkMB8GA1UdIwQYMBaAFPkk8Ksje7hCR1kduP3G
+        NQJJTvAkMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSAAwRQIhAOGb+9h1
+        Mf0p/ul93G0bvfikDcZ/mlSJigo2Dre34qzCAiAboGD7mBBJve7/kKmDmyMdXXCK
+        qIToYTvhPrM0QvBDRQ==";
+    // computed with the openssl command at the top of the file
+    const CERTIFICATE_PIN: &str = "SXEyTmXX2eDEeQK/Di4b3Fr5JP/RVZ4dUB2oCbyIVew=";
+    const OTHER_PIN: &str = "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+
+    fn certificate() -> Vec<u8> {
+        let base64: String = CERTIFICATE_PEM.split_whitespace().collect();
+        STANDARD.decode(base64).unwrap()
+    }
+
+    #[test]
+    fn known_certificate_gives_its_spki_sha256() {
+        let hash = spki_sha256(&certificate()).unwrap();
+        assert_eq!(STANDARD.encode(hash), CERTIFICATE_PIN);
+    }
+
+    #[test]
+    fn truncated_certificate_is_malformed() {
+        let certificate = certificate();
+        for length in 0..certificate.len() {
+            assert!(spki_sha256(&certificate[..length]).is_err());
+        }
+    }
+
+    #[test]
+    fn malformed_lengths_are_refused() {
+        // indefinite length
+        assert!(read_element(&[0x30, 0x80, 0x00, 0x00], 0x30).is_err());
+        // length on more than 4 bytes
+        assert!(read_element(&[0x30, 0x85, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00], 0x30).is_err());
+        // length bytes missing
+        assert!(read_element(&[0x30, 0x82, 0x01], 0x30).is_err());
+        // content longer than the input
+        assert!(read_element(&[0x30, 0x84, 0xff, 0xff, 0xff, 0xff, 0x00], 0x30).is_err());
+        assert!(read_element(&[0x30, 0x81, 0x02, 0x00], 0x30).is_err());
+        // wrong tag
+        assert!(read_element(&[0x02, 0x01, 0x00], 0x30).is_err());
+    }
+
+    #[test]
+    fn long_form_length_is_read() {
+        let mut input = vec![0x30, 0x81, 0x80];
+        input.extend([0x00; 0x80]);
+        input.push(0x05);
+
+        let (element, rest) = read_element(&input, 0x30).unwrap();
+
+        assert_eq!(element.len(), 3 + 0x80);
+        assert_eq!(content(element).len(), 0x80);
+        assert_eq!(rest, [0x05]);
+    }
+
+    #[test]
+    fn chain_with_a_pinned_key_is_accepted() {
+        let certificate = certificate();
+        let tls = TlsSettings::new(None, &[OTHER_PIN, CERTIFICATE_PIN]);
+        assert!(tls.verify_pins("stand-in.local", &[&certificate]).is_ok());
+    }
+
+    #[test]
+    fn chain_without_a_pinned_key_is_refused() {
+        let certificate = certificate();
+        let tls = TlsSettings::new(None, &[OTHER_PIN]);
+
+        let error = tls
+            .verify_pins("stand-in.local", &[&certificate])
+            .unwrap_err();
+
+        assert!(error.0.contains(CERTIFICATE_PIN));
+    }
+
+    #[test]
+    fn invalid_pins_still_pin_the_connection() {
+        let certificate = certificate();
+        let tls = TlsSettings::new(None, &["not base64!", "c2hvcnQ="]);
+
+        assert!(tls.pins.is_empty());
+        assert!(tls.verify_pins("stand-in.local", &[&certificate]).is_err());
+        assert!(TlsSettings::new(None, &[])
+            .verify_pins("stand-in.local", &[&certificate])
+            .is_ok());
+    }
+}
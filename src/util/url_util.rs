@@ -0,0 +1,81 @@
+// scheme and authority of the url, e.g. `http://192.168.1.102:8080`
+pub fn origin(url: &str) -> &str {
+    let path_start = url
+        .find("://")
+        .and_then(|scheme_end| url[scheme_end + 3..].find('/').map(|i| scheme_end + 3 + i));
+    &url[..path_start.unwrap_or(url.len())]
+}
+
+// the url with the scheme and authority of `origin`, and its own path
+pub fn with_origin(url: &str, origin: &str) -> String {
+    format!("{}{}", origin, &url[self::origin(url).len()..])
+}
+
+// host of the url, without the brackets of an IPv6 literal, e.g. `fe80::1`
+// for `http://[fe80::1]:8080/alert`
+pub fn host(url: &str) -> &str {
+    let origin = origin(url);
+    let authority = origin
+        .split_once("://")
+        .map_or(origin, |(_, authority)| authority);
+    split_authority(authority).0
+}
+
+// host and port of an authority, e.g. `[fe80::1]:8080` or `example.org`
+pub fn split_authority(authority: &str) -> (&str, Option<&str>) {
+    if let Some((host, rest)) = authority
+        .strip_prefix('[')
+        .and_then(|authority| authority.split_once(']'))
+    {
+        return (host, rest.strip_prefix(':'));
+    }
+    match authority.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port)),
+        None => (authority, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origin_stops_at_the_path() {
+        assert_eq!(
+            origin("http://192.168.1.102:8080/api/v1/alert"),
+            "http://192.168.1.102:8080"
+        );
+        assert_eq!(origin("https://example.org"), "https://example.org");
+        assert_eq!(
+            origin("http://[fe80::1]:8080/alert"),
+            "http://[fe80::1]:8080"
+        );
+    }
+
+    #[test]
+    fn with_origin_keeps_the_path() {
+        assert_eq!(
+            with_origin("http://motion.local/alert?id=1", "http://192.168.1.7:8080"),
+            "http://192.168.1.7:8080/alert?id=1"
+        );
+    }
+
+    #[test]
+    fn host_drops_the_port_and_the_brackets() {
+        assert_eq!(host("http://192.168.1.102:8080/alert"), "192.168.1.102");
+        assert_eq!(host("https://example.org/alert"), "example.org");
+        assert_eq!(host("http://[fe80::1]:8080/alert"), "fe80::1");
+        assert_eq!(host("http://[fe80::1]/alert"), "fe80::1");
+    }
+
+    #[test]
+    fn authority_is_split_at_the_port() {
+        assert_eq!(
+            split_authority("example.org:8443"),
+            ("example.org", Some("8443"))
+        );
+        assert_eq!(split_authority("example.org"), ("example.org", None));
+        assert_eq!(split_authority("[::1]:8080"), ("::1", Some("8080")));
+        assert_eq!(split_authority("[::1]"), ("::1", None));
+    }
+}
@@ -0,0 +1,44 @@
+// Every scenario of the scenarios directory, run as by `cargo scenario`: a
+// scenario that fails to load or to run, or whose expectations are not met,
+// fails the test.
+#![cfg(feature = "simulator")]
+
+use motion_detector::scenario::{runner::run_scenario, timeline::Scenario};
+use std::{fs, path::Path};
+
+#[test]
+fn scenarios_meet_their_expectations() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+    let mut paths: Vec<_> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    let mut failures = Vec::new();
+    for path in paths.iter() {
+        let result = fs::read_to_string(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Scenario::from_json(&json))
+            .and_then(|scenario| {
+                let report = run_scenario(&scenario)?;
+                Ok(report.failures(&scenario.expect))
+            });
+        match result {
+            Ok(scenario_failures) if scenario_failures.is_empty() => {}
+            Ok(scenario_failures) => failures.push(format!(
+                "{}: {}",
+                path.display(),
+                scenario_failures.join(", ")
+            )),
+            Err(e) => failures.push(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,368 @@
+// Registration, configuration download and alerts of `ClientService` against
+// the stand-in server, over the HTTP transport of the host. The retries sleep
+// on a virtual clock.
+#![cfg(feature = "simulator")]
+
+use chrono::{TimeZone, Utc};
+use motion_detector::{
+    dto::config_response::Configuration,
+    service::{
+        alert_queue_service::QueuedAlert,
+        client_service::{ClientError, ClientService},
+    },
+    stand_in::server::{
+        Behaviour, Endpoint, StandInServer, ALERT_PATH, CONFIGURATION_PATH, I_AM_ALIVE_PATH,
+        REGISTER_DEVICE_PATH,
+    },
+    transport::host_transport::HostHttpTransport,
+    util::{
+        clock_util::{Clock, VirtualClock},
+        retry_util::{
+            Retryable, ALERT_RETRY_POLICY, CONFIGURATION_RETRY_POLICY, REGISTRATION_RETRY_POLICY,
+        },
+    },
+};
+use serde_json::{json, Value};
+use std::{sync::Arc, time::Duration};
+
+const MAC_ADDRESS: &str = "02:00:00:00:00:01";
+// nothing listens there: the connection is refused at once
+const UNREACHABLE_ALERT_URL: &str = "http://127.0.0.1:1/alert";
+
+fn start() -> (StandInServer, ClientService, Arc<VirtualClock>) {
+    let server = StandInServer::start("127.0.0.1:0").unwrap();
+    let clock = Arc::new(VirtualClock::new(
+        Utc.with_ymd_and_hms(2023, 11, 20, 10, 0, 0).unwrap(),
+    ));
+    let client_service = ClientService::new(Box::new(HostHttpTransport::new()), clock.clone());
+    (server, client_service, clock)
+}
+
+fn configure(client_service: &mut ClientService, server: &StandInServer, alert_targets: Value) {
+    let configuration: Configuration = serde_json::from_value(json!({
+        "iAmAliveEndpoint": server.url(I_AM_ALIVE_PATH),
+        "iAmAliveIntervalSeconds": 30,
+        "crontab": "* * * * * * *",
+        "timezoneOffsetSec": 0,
+        "alertTargets": alert_targets,
+    }))
+    .unwrap();
+    client_service.configure(&configuration);
+}
+
+fn server_target(name: &str, url: &str, mode: &str) -> Value {
+    json!({"name": name, "type": "server", "url": url, "mode": mode})
+}
+
+fn alerts(server: &StandInServer) -> usize {
+    server
+        .requests()
+        .iter()
+        .filter(|request| request.endpoint == Endpoint::Alert)
+        .count()
+}
+
+fn body(server: &StandInServer, index: usize) -> Value {
+    serde_json::from_str(&server.requests()[index].body).unwrap()
+}
+
+#[test]
+fn register_device_stores_the_token() {
+    let (server, mut client_service, _) = start();
+
+    let response = client_service
+        .register_device(&server.url(REGISTER_DEVICE_PATH), MAC_ADDRESS)
+        .unwrap();
+
+    assert!(response.success);
+    assert_eq!(response.token.as_deref(), Some("token-1"));
+    assert_eq!(client_service.device_token(), Some("token-1"));
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].endpoint, Endpoint::RegisterDevice);
+    assert!(requests[0].delivered);
+    assert_eq!(body(&server, 0)["macAddress"], MAC_ADDRESS);
+}
+
+#[test]
+fn register_device_retries_until_the_server_answers() {
+    let (server, mut client_service, clock) = start();
+    server.set_behaviour(Endpoint::RegisterDevice, Behaviour::Status { code: 503 });
+
+    let result = client_service.register_device(&server.url(REGISTER_DEVICE_PATH), MAC_ADDRESS);
+
+    assert!(matches!(
+        result,
+        Err(ClientError::Status { status: 503, .. })
+    ));
+    assert_eq!(
+        server.requests().len(),
+        REGISTRATION_RETRY_POLICY.max_attempts as usize
+    );
+    assert!(
+        clock.elapsed() <= Duration::from_millis(REGISTRATION_RETRY_POLICY.max_blocking_millis)
+    );
+    assert_eq!(client_service.device_token(), None);
+}
+
+#[test]
+fn register_device_does_not_retry_a_refused_request() {
+    let (server, mut client_service, _) = start();
+    server.set_behaviour(Endpoint::RegisterDevice, Behaviour::Status { code: 400 });
+
+    let result = client_service.register_device(&server.url(REGISTER_DEVICE_PATH), MAC_ADDRESS);
+
+    assert!(result.unwrap_err().is_permanent());
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn register_device_does_not_retry_a_rejection() {
+    let (server, mut client_service, _) = start();
+    server.set_behaviour(
+        Endpoint::RegisterDevice,
+        Behaviour::Rejected {
+            message: Some("unknown device".to_owned()),
+        },
+    );
+
+    let result = client_service.register_device(&server.url(REGISTER_DEVICE_PATH), MAC_ADDRESS);
+
+    match result {
+        Err(ClientError::Rejected { message }) => {
+            assert_eq!(message.as_deref(), Some("unknown device"))
+        }
+        _ => panic!("expected a rejection"),
+    }
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn register_device_counts_the_connection_failures() {
+    let (server, mut client_service, _) = start();
+    server.set_behaviour(Endpoint::RegisterDevice, Behaviour::Drop);
+
+    let result = client_service.register_device(&server.url(REGISTER_DEVICE_PATH), MAC_ADDRESS);
+
+    assert!(matches!(result, Err(ClientError::Transport(_))));
+    assert_eq!(client_service.connection_failures(), 1);
+}
+
+#[test]
+fn get_configuration_returns_the_endpoints_of_the_server() {
+    let (server, mut client_service, _) = start();
+
+    let configuration = client_service
+        .get_configuration(&server.url(CONFIGURATION_PATH), MAC_ADDRESS)
+        .unwrap();
+
+    assert_eq!(configuration.alert_endpoint, Some(server.url(ALERT_PATH)));
+    assert_eq!(
+        configuration.i_am_alive_endpoint,
+        server.url(I_AM_ALIVE_PATH)
+    );
+    assert_eq!(body(&server, 0)["macAddress"], MAC_ADDRESS);
+}
+
+#[test]
+fn get_configuration_reads_a_chunked_configuration() {
+    let (server, mut client_service, _) = start();
+    server.set_behaviour(Endpoint::Configuration, Behaviour::Chunked);
+    server.set_configuration(json!({
+        "iAmAliveEndpoint": server.url(I_AM_ALIVE_PATH),
+        "iAmAliveIntervalSeconds": 30,
+        "crontab": "* * 8-18 * * Mon-Fri *",
+        "timezoneOffsetSec": 3600,
+        "timezone": "Europe/Rome",
+    }));
+
+    let configuration = client_service
+        .get_configuration(&server.url(CONFIGURATION_PATH), MAC_ADDRESS)
+        .unwrap();
+
+    assert_eq!(configuration.alert_endpoint, None);
+    assert_eq!(configuration.i_am_alive_interval_seconds, 30);
+    assert_eq!(configuration.crontab, "* * 8-18 * * Mon-Fri *");
+    assert_eq!(configuration.timezone.as_deref(), Some("Europe/Rome"));
+}
+
+#[test]
+fn get_configuration_sends_the_token_of_the_registration() {
+    let (server, mut client_service, _) = start();
+    server.require_tokens();
+
+    client_service
+        .register_device(&server.url(REGISTER_DEVICE_PATH), MAC_ADDRESS)
+        .unwrap();
+    let result = client_service.get_configuration(&server.url(CONFIGURATION_PATH), MAC_ADDRESS);
+
+    assert!(result.is_ok());
+    assert!(!client_service.is_token_rejected());
+}
+
+#[test]
+fn get_configuration_reports_a_rejected_token() {
+    let (server, mut client_service, _) = start();
+    client_service
+        .register_device(&server.url(REGISTER_DEVICE_PATH), MAC_ADDRESS)
+        .unwrap();
+    server.require_tokens();
+
+    let result = client_service.get_configuration(&server.url(CONFIGURATION_PATH), MAC_ADDRESS);
+
+    assert!(result.unwrap_err().is_token_rejected());
+    assert!(client_service.is_token_rejected());
+    // a rejected token is not retried
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[test]
+fn get_configuration_refuses_an_oversized_body() {
+    let (server, mut client_service, _) = start();
+    server.set_behaviour(
+        Endpoint::Configuration,
+        Behaviour::Oversized { bytes: 64 * 1024 },
+    );
+
+    let result = client_service.get_configuration(&server.url(CONFIGURATION_PATH), MAC_ADDRESS);
+
+    assert!(matches!(result, Err(ClientError::ResponseTooLarge { .. })));
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn get_configuration_retries_a_malformed_body() {
+    let (server, mut client_service, _) = start();
+    server.set_behaviour(Endpoint::Configuration, Behaviour::MalformedJson);
+
+    let result = client_service.get_configuration(&server.url(CONFIGURATION_PATH), MAC_ADDRESS);
+
+    assert!(matches!(result, Err(ClientError::InvalidResponse { .. })));
+    assert_eq!(
+        server.requests().len(),
+        CONFIGURATION_RETRY_POLICY.max_attempts as usize
+    );
+}
+
+#[test]
+fn send_alert_is_not_sent_again_to_a_target_that_got_it() {
+    let (server, mut client_service, _) = start();
+    configure(
+        &mut client_service,
+        &server,
+        json!([
+            server_target("primary", &server.url(ALERT_PATH), "fanOut"),
+            server_target("backup", UNREACHABLE_ALERT_URL, "fanOut"),
+        ]),
+    );
+    let mut alert = QueuedAlert::new("2023-11-20T10:00:00Z".to_owned());
+
+    for _ in 0..2 {
+        let deadline = client_service.alert_deadline();
+        let result = client_service.send_alert(MAC_ADDRESS, &mut alert, deadline);
+        assert!(matches!(result, Err(ClientError::Transport(_))));
+    }
+
+    assert!(alert.is_delivered_to(0));
+    assert!(!alert.is_delivered_to(1));
+    assert_eq!(alerts(&server), 1);
+}
+
+#[test]
+fn send_alert_fails_over_to_the_next_target() {
+    let (server, mut client_service, _) = start();
+    configure(
+        &mut client_service,
+        &server,
+        json!([
+            server_target("primary", UNREACHABLE_ALERT_URL, "failover"),
+            server_target("backup", &server.url(ALERT_PATH), "failover"),
+        ]),
+    );
+    let mut alert = QueuedAlert::new("2023-11-20T10:00:00Z".to_owned());
+
+    let deadline = client_service.alert_deadline();
+    let result = client_service.send_alert(MAC_ADDRESS, &mut alert, deadline);
+
+    assert!(result.is_ok());
+    assert!(alert.is_delivered_to(1));
+    assert_eq!(alerts(&server), 1);
+}
+
+#[test]
+fn send_alert_shares_the_deadline_between_the_targets() {
+    let (server, mut client_service, clock) = start();
+    configure(
+        &mut client_service,
+        &server,
+        json!([
+            server_target("first", UNREACHABLE_ALERT_URL, "fanOut"),
+            server_target("second", UNREACHABLE_ALERT_URL, "fanOut"),
+            server_target("third", UNREACHABLE_ALERT_URL, "fanOut"),
+        ]),
+    );
+    let mut alert = QueuedAlert::new("2023-11-20T10:00:00Z".to_owned());
+
+    let deadline = client_service.alert_deadline();
+    let result = client_service.send_alert(MAC_ADDRESS, &mut alert, deadline);
+
+    assert!(result.unwrap_err().is_retryable());
+    assert!(clock.elapsed() <= Duration::from_millis(ALERT_RETRY_POLICY.max_blocking_millis));
+}
+
+#[test]
+fn send_alert_takes_an_oversized_acknowledgement_as_delivered() {
+    let (server, mut client_service, _) = start();
+    server.set_behaviour(Endpoint::Alert, Behaviour::Oversized { bytes: 64 * 1024 });
+    configure(
+        &mut client_service,
+        &server,
+        json!([server_target("primary", &server.url(ALERT_PATH), "fanOut")]),
+    );
+    let mut alert = QueuedAlert::new("2023-11-20T10:00:00Z".to_owned());
+
+    let deadline = client_service.alert_deadline();
+    let result = client_service.send_alert(MAC_ADDRESS, &mut alert, deadline);
+
+    assert!(result.is_ok());
+    assert!(alert.is_delivered_to(0));
+    assert_eq!(alerts(&server), 1);
+}
+
+#[test]
+fn send_alert_without_target_is_a_permanent_error() {
+    let (server, mut client_service, _) = start();
+    configure(&mut client_service, &server, json!([]));
+    let mut alert = QueuedAlert::new("2023-11-20T10:00:00Z".to_owned());
+
+    let deadline = client_service.alert_deadline();
+    let result = client_service.send_alert(MAC_ADDRESS, &mut alert, deadline);
+
+    assert!(matches!(result, Err(ClientError::NoAlertTarget)));
+    assert!(result.unwrap_err().is_permanent());
+}
+
+#[test]
+fn send_alert_to_a_notifier_refusing_its_credentials_is_a_permanent_error() {
+    let (server, mut client_service, _) = start();
+    server.set_behaviour(Endpoint::Alert, Behaviour::Status { code: 401 });
+    configure(
+        &mut client_service,
+        &server,
+        json!([{"name": "discord", "type": "discord", "webhookUrl": server.url(ALERT_PATH)}]),
+    );
+    let mut alert = QueuedAlert::new("2023-11-20T10:00:00Z".to_owned());
+
+    let deadline = client_service.alert_deadline();
+    let result = client_service.send_alert(MAC_ADDRESS, &mut alert, deadline);
+
+    assert!(matches!(
+        result,
+        Err(ClientError::CredentialsRejected { status: 401, .. })
+    ));
+    assert!(result.unwrap_err().is_permanent());
+    assert!(!alert.is_delivered());
+    assert_eq!(alerts(&server), 1);
+    // the device token is not the one refused
+    assert!(!client_service.is_token_rejected());
+}